@@ -0,0 +1,10 @@
+CREATE TABLE resources (
+  id TEXT PRIMARY KEY,
+  note_id TEXT NOT NULL DEFAULT "",
+  title TEXT NOT NULL DEFAULT "",
+  mime TEXT NOT NULL DEFAULT "",
+  size BIGINT NOT NULL DEFAULT 0,
+  sha256 TEXT NOT NULL DEFAULT "",
+  blob BLOB,
+  file_path TEXT
+);
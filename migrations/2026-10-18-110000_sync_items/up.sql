@@ -0,0 +1,7 @@
+CREATE TABLE sync_items (
+  target TEXT NOT NULL,
+  item_id TEXT NOT NULL,
+  hash TEXT NOT NULL DEFAULT "",
+  rev TEXT NOT NULL DEFAULT "",
+  PRIMARY KEY (target, item_id)
+);
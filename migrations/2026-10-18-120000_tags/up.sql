@@ -0,0 +1,5 @@
+CREATE TABLE tags (
+  note_id TEXT NOT NULL,
+  title TEXT NOT NULL,
+  PRIMARY KEY (note_id, title)
+);
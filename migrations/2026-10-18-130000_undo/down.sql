@@ -0,0 +1,14 @@
+DROP TRIGGER tags_delete_undo;
+DROP TRIGGER tags_update_undo;
+DROP TRIGGER tags_insert_undo;
+DROP TRIGGER resources_delete_undo;
+DROP TRIGGER resources_update_undo;
+DROP TRIGGER resources_insert_undo;
+DROP TRIGGER notes_delete_undo;
+DROP TRIGGER notes_update_undo;
+DROP TRIGGER notes_insert_undo;
+DROP TRIGGER folders_delete_undo;
+DROP TRIGGER folders_update_undo;
+DROP TRIGGER folders_insert_undo;
+DROP TABLE undo_log;
+DROP TABLE operations;
@@ -0,0 +1,53 @@
+-- inverse statements of changes, replayed in reverse order to undo or redo
+CREATE TABLE undo_log (
+  seq INTEGER PRIMARY KEY AUTOINCREMENT,
+  statement TEXT NOT NULL
+);
+-- commands that can be undone, with their range of undo_log
+CREATE TABLE operations (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  title TEXT NOT NULL,
+  first_seq INTEGER NOT NULL,
+  last_seq INTEGER NOT NULL,
+  undone BOOLEAN NOT NULL DEFAULT 0
+);
+CREATE TRIGGER folders_insert_undo AFTER INSERT ON folders BEGIN
+  INSERT INTO undo_log(statement) VALUES ('DELETE FROM folders WHERE rowid=' || new.rowid);
+END;
+CREATE TRIGGER folders_update_undo AFTER UPDATE ON folders
+WHEN old.id IS NOT new.id OR old.title IS NOT new.title OR old.parent_id IS NOT new.parent_id BEGIN
+  INSERT INTO undo_log(statement) VALUES ('UPDATE folders SET ' || 'id=' || quote(old.id) || ',' || 'title=' || quote(old.title) || ',' || 'parent_id=' || quote(old.parent_id) || ' WHERE rowid=' || old.rowid);
+END;
+CREATE TRIGGER folders_delete_undo BEFORE DELETE ON folders BEGIN
+  INSERT INTO undo_log(statement) VALUES ('INSERT INTO folders(rowid,id,title,parent_id) VALUES(' || old.rowid || ',' || quote(old.id) || ',' || quote(old.title) || ',' || quote(old.parent_id) || ')');
+END;
+CREATE TRIGGER notes_insert_undo AFTER INSERT ON notes BEGIN
+  INSERT INTO undo_log(statement) VALUES ('DELETE FROM notes WHERE rowid=' || new.rowid);
+END;
+CREATE TRIGGER notes_update_undo AFTER UPDATE ON notes
+WHEN old.id IS NOT new.id OR old.parent_id IS NOT new.parent_id OR old.title IS NOT new.title OR old.body IS NOT new.body BEGIN
+  INSERT INTO undo_log(statement) VALUES ('UPDATE notes SET ' || 'id=' || quote(old.id) || ',' || 'parent_id=' || quote(old.parent_id) || ',' || 'title=' || quote(old.title) || ',' || 'body=' || quote(old.body) || ' WHERE rowid=' || old.rowid);
+END;
+CREATE TRIGGER notes_delete_undo BEFORE DELETE ON notes BEGIN
+  INSERT INTO undo_log(statement) VALUES ('INSERT INTO notes(rowid,id,parent_id,title,body) VALUES(' || old.rowid || ',' || quote(old.id) || ',' || quote(old.parent_id) || ',' || quote(old.title) || ',' || quote(old.body) || ')');
+END;
+CREATE TRIGGER resources_insert_undo AFTER INSERT ON resources BEGIN
+  INSERT INTO undo_log(statement) VALUES ('DELETE FROM resources WHERE rowid=' || new.rowid);
+END;
+CREATE TRIGGER resources_update_undo AFTER UPDATE ON resources
+WHEN old.id IS NOT new.id OR old.note_id IS NOT new.note_id OR old.title IS NOT new.title OR old.mime IS NOT new.mime OR old.size IS NOT new.size OR old.sha256 IS NOT new.sha256 OR old.blob IS NOT new.blob OR old.file_path IS NOT new.file_path BEGIN
+  INSERT INTO undo_log(statement) VALUES ('UPDATE resources SET ' || 'id=' || quote(old.id) || ',' || 'note_id=' || quote(old.note_id) || ',' || 'title=' || quote(old.title) || ',' || 'mime=' || quote(old.mime) || ',' || 'size=' || quote(old.size) || ',' || 'sha256=' || quote(old.sha256) || ',' || 'blob=' || quote(old.blob) || ',' || 'file_path=' || quote(old.file_path) || ' WHERE rowid=' || old.rowid);
+END;
+CREATE TRIGGER resources_delete_undo BEFORE DELETE ON resources BEGIN
+  INSERT INTO undo_log(statement) VALUES ('INSERT INTO resources(rowid,id,note_id,title,mime,size,sha256,blob,file_path) VALUES(' || old.rowid || ',' || quote(old.id) || ',' || quote(old.note_id) || ',' || quote(old.title) || ',' || quote(old.mime) || ',' || quote(old.size) || ',' || quote(old.sha256) || ',' || quote(old.blob) || ',' || quote(old.file_path) || ')');
+END;
+CREATE TRIGGER tags_insert_undo AFTER INSERT ON tags BEGIN
+  INSERT INTO undo_log(statement) VALUES ('DELETE FROM tags WHERE rowid=' || new.rowid);
+END;
+CREATE TRIGGER tags_update_undo AFTER UPDATE ON tags
+WHEN old.note_id IS NOT new.note_id OR old.title IS NOT new.title BEGIN
+  INSERT INTO undo_log(statement) VALUES ('UPDATE tags SET ' || 'note_id=' || quote(old.note_id) || ',' || 'title=' || quote(old.title) || ' WHERE rowid=' || old.rowid);
+END;
+CREATE TRIGGER tags_delete_undo BEFORE DELETE ON tags BEGIN
+  INSERT INTO undo_log(statement) VALUES ('INSERT INTO tags(rowid,note_id,title) VALUES(' || old.rowid || ',' || quote(old.note_id) || ',' || quote(old.title) || ')');
+END;
@@ -1,13 +1,20 @@
 use std::{
     fs::OpenOptions,
-    io::{stdin, stdout, Read, Write},
+    io::{stdin, stdout, IsTerminal, Read, Write},
+    path::{Component, Path, PathBuf},
 };
 
-use crate::db::{models::*, DbConnection};
+use crate::{
+    db::{crypto, models::*, undo, DbConnection},
+    export::{self, Format},
+    find,
+    import::{self, Source},
+    journal, render, sync, template,
+};
 use structopt::{clap::AppSettings, StructOpt};
 use uuid::Uuid;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 #[structopt(about, rename_all = "lower",global_settings(&[AppSettings::VersionlessSubcommands, AppSettings::NoBinaryName, AppSettings::DisableVersion]))]
 pub enum Command {
     #[structopt(visible_alias = "ls", about = "list book")]
@@ -16,23 +23,40 @@ pub enum Command {
         indent: String,
         #[structopt(short = "r")]
         recursive: bool,
-        #[structopt(default_value = "/")]
+        #[structopt(default_value = ".")]
         path: std::path::PathBuf,
     },
     #[structopt(visible_alias = "mk", about = "remove book (path ends with /) or note")]
     Make {
         #[structopt(short = "p")]
         parents: bool,
+        #[structopt(long, help = "fill note from template")]
+        template: Option<String>,
+        #[structopt(
+            long = "var",
+            requires = "template",
+            parse(try_from_str = template::parse_var),
+            help = "template variable as key=value"
+        )]
+        vars: Vec<(String, String)>,
         path: std::path::PathBuf,
     },
     #[structopt(visible_alias = "rm", about = "remove book (path ends with /) or note")]
     Remove {
         #[structopt(short = "r")]
         recursive: bool,
+        #[structopt(short = "y", help = "do not ask before removing matched paths")]
+        yes: bool,
         path: std::path::PathBuf,
     },
+    #[structopt(about = "list book and note paths matching pattern, best match first")]
+    Find { pattern: String },
     #[structopt(about = "output note to file or stdout")]
     Cat {
+        #[structopt(long, help = "render markdown, default when stdout is a terminal")]
+        render: bool,
+        #[structopt(long, conflicts_with = "render", help = "output markdown as is")]
+        raw: bool,
         note: std::path::PathBuf,
         out_file: Option<std::path::PathBuf>,
     },
@@ -49,6 +73,8 @@ pub enum Command {
         parents: bool,
         #[structopt(short = "o")]
         overwrite: bool,
+        #[structopt(short = "y", help = "do not ask before moving matched notes")]
+        yes: bool,
         src: std::path::PathBuf,
         dest_book: std::path::PathBuf,
     },
@@ -58,14 +84,366 @@ pub enum Command {
         parents: bool,
         #[structopt(short = "o")]
         overwrite: bool,
+        #[structopt(short = "y", help = "do not ask before overwriting with matched notes")]
+        yes: bool,
         src: std::path::PathBuf,
         dest_book: std::path::PathBuf,
     },
+    #[structopt(about = "attach file to note")]
+    Attach {
+        note: std::path::PathBuf,
+        file: std::path::PathBuf,
+    },
+    #[structopt(about = "list attachments of note")]
+    Attachments { note: std::path::PathBuf },
+    #[structopt(about = "list tags of note, or all tags")]
+    Tags { note: Option<std::path::PathBuf> },
+    #[structopt(about = "remove attachment")]
+    Detach { resource_id: String },
+    #[structopt(about = "write attachment to file")]
+    Extract {
+        resource_id: String,
+        out_file: std::path::PathBuf,
+    },
+    #[structopt(about = "remove attachments not referenced by any note")]
+    Gc,
+    #[structopt(about = "list, show or edit note templates")]
+    Template(TemplateCommand),
+    #[structopt(about = "edit journal entry of today")]
+    Today,
+    #[structopt(about = "edit journal entry of date (YYYY-MM-DD), today by default")]
+    Journal {
+        date: Option<chrono::NaiveDate>,
+        #[structopt(subcommand)]
+        config: Option<JournalCommand>,
+    },
+    #[structopt(about = "encrypt note bodies and attachments")]
+    Encrypt(EncryptCommand),
+    #[structopt(about = "unlock encrypted notes, or notes of encrypted book, for this session")]
+    Unlock { book: Option<std::path::PathBuf> },
+    #[structopt(about = "forget passphrase, or passphrase of book, for this session")]
+    Lock { book: Option<std::path::PathBuf> },
+    #[structopt(about = "sync notes with configured target")]
+    Sync {
+        #[structopt(subcommand)]
+        config: Option<SyncCommand>,
+    },
+    #[structopt(about = "export book (path ends with /) or note to directory")]
+    Export {
+        #[structopt(long, default_value = "md", help = "md, html, joplin")]
+        format: Format,
+        path: std::path::PathBuf,
+        dir: std::path::PathBuf,
+    },
+    #[structopt(about = "import notes exported by another application")]
+    Import {
+        #[structopt(
+            long,
+            help = "joplin (RAW directory or .jex file), obsidian or logseq (vault)"
+        )]
+        from: Source,
+        path: std::path::PathBuf,
+    },
+    #[structopt(about = "undo last change to notes")]
+    Undo,
+    #[structopt(about = "redo last undone change")]
+    Redo,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum TemplateCommand {
+    #[structopt(about = "list templates")]
+    Ls,
+    #[structopt(about = "output template")]
+    Show { name: String },
+    #[structopt(about = "edit template in an editor, created if missing")]
+    Edit { name: String },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum JournalCommand {
+    #[structopt(about = "configure journal book and template")]
+    Config {
+        #[structopt(long, help = "book holding entries, /journal by default")]
+        book: Option<std::path::PathBuf>,
+        #[structopt(long, help = "template of new entries, journal by default")]
+        template: Option<String>,
+    },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+#[structopt(rename_all = "kebab-case")]
+pub enum EncryptCommand {
+    #[structopt(about = "encrypt all notes with a new passphrase")]
+    Enable,
+    #[structopt(about = "re-encrypt all notes with a new passphrase")]
+    ChangePassphrase,
+    #[structopt(about = "encrypt book and notes under it with a passphrase of its own")]
+    Book { book: std::path::PathBuf },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum SyncCommand {
+    #[structopt(about = "configure sync target")]
+    Config {
+        #[structopt(
+            long,
+            help = "directory to sync to",
+            required_unless_one = &["webdav", "git"]
+        )]
+        target: Option<std::path::PathBuf>,
+        #[structopt(
+            long,
+            help = "webdav collection url to sync to",
+            conflicts_with_all = &["target", "git"]
+        )]
+        webdav: Option<String>,
+        #[structopt(long, help = "git repository to sync to", conflicts_with = "target")]
+        git: Option<std::path::PathBuf>,
+        #[structopt(long, requires = "git", help = "git remote to pull from and push to")]
+        remote: Option<String>,
+        #[structopt(long, requires = "webdav")]
+        user: Option<String>,
+        #[structopt(long, requires = "user")]
+        password: Option<String>,
+    },
+}
+
+/// `path` relative to book `cwd` as absolute path, `.` and `..` resolved.
+///
+/// trailing `/` is kept as it marks books.
+pub fn resolve_path(cwd: &Path, path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    for component in cwd.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(title) => resolved.push(title),
+            _ => {}
+        }
+    }
+    let raw = path.to_string_lossy();
+    let is_book = raw.ends_with('/') || raw.ends_with("/.") || raw.ends_with("/..");
+    if is_book && resolved.parent().is_some() {
+        resolved.as_mut_os_string().push("/");
+    }
+    resolved
+}
+
+/// list `targets` and ask before `action` on them, fails without a terminal
+/// to ask on (e.g. scripts read from stdin).
+fn confirm(action: &str, targets: &[PathBuf]) -> Result<(), String> {
+    if !stdin().is_terminal() {
+        return Err("ConfirmationRequired".into());
+    }
+    for target in targets {
+        eprintln!("  {}", target.to_string_lossy());
+    }
+    eprint!("{} {} items? [y/N] ", action, targets.len());
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).map_err(|e| e.to_string())?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err("Cancelled".into())
+    }
+}
+
+fn is_book(path: &Path) -> bool {
+    path.to_string_lossy().ends_with('/')
+}
+
+pub fn read_passphrase(prompt: &str) -> Result<String, String> {
+    rpassword::prompt_password(prompt).map_err(|e| e.to_string())
+}
+
+fn read_new_passphrase() -> Result<String, String> {
+    let passphrase = read_passphrase("new passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("EmptyPassphrase".into());
+    }
+    if passphrase != read_passphrase("repeat passphrase: ")? {
+        return Err("PassphraseMismatch".into());
+    }
+    Ok(passphrase)
 }
 
 impl Command {
+    /// resolve book and note paths relative to book `cwd`.
+    pub fn resolve(&mut self, cwd: &Path) {
+        let resolve = |path: &mut PathBuf| *path = resolve_path(cwd, path);
+        match self {
+            Command::List { path, .. }
+            | Command::Make { path, .. }
+            | Command::Remove { path, .. }
+            | Command::Export { path, .. } => resolve(path),
+            Command::Cat { note, .. }
+            | Command::Update { note, .. }
+            | Command::Edit { note }
+            | Command::Attach { note, .. }
+            | Command::Attachments { note } => resolve(note),
+            Command::Move { src, dest_book, .. } | Command::Copy { src, dest_book, .. } => {
+                resolve(src);
+                resolve(dest_book);
+            }
+            Command::Journal {
+                config:
+                    Some(JournalCommand::Config {
+                        book: Some(book), ..
+                    }),
+                ..
+            }
+            | Command::Unlock { book: Some(book) }
+            | Command::Lock { book: Some(book) }
+            | Command::Tags { note: Some(book) }
+            | Command::Encrypt(EncryptCommand::Book { book }) => resolve(book),
+            _ => {}
+        }
+    }
+
+    /// path of command if it is a glob pattern, paths of existing books or
+    /// notes are taken literally.
+    fn pattern(&self, connection: &DbConnection) -> Option<&PathBuf> {
+        match self {
+            Command::List { path, .. } | Command::Remove { path, .. } => Some(path),
+            Command::Cat { note, .. } => Some(note),
+            Command::Move { src, .. } | Command::Copy { src, .. } => Some(src),
+            _ => None,
+        }
+        .filter(|path| path.to_string_lossy().contains(&['*', '?'][..]))
+        .filter(|path| {
+            Folder::get(path, connection).is_err() && Note::query(path, connection).is_err()
+        })
+    }
+
+    fn with_path(&self, target: PathBuf) -> Self {
+        let mut command = self.clone();
+        match &mut command {
+            Command::List { path, .. } | Command::Remove { path, .. } => *path = target,
+            Command::Cat { note, .. } => *note = target,
+            Command::Move { src, .. } | Command::Copy { src, .. } => *src = target,
+            _ => {}
+        }
+        command
+    }
+
+    /// execute command for each book and note matching `pattern`.
+    fn execute_glob(&self, pattern: &Path, connection: &DbConnection) -> Result<String, String> {
+        let mut targets = Folder::glob(pattern, connection)?;
+        match self {
+            Command::Cat { out_file, .. } => {
+                targets.retain(|target| !is_book(target));
+                if out_file.is_some() && targets.len() > 1 {
+                    return Err("MultipleTargets".into());
+                }
+            }
+            Command::Move { .. } | Command::Copy { .. } => {
+                targets.retain(|target| !is_book(target))
+            }
+            // notes before their books
+            Command::Remove { .. } => targets.reverse(),
+            _ => {}
+        }
+        if targets.is_empty() {
+            return Err("NotFound".into());
+        }
+        match self {
+            Command::Remove { yes: false, .. } => confirm("remove", &targets)?,
+            Command::Move { yes: false, .. } => confirm("move", &targets)?,
+            Command::Copy {
+                overwrite: true,
+                yes: false,
+                ..
+            } => confirm("copy over", &targets)?,
+            _ => {}
+        }
+        let mut outputs = vec![];
+        for target in targets {
+            let output = match self {
+                Command::List { .. } if !is_book(&target) => target.to_string_lossy().to_string(),
+                Command::List { .. } => format!(
+                    "{}\n{}",
+                    target.to_string_lossy(),
+                    self.with_path(target.clone()).execute(connection)?
+                ),
+                _ => self.with_path(target).execute(connection)?,
+            };
+            if !output.is_empty() {
+                outputs.push(output);
+            }
+        }
+        Ok(outputs.join("\n"))
+    }
+
+    /// title of undoable commands in the journal.
+    fn title(&self) -> Option<String> {
+        let title = match self {
+            Command::Make { path, .. } => format!("mk {}", path.display()),
+            Command::Remove { path, .. } => format!("rm {}", path.display()),
+            Command::Update { note, .. } => format!("update {}", note.display()),
+            Command::Edit { note } => format!("edit {}", note.display()),
+            Command::Move { src, dest_book, .. } => {
+                format!("mv {} {}", src.display(), dest_book.display())
+            }
+            Command::Copy { src, dest_book, .. } => {
+                format!("cp {} {}", src.display(), dest_book.display())
+            }
+            Command::Attach { note, file } => {
+                format!("attach {} {}", note.display(), file.display())
+            }
+            Command::Detach { resource_id } => format!("detach {}", resource_id),
+            Command::Today => "today".into(),
+            Command::Journal { date, config: None } => match date {
+                Some(date) => format!("journal {}", date),
+                None => "journal".into(),
+            },
+            Command::Import { from, path } => format!("import {:?} {}", from, path.display()),
+            _ => return None,
+        };
+        Some(title)
+    }
+
+    /// execute and journal changes of undoable commands, other changes clear
+    /// the journal.
+    pub fn run(&self, connection: &DbConnection) -> Result<String, String> {
+        if let Command::Undo | Command::Redo = self {
+            return self.execute(connection);
+        }
+        let title = self.title();
+        undo::record(
+            title.as_deref().unwrap_or_default(),
+            title.is_some(),
+            connection,
+            || self.execute(connection),
+        )
+    }
+
+    /// run, when the master key is locked ask for the passphrase on a terminal
+    /// and run again, changes of the locked attempt are rolled back.
+    pub fn run_unlocking(&self, connection: &DbConnection) -> Result<String, String> {
+        use diesel::{result::Error, Connection};
+        let result = connection.transaction(|| match self.run(connection) {
+            Err(err) if err == "EncryptionLocked" && stdin().is_terminal() => {
+                Err(Error::RollbackTransaction)
+            }
+            result => Ok(result),
+        });
+        match result {
+            Ok(result) => result,
+            Err(Error::RollbackTransaction) => {
+                crypto::unlock(None, &read_passphrase("passphrase: ")?, connection)?;
+                self.run(connection)
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
     pub fn execute(&self, connection: &DbConnection) -> Result<String, String> {
         // dbg!(&self);
+        if let Some(pattern) = self.pattern(connection) {
+            return self.execute_glob(pattern, connection);
+        }
         let output;
         match &self {
             Command::List {
@@ -84,12 +462,27 @@ impl Command {
                         "`-"
                     }
                 };
+                let locked = |locked| if locked { " [locked]" } else { "" };
                 for note in notes {
-                    buffer.push_str(&format!("{}{}{}\n", indent, connector(), note.title));
+                    buffer.push_str(&format!(
+                        "{}{}{}{}\n",
+                        indent,
+                        connector(),
+                        note.title,
+                        locked(note.is_locked())
+                    ));
                 }
                 for folder in folders {
                     let connector = connector();
-                    buffer.push_str(&format!("{}{}{}/\n", indent, connector, folder.title));
+                    let is_locked = crypto::is_book_encrypted(folder.get_id(), connection)?
+                        && !crypto::is_unlocked(folder.get_id());
+                    buffer.push_str(&format!(
+                        "{}{}{}/{}\n",
+                        indent,
+                        connector,
+                        folder.title,
+                        locked(is_locked)
+                    ));
                     if *recursive {
                         let mut path = path.clone();
                         path.push(folder.title);
@@ -109,15 +502,34 @@ impl Command {
                 }
                 output = buffer;
             }
-            Command::Make { parents, path } => {
+            Command::Make {
+                parents,
+                template,
+                vars,
+                path,
+            } => {
                 if path.to_string_lossy().ends_with("/") {
+                    if template.is_some() {
+                        return Err("NotANote".into());
+                    }
                     Folder::make(&path, *parents, &connection)?;
+                } else if let Some(name) = template {
+                    // an existing note keeps its body
+                    if Note::query(path, connection).is_ok() {
+                        return Err("AlreadyExists".into());
+                    }
+                    // render first, so missing template does not leave empty note
+                    let body = template::render(name, &template::variables(path, vars))?;
+                    Note::make(path, *parents, connection)?;
+                    Note::update(path, body, connection)?;
                 } else {
                     Note::make(&path, *parents, &connection)?;
                 }
                 output = format!("{} successfully created", path.to_string_lossy());
             }
-            Command::Remove { recursive, path } => {
+            Command::Remove {
+                recursive, path, ..
+            } => {
                 let rows = if path.to_string_lossy().ends_with("/") {
                     Folder::delete(path, *recursive, &connection)?
                 } else {
@@ -130,6 +542,8 @@ impl Command {
                 );
             }
             Command::Cat {
+                render,
+                raw,
                 note,
                 out_file: dest,
             } => {
@@ -143,8 +557,12 @@ impl Command {
                     ),
                     None => Box::new(stdout()),
                 };
+                let mut body = Note::cat(note, connection)?;
+                if *render || (!raw && dest.is_none() && stdout().is_terminal()) {
+                    body = render::render(&body, render::terminal_width());
+                }
                 writer
-                    .write_all(Note::cat(&note, &connection)?.as_bytes())
+                    .write_all(body.as_bytes())
                     .map_err(|e| e.to_string())?;
                 output = String::new();
             }
@@ -159,6 +577,8 @@ impl Command {
                         .to_string_lossy()
                 ));
                 Command::Cat {
+                    render: false,
+                    raw: true,
                     note: note.clone(),
                     out_file: Some(temp_file.clone()),
                 }
@@ -198,6 +618,7 @@ impl Command {
                 overwrite,
                 src,
                 dest_book,
+                ..
             } => {
                 let rows = Note::copy_note(&src, &dest_book, *overwrite, *parents, &connection)?;
                 output = format!("copy successful\n {} rows effected", rows);
@@ -207,10 +628,163 @@ impl Command {
                 overwrite,
                 src,
                 dest_book,
+                ..
             } => {
                 let rows = Note::move_note(&src, &dest_book, *overwrite, *parents, &connection)?;
                 output = format!("move successful\n {} rows effected", rows);
             }
+            Command::Attach { note, file } => {
+                let resource = Resource::attach(note, file, connection)?;
+                output = format!(
+                    "{} attached, reference it as [{}](:/{})",
+                    file.to_string_lossy(),
+                    resource.title,
+                    resource.get_id()
+                );
+            }
+            Command::Attachments { note } => {
+                output = Resource::list(note, connection)?
+                    .iter()
+                    .map(|r| format!("{} {} {} {}\n", r.get_id(), r.mime, r.size, r.title))
+                    .collect();
+            }
+            Command::Find { pattern } => {
+                output = find::find(pattern, connection)?
+                    .iter()
+                    .map(|m| m.path.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+            }
+            Command::Tags { note } => {
+                let tags = match note {
+                    Some(note) => Tag::list(note, connection)?,
+                    None => Tag::all(connection)?,
+                };
+                output = tags.join("\n");
+            }
+            Command::Detach { resource_id } => {
+                let rows = Resource::detach(resource_id, connection)?;
+                output = format!("detach successful\n {} rows effected", rows);
+            }
+            Command::Extract {
+                resource_id,
+                out_file,
+            } => {
+                let size = Resource::extract(resource_id, out_file, connection)?;
+                output = format!("{} bytes written to {}", size, out_file.to_string_lossy());
+            }
+            Command::Gc => {
+                let rows = Resource::gc(connection)? + Tag::gc(connection)?;
+                output = format!("gc successful\n {} items removed", rows);
+            }
+            Command::Today => {
+                output = Command::Journal {
+                    date: None,
+                    config: None,
+                }
+                .execute(connection)?;
+            }
+            Command::Journal { date, config: None } => {
+                let note = journal::open(date.unwrap_or_else(journal::today), connection)?;
+                Command::Edit { note: note.clone() }.execute(connection)?;
+                output = note.to_string_lossy().to_string();
+            }
+            Command::Journal {
+                config: Some(JournalCommand::Config { book, template }),
+                ..
+            } => {
+                journal::configure(book.as_deref(), template.as_deref(), connection)?;
+                output = "journal configured".into();
+            }
+            Command::Template(TemplateCommand::Ls) => {
+                output = template::list()?.join("\n");
+            }
+            Command::Template(TemplateCommand::Show { name }) => {
+                output = template::read(name)?;
+            }
+            Command::Template(TemplateCommand::Edit { name }) => {
+                let path = template::path(name)?;
+                if !path.exists() {
+                    std::fs::write(&path, "# {{title}}\n").map_err(|e| e.to_string())?;
+                }
+                edit::edit_file(&path).map_err(|e| e.to_string())?;
+                output = format!("template {} saved", name);
+            }
+            Command::Encrypt(EncryptCommand::Enable) => {
+                let rows = crypto::enable(&read_new_passphrase()?, connection)?;
+                output = format!("encryption enabled\n {} rows effected", rows);
+            }
+            Command::Encrypt(EncryptCommand::ChangePassphrase) => {
+                let old = read_passphrase("passphrase: ")?;
+                let rows = crypto::change_passphrase(&old, &read_new_passphrase()?, connection)?;
+                output = format!("passphrase changed\n {} rows effected", rows);
+            }
+            Command::Encrypt(EncryptCommand::Book { book }) => {
+                let rows = crypto::encrypt_book(book, &read_new_passphrase()?, connection)?;
+                output = format!(
+                    "{} encrypted\n {} rows effected",
+                    book.to_string_lossy(),
+                    rows
+                );
+            }
+            Command::Unlock { book } => {
+                crypto::unlock(book.as_ref(), &read_passphrase("passphrase: ")?, connection)?;
+                output = "unlocked".into();
+            }
+            Command::Lock { book } => {
+                crypto::lock(book.as_ref(), connection)?;
+                output = "locked".into();
+            }
+            Command::Sync {
+                config:
+                    Some(SyncCommand::Config {
+                        target,
+                        webdav,
+                        git,
+                        remote,
+                        user,
+                        password,
+                    }),
+            } => {
+                if let Some(repo) = git {
+                    sync::configure_git(repo, remote.as_deref(), connection)?;
+                    output = format!("sync target set to {}", repo.to_string_lossy());
+                } else if let Some(url) = webdav {
+                    let stored = sync::configure_webdav(
+                        url,
+                        user.as_deref(),
+                        password.as_deref(),
+                        connection,
+                    )?;
+                    output = if password.is_some() && !stored {
+                        format!(
+                            "sync target set to {}\npassword is asked on sync, \
+                             it is only kept with encryption enabled",
+                            url
+                        )
+                    } else {
+                        format!("sync target set to {}", url)
+                    };
+                } else if let Some(target) = target {
+                    sync::configure_local(target, connection)?;
+                    output = format!("sync target set to {}", target.to_string_lossy());
+                } else {
+                    return Err("SyncNotConfigured".into());
+                }
+            }
+            Command::Sync { config: None } => {
+                let report = sync::sync(&mut *sync::configured_target(connection)?, connection)?;
+                output = format!("sync done\n {}", report);
+            }
+            Command::Export { format, path, dir } => {
+                let count = export::export(*format, path, dir, connection)?;
+                output = format!("{} notes exported to {}", count, dir.to_string_lossy());
+            }
+            Command::Import { from, path } => {
+                output = import::import(*from, path, connection)?.to_string();
+            }
+            Command::Undo => output = undo::undo(connection)?,
+            Command::Redo => output = undo::redo(connection)?,
         }
         Ok(output)
     }
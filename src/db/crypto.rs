@@ -0,0 +1,619 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use diesel::Connection;
+
+use super::{
+    models::{Folder, Note, Resource, Setting},
+    DbConnection, DieselStringError,
+};
+
+/// encrypted values look like `md_cli:enc:<key id>:<nonce + ciphertext>`,
+/// payload is base64 encoded for note bodies and raw for attachment blobs.
+/// key id is `master` or id of encrypted book.
+const PREFIX: &str = "md_cli:enc:";
+const NONCE_LEN: usize = 24;
+pub const MASTER: &str = "master";
+const CHECK_VALUE: &str = structopt::clap::crate_name!();
+
+/// settings holding salt and check value of key
+fn setting_keys(key_id: &str) -> (String, String) {
+    if key_id == MASTER {
+        ("encryption.salt".into(), "encryption.check".into())
+    } else {
+        (
+            format!("encryption.{}.salt", key_id),
+            format!("encryption.{}.check", key_id),
+        )
+    }
+}
+
+/// settings holding secrets, kept sealed with master key.
+const SECRETS: &[&str] = &[crate::sync::PASSWORD];
+
+/// keys unlocked in this session, by key id
+static KEYS: Mutex<BTreeMap<String, Key>> = Mutex::new(BTreeMap::new());
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, String> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn new_salt() -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    hex::encode(salt)
+}
+
+fn get_key(key_id: &str) -> Option<Key> {
+    KEYS.lock().unwrap().get(key_id).cloned()
+}
+
+fn set_key(key_id: &str, key: Option<Key>) {
+    let mut keys = KEYS.lock().unwrap();
+    match key {
+        Some(key) => keys.insert(key_id.into(), key),
+        None => keys.remove(key_id),
+    };
+}
+
+pub fn is_unlocked(key_id: &str) -> bool {
+    get_key(key_id).is_some()
+}
+
+/// key id of encrypted data, None for plain data.
+pub fn key_id(data: &[u8]) -> Option<&str> {
+    let rest = data.strip_prefix(PREFIX.as_bytes())?;
+    let end = rest.iter().position(|b| *b == b':')?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+fn payload<'a>(data: &'a [u8], key_id: &str) -> &'a [u8] {
+    &data[PREFIX.len() + key_id.len() + 1..]
+}
+
+fn encrypt_with(key: &Key, data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(key)
+        .encrypt(&nonce, data)
+        .map_err(|_| "EncryptionFailed".to_string())?;
+    let mut payload = nonce.to_vec();
+    payload.extend(ciphertext);
+    Ok(payload)
+}
+
+fn decrypt_with(key: &Key, payload: &[u8]) -> Result<Vec<u8>, String> {
+    if payload.len() < NONCE_LEN {
+        return Err("CorruptCiphertext".into());
+    }
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key)
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "WrongPassphrase".to_string())
+}
+
+fn seal_text_with(key: &Key, key_id: &str, text: &str) -> Result<String, String> {
+    Ok(format!(
+        "{}{}:{}",
+        PREFIX,
+        key_id,
+        STANDARD.encode(encrypt_with(key, text.as_bytes())?)
+    ))
+}
+
+fn open_text_with(key: &Key, key_id: &str, text: &str) -> Result<String, String> {
+    let payload = STANDARD
+        .decode(payload(text.as_bytes(), key_id))
+        .map_err(|_| "CorruptCiphertext".to_string())?;
+    String::from_utf8(decrypt_with(key, &payload)?).map_err(|e| e.to_string())
+}
+
+fn seal_bytes_with(key: &Key, key_id: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut sealed = format!("{}{}:", PREFIX, key_id).into_bytes();
+    sealed.extend(encrypt_with(key, data)?);
+    Ok(sealed)
+}
+
+fn open_bytes_with(key: &Key, key_id: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    decrypt_with(key, payload(data, key_id))
+}
+
+fn unlocked_key(key_id: &str) -> Result<Key, String> {
+    get_key(key_id).ok_or_else(|| {
+        if key_id == MASTER {
+            "EncryptionLocked".to_string()
+        } else {
+            "BookLocked".to_string()
+        }
+    })
+}
+
+fn has_key(key_id: &str, conn: &DbConnection) -> Result<bool, String> {
+    Ok(Setting::get(&setting_keys(key_id).0, conn)?.is_some())
+}
+
+pub fn is_enabled(conn: &DbConnection) -> Result<bool, String> {
+    has_key(MASTER, conn)
+}
+
+pub fn is_book_encrypted(folder_id: &str, conn: &DbConnection) -> Result<bool, String> {
+    has_key(folder_id, conn)
+}
+
+/// key of nearest encrypted book, else master key if enabled
+fn key_for(parent_id: &str, conn: &DbConnection) -> Result<Option<String>, String> {
+    for id in Folder::ancestor_ids(parent_id, conn)? {
+        if has_key(&id, conn)? {
+            return Ok(Some(id));
+        }
+    }
+    // a failed lookup must not be taken for disabled, it would write plaintext
+    let enabled = is_enabled(conn)?;
+    Ok(Some(MASTER.to_string()).filter(|_| enabled))
+}
+
+/// encrypt body of note in book parent_id if needed
+pub fn seal(text: String, parent_id: &str, conn: &DbConnection) -> Result<String, String> {
+    match key_for(parent_id, conn)? {
+        Some(key_id) => seal_text_with(&unlocked_key(&key_id)?, &key_id, &text),
+        None => Ok(text),
+    }
+}
+
+/// decrypt note body if it is encrypted
+pub fn open(text: &str) -> Result<String, String> {
+    match key_id(text.as_bytes()) {
+        Some(key_id) => open_text_with(&unlocked_key(key_id)?, key_id, text),
+        None => Ok(text.to_string()),
+    }
+}
+
+/// encrypt attachment blob of note in book parent_id if needed
+pub fn seal_bytes(data: Vec<u8>, parent_id: &str, conn: &DbConnection) -> Result<Vec<u8>, String> {
+    match key_for(parent_id, conn)? {
+        Some(key_id) => seal_bytes_with(&unlocked_key(&key_id)?, &key_id, &data),
+        None => Ok(data),
+    }
+}
+
+/// decrypt attachment blob if it is encrypted
+pub fn open_bytes(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match key_id(&data) {
+        Some(key_id) => open_bytes_with(&unlocked_key(key_id)?, key_id, &data),
+        None => Ok(data),
+    }
+}
+
+/// store secret setting sealed with master key, secrets are not stored while
+/// encryption is disabled. returns whether it was stored.
+pub fn set_secret(key: &str, value: &str, conn: &DbConnection) -> Result<bool, String> {
+    if !is_enabled(conn)? {
+        Setting::unset(key, conn)?;
+        return Ok(false);
+    }
+    let sealed = seal_text_with(&unlocked_key(MASTER)?, MASTER, value)?;
+    Setting::set(key, &sealed, conn)?;
+    Ok(true)
+}
+
+/// secret setting stored with `set_secret`.
+pub fn get_secret(key: &str, conn: &DbConnection) -> Result<Option<String>, String> {
+    Setting::get(key, conn)?
+        .map(|value| open(&value))
+        .transpose()
+}
+
+/// ids of stored keys with their salt and check value.
+pub fn stored_keys(conn: &DbConnection) -> Result<Vec<(String, String, String)>, String> {
+    let mut ids = vec![MASTER.to_string()];
+    ids.extend(Folder::all(conn)?.iter().map(|f| f.get_id().to_string()));
+    let mut keys = vec![];
+    for id in ids {
+        let (salt, check) = setting_keys(&id);
+        if let (Some(salt), Some(check)) = (Setting::get(&salt, conn)?, Setting::get(&check, conn)?)
+        {
+            keys.push((id, salt, check));
+        }
+    }
+    Ok(keys)
+}
+
+/// store salt and check value of a key from another database, so the key
+/// is derived there from the same passphrase.
+pub fn save_stored_key(
+    key_id: &str,
+    salt: &str,
+    check: &str,
+    conn: &DbConnection,
+) -> Result<usize, String> {
+    let (salt_key, check_key) = setting_keys(key_id);
+    // key unlocked with another salt no longer opens what is sealed
+    if Setting::get(&salt_key, conn)?.as_deref() != Some(salt) {
+        set_key(key_id, None);
+    }
+    Ok(Setting::set(&salt_key, salt, conn)? + Setting::set(&check_key, check, conn)?)
+}
+
+/// derive key from passphrase and check it against the stored value.
+fn verify(key_id: &str, passphrase: &str, conn: &DbConnection) -> Result<Key, String> {
+    let (salt, check) = setting_keys(key_id);
+    let salt = Setting::get(&salt, conn)?.ok_or_else(|| "EncryptionDisabled".to_string())?;
+    let check = Setting::get(&check, conn)?.unwrap_or_default();
+    let key = derive_key(passphrase, salt.as_bytes())?;
+    match open_text_with(&key, key_id, &check) {
+        Ok(value) if value == CHECK_VALUE => Ok(key),
+        _ => Err("WrongPassphrase".into()),
+    }
+}
+
+/// re-encrypt everything encrypted with old master key (or plain if None) with new key.
+fn rekey(old: Option<&Key>, new: &Key, conn: &DbConnection) -> Result<usize, String> {
+    let mut rows = Note::reseal_all(
+        |body| match (key_id(body.as_bytes()), old) {
+            (None, _) => seal_text_with(new, MASTER, body).map(Some),
+            (Some(MASTER), Some(old)) => {
+                seal_text_with(new, MASTER, &open_text_with(old, MASTER, body)?).map(Some)
+            }
+            _ => Ok(None),
+        },
+        conn,
+    )?;
+    rows += Resource::reseal_all(
+        |data| match (key_id(data), old) {
+            (None, _) => seal_bytes_with(new, MASTER, data).map(Some),
+            (Some(MASTER), Some(old)) => {
+                seal_bytes_with(new, MASTER, &open_bytes_with(old, MASTER, data)?).map(Some)
+            }
+            _ => Ok(None),
+        },
+        None,
+        conn,
+    )?;
+    for secret in SECRETS {
+        let value = match (Setting::get(secret, conn)?, old) {
+            (Some(value), _) if key_id(value.as_bytes()).is_none() => value,
+            (Some(value), Some(old)) => open_text_with(old, MASTER, &value)?,
+            _ => continue,
+        };
+        Setting::set(secret, &seal_text_with(new, MASTER, &value)?, conn)?;
+    }
+    Ok(rows)
+}
+
+/// encrypt notes of book and its children (except encrypted ones) with key.
+fn seal_book(
+    path: &PathBuf,
+    key: &Key,
+    key_id: &str,
+    conn: &DbConnection,
+) -> Result<usize, String> {
+    let (folders, notes) = Folder::list(path, conn)?;
+    let mut rows = 0;
+    for mut note in notes {
+        rows += note.reseal(
+            |body| seal_text_with(key, key_id, &open(body)?).map(Some),
+            conn,
+        )?;
+        rows += Resource::reseal_all(
+            |data| seal_bytes_with(key, key_id, &open_bytes(data.to_vec())?).map(Some),
+            Some(note.get_id()),
+            conn,
+        )?;
+    }
+    for folder in folders {
+        if !is_book_encrypted(folder.get_id(), conn)? {
+            rows += seal_book(&path.join(&folder.title), key, key_id, conn)?;
+        }
+    }
+    Ok(rows)
+}
+
+fn store_key(key_id: &str, key: &Key, salt: &str, conn: &DbConnection) -> Result<(), String> {
+    let (salt_key, check_key) = setting_keys(key_id);
+    Setting::set(&salt_key, salt, conn)?;
+    Setting::set(&check_key, &seal_text_with(key, key_id, CHECK_VALUE)?, conn)?;
+    Ok(())
+}
+
+/// encrypt all notes and attachments with a key derived from passphrase.
+pub fn enable(passphrase: &str, conn: &DbConnection) -> Result<usize, String> {
+    if is_enabled(conn)? {
+        return Err("EncryptionEnabled".into());
+    }
+    let salt = new_salt();
+    let key = derive_key(passphrase, salt.as_bytes())?;
+    let rows = conn
+        .transaction::<_, DieselStringError, _>(|| {
+            store_key(MASTER, &key, &salt, conn)?;
+            Ok(rekey(None, &key, conn)?)
+        })
+        .map_err(|e| e.0)?;
+    set_key(MASTER, Some(key));
+    Ok(rows)
+}
+
+/// re-encrypt all notes and attachments in one transaction.
+pub fn change_passphrase(old: &str, new: &str, conn: &DbConnection) -> Result<usize, String> {
+    let old = verify(MASTER, old, conn)?;
+    let salt = new_salt();
+    let key = derive_key(new, salt.as_bytes())?;
+    let rows = conn
+        .transaction::<_, DieselStringError, _>(|| {
+            store_key(MASTER, &key, &salt, conn)?;
+            Ok(rekey(Some(&old), &key, conn)?)
+        })
+        .map_err(|e| e.0)?;
+    set_key(MASTER, Some(key));
+    Ok(rows)
+}
+
+/// encrypt book and notes under it with a key of its own.
+pub fn encrypt_book(
+    path: &PathBuf,
+    passphrase: &str,
+    conn: &DbConnection,
+) -> Result<usize, String> {
+    let folder = Folder::get(path, conn)?;
+    if is_book_encrypted(folder.get_id(), conn)? {
+        return Err("BookEncrypted".into());
+    }
+    let salt = new_salt();
+    let key = derive_key(passphrase, salt.as_bytes())?;
+    let rows = conn
+        .transaction::<_, DieselStringError, _>(|| {
+            store_key(folder.get_id(), &key, &salt, conn)?;
+            Ok(seal_book(path, &key, folder.get_id(), conn)?)
+        })
+        .map_err(|e| e.0)?;
+    set_key(folder.get_id(), Some(key));
+    Ok(rows)
+}
+
+/// unlock master key, or key of book if given
+pub fn unlock(book: Option<&PathBuf>, passphrase: &str, conn: &DbConnection) -> Result<(), String> {
+    let key_id = match book {
+        Some(path) => {
+            let folder = Folder::get(path, conn)?;
+            if !is_book_encrypted(folder.get_id(), conn)? {
+                return Err("BookNotEncrypted".into());
+            }
+            folder.get_id().to_string()
+        }
+        None => MASTER.to_string(),
+    };
+    set_key(&key_id, Some(verify(&key_id, passphrase, conn)?));
+    Ok(())
+}
+
+/// forget master key, or key of book if given
+pub fn lock(book: Option<&PathBuf>, conn: &DbConnection) -> Result<(), String> {
+    match book {
+        Some(path) => set_key(Folder::get(path, conn)?.get_id(), None),
+        None => set_key(MASTER, None),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        establish_connection,
+        models::{Folder, Note},
+    };
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Folder::make(&"book".into(), false, &conn).unwrap();
+        Note::make(&"book/note".into(), false, &conn).unwrap();
+        Note::update(&"book/note".into(), "secret".into(), &conn).unwrap();
+
+        {
+            let msg = "secrets are not stored while encryption is disabled";
+            assert_eq!(
+                set_secret("sync.password", "dav", &conn),
+                Ok(false),
+                "{}",
+                msg
+            );
+            assert_eq!(get_secret("sync.password", &conn), Ok(None), "{}", msg);
+        }
+
+        {
+            let msg = "enable encrypts existing notes";
+            assert_eq!(enable("pass", &conn), Ok(1), "{}", msg);
+            let note = Note::query(&"book/note".into(), &conn).unwrap();
+            assert_eq!(
+                key_id(note.get_raw_body().as_bytes()),
+                Some(MASTER),
+                "{}",
+                msg
+            );
+            assert_eq!(note.read_body(), Ok("secret".into()), "{}", msg);
+            assert_eq!(
+                enable("pass", &conn),
+                Err("EncryptionEnabled".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "secrets are sealed with master key";
+            assert_eq!(
+                set_secret("sync.password", "dav", &conn),
+                Ok(true),
+                "{}",
+                msg
+            );
+            let stored = Setting::get("sync.password", &conn).unwrap().unwrap();
+            assert_eq!(key_id(stored.as_bytes()), Some(MASTER), "{}", msg);
+            assert_eq!(
+                get_secret("sync.password", &conn),
+                Ok(Some("dav".into())),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "locked session cannot read or write";
+            lock(None, &conn).unwrap();
+            assert_eq!(
+                Note::cat(&"book/note".into(), &conn),
+                Err("EncryptionLocked".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Note::update(&"book/note".into(), "x".into(), &conn),
+                Err("EncryptionLocked".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "wrong passphrase is rejected";
+            assert_eq!(
+                unlock(None, "wrong", &conn),
+                Err("WrongPassphrase".into()),
+                "{}",
+                msg
+            );
+            assert!(!is_unlocked(MASTER), "{}", msg);
+            assert_eq!(unlock(None, "pass", &conn), Ok(()), "{}", msg);
+            assert_eq!(
+                Note::cat(&"book/note".into(), &conn),
+                Ok("secret".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "change passphrase re-keys notes";
+            let before = Note::query(&"book/note".into(), &conn).unwrap();
+            assert_eq!(
+                change_passphrase("wrong", "new", &conn),
+                Err("WrongPassphrase".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(change_passphrase("pass", "new", &conn), Ok(1), "{}", msg);
+            let after = Note::query(&"book/note".into(), &conn).unwrap();
+            assert_ne!(before.get_raw_body(), after.get_raw_body(), "{}", msg);
+            assert_eq!(after.read_body(), Ok("secret".into()), "{}", msg);
+            assert_eq!(
+                get_secret("sync.password", &conn),
+                Ok(Some("dav".into())),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                unlock(None, "pass", &conn),
+                Err("WrongPassphrase".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(unlock(None, "new", &conn), Ok(()), "{}", msg);
+        }
+    }
+
+    #[test]
+    pub fn book_integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Folder::make(&"secret/inner".into(), true, &conn).unwrap();
+        Folder::make(&"plain".into(), false, &conn).unwrap();
+        Note::make(&"secret/inner/note".into(), false, &conn).unwrap();
+        Note::update(&"secret/inner/note".into(), "body".into(), &conn).unwrap();
+        let book = "secret".into();
+
+        {
+            let msg = "encrypt book seals notes under it";
+            assert_eq!(encrypt_book(&book, "pass", &conn), Ok(1), "{}", msg);
+            let note = Note::query(&"secret/inner/note".into(), &conn).unwrap();
+            let folder = Folder::get(&book, &conn).unwrap();
+            assert_eq!(
+                key_id(note.get_raw_body().as_bytes()),
+                Some(folder.get_id()),
+                "{}",
+                msg
+            );
+            Note::make(&"secret/new".into(), false, &conn).unwrap();
+            let note = Note::query(&"secret/new".into(), &conn).unwrap();
+            assert!(key_id(note.get_raw_body().as_bytes()).is_some(), "{}", msg);
+        }
+
+        {
+            let msg = "sealed attachments don't store hash of content";
+            let note = Note::query(&"secret/new".into(), &conn).unwrap();
+            let data = b"same bytes".to_vec();
+            let resource = Resource::new("a.txt".into(), &note, data.clone(), &conn).unwrap();
+            assert_ne!(
+                resource.sha256,
+                hex::encode(Sha256::digest(&data)),
+                "{}",
+                msg
+            );
+            assert_eq!(resource.read_data(), Ok(data), "{}", msg);
+        }
+
+        {
+            let msg = "locked book refuses reads";
+            lock(Some(&book), &conn).unwrap();
+            let note = Note::query(&"secret/inner/note".into(), &conn).unwrap();
+            assert!(note.is_locked(), "{}", msg);
+            assert_eq!(
+                Note::cat(&"secret/inner/note".into(), &conn),
+                Err("BookLocked".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                unlock(Some(&book), "wrong", &conn),
+                Err("WrongPassphrase".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                unlock(Some(&"plain".into()), "pass", &conn),
+                Err("BookNotEncrypted".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "unlocked book can be read and notes moved out";
+            unlock(Some(&book), "pass", &conn).unwrap();
+            assert_eq!(
+                Note::cat(&"secret/inner/note".into(), &conn),
+                Ok("body".into()),
+                "{}",
+                msg
+            );
+            Note::move_note(
+                &"secret/inner/note".into(),
+                &"plain".into(),
+                false,
+                false,
+                &conn,
+            )
+            .unwrap();
+            let note = Note::query(&"plain/note".into(), &conn).unwrap();
+            assert_eq!(note.get_raw_body(), "body", "{}", msg);
+        }
+    }
+}
@@ -1,5 +1,9 @@
+pub mod crypto;
 pub mod models;
 pub mod schema;
+pub mod undo;
+
+use std::path::PathBuf;
 
 use diesel::{connection::Connection, SqliteConnection};
 
@@ -7,21 +11,52 @@ embed_migrations!();
 
 pub type DbConnection = SqliteConnection;
 
-pub fn establish_connection() -> Result<SqliteConnection, String> {
+/// name of dir of a test thread, the dir is removed when the thread ends.
+#[cfg(test)]
+struct TestDir(String);
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let mut path = std::env::temp_dir();
+        path.push(structopt::clap::crate_name!());
+        path.push(&self.0);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+/// local_data_dir/<app>/, created on first use
+pub fn data_dir() -> Result<PathBuf, String> {
+    use std::fs::create_dir_all;
+
     #[cfg(not(test))]
-    let db_url = {
-        use std::fs::create_dir_all;
+    let mut path = dirs::data_local_dir().ok_or_else(|| "NoDataDir".to_string())?;
 
-        // get local data dir
-        let mut path = dirs::data_local_dir().expect("should have local data dir");
+    #[cfg(test)]
+    let mut path = std::env::temp_dir();
 
-        // create local_data_dir/<app>/
-        path.push(structopt::clap::crate_name!());
-        if !path.exists() {
-            create_dir_all(&path).expect(&format!("creating dir {:#?} failed", &path));
+    path.push(structopt::clap::crate_name!());
+
+    // each test thread gets its own dir, so tests do not see (or gc) files of
+    // other tests
+    #[cfg(test)]
+    {
+        thread_local! {
+            static TEST_DIR: TestDir = TestDir(uuid::Uuid::new_v4().to_string());
         }
+        path.push(TEST_DIR.with(|dir| dir.0.clone()));
+    }
+    if !path.exists() {
+        create_dir_all(&path).map_err(|e| format!("creating dir {:#?} failed: {}", &path, e))?;
+    }
+    Ok(path)
+}
 
+pub fn establish_connection() -> Result<SqliteConnection, String> {
+    #[cfg(not(test))]
+    let db_url = {
         // set path to local_data_dir/<app>/<app.db>
+        let mut path = data_dir()?;
         path.push(format!("{}.db", structopt::clap::crate_name!()));
         path.to_string_lossy().into_owned()
     };
@@ -41,3 +76,9 @@ impl From<diesel::result::Error> for DieselStringError {
         DieselStringError(error.to_string())
     }
 }
+
+impl From<String> for DieselStringError {
+    fn from(error: String) -> Self {
+        DieselStringError(error)
+    }
+}
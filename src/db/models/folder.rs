@@ -1,4 +1,7 @@
-use std::{borrow::Cow, path::PathBuf};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
 
 use diesel::prelude::*;
 
@@ -26,19 +29,19 @@ impl Folder {
 
         diesel::insert_or_ignore_into(folders::table)
             .values(&folder)
-            .execute(*&conn)
+            .execute(conn)
             .map_err(|e| e.to_string())?;
 
         folders::table
             .filter(folders::title.eq(&folder.title))
             .filter(folders::parent_id.eq(&folder.parent_id))
-            .first(*&conn)
+            .first(conn)
             .map_err(|e| e.to_string())
     }
 
     pub fn make(path: &PathBuf, parents: bool, conn: &DbConnection) -> Result<Self, String> {
         let parent = if let Some(path) = path.parent() {
-            Self::query(&path.to_path_buf(), parents, *&conn)?
+            Self::query(&path.to_path_buf(), parents, conn)?
         } else {
             None
         };
@@ -47,7 +50,7 @@ impl Folder {
                 .map(|p| p.to_string_lossy().to_string())
                 .unwrap_or("".to_string()),
             parent,
-            *&conn,
+            conn,
         )
     }
 
@@ -67,13 +70,13 @@ impl Folder {
             match folders::table
                 .filter(folders::title.eq(&title.to_string_lossy()))
                 .filter(folders::parent_id.eq(&parent_id))
-                .first(*&conn)
+                .first(conn)
             {
                 Ok(folder_item) => {
                     folder = Some(folder_item);
                 }
                 Err(diesel::NotFound) if create => {
-                    folder = Some(Self::new(title.to_string_lossy().into(), folder, *&conn)?);
+                    folder = Some(Self::new(title.to_string_lossy().into(), folder, conn)?);
                 }
                 Err(e) => return Err(e.to_string()),
             }
@@ -88,9 +91,107 @@ impl Folder {
         }
     }
 
+    pub fn get_id(&self) -> &str {
+        self.id.as_deref().unwrap_or("")
+    }
+
+    pub fn get_parent_id(&self) -> &str {
+        &self.parent_id
+    }
+
+    pub fn from_raw(id: String, title: String, parent_id: String) -> Self {
+        Self {
+            id: Some(id),
+            title,
+            parent_id,
+        }
+    }
+
+    pub fn all(conn: &DbConnection) -> Result<Vec<Self>, String> {
+        folders::table.load(conn).map_err(|e| e.to_string())
+    }
+
+    /// insert or update folder keeping its id, title gets id suffix if taken.
+    pub fn save_raw(&mut self, conn: &DbConnection) -> Result<usize, String> {
+        let exists = folders::table
+            .find(&self.id)
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| e.to_string())?
+            > 0;
+        let save = |folder: &Self| {
+            if exists {
+                diesel::update(folders::table.find(&folder.id))
+                    .set((
+                        folders::title.eq(&folder.title),
+                        folders::parent_id.eq(&folder.parent_id),
+                    ))
+                    .execute(conn)
+            } else {
+                diesel::insert_into(folders::table)
+                    .values(folder)
+                    .execute(conn)
+            }
+        };
+        match save(self) {
+            Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => {
+                self.title = format!("{} ({})", self.title, &self.get_id()[..8]);
+                save(self).map_err(|e| e.to_string())
+            }
+            result => result.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn delete_by_id(id: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::delete(folders::table.find(id))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// book holds no books or notes.
+    pub fn is_empty_by_id(id: &str, conn: &DbConnection) -> Result<bool, String> {
+        let books = folders::table
+            .filter(folders::parent_id.eq(id))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| e.to_string())?;
+        Ok(books == 0 && Note::list_parent_id(id, conn)?.is_empty())
+    }
+
+    /// existing book at path, root is not a book
+    pub fn get(path: &PathBuf, conn: &DbConnection) -> Result<Self, String> {
+        Self::query(path, false, conn)?.ok_or_else(|| "NamelessFolder".to_string())
+    }
+
+    /// id followed by ids of all its parents, empty for root, stops at a book
+    /// missing from database
+    pub fn ancestor_ids(id: &str, conn: &DbConnection) -> Result<Vec<String>, String> {
+        let mut ids = vec![];
+        let mut id = id.to_string();
+        while !id.is_empty() {
+            // books of synced items may not be pulled yet
+            let parent_id = match folders::table
+                .find(&id)
+                .select(folders::parent_id)
+                .first::<String>(conn)
+                .optional()
+                .map_err(|e| e.to_string())?
+            {
+                Some(parent_id) => parent_id,
+                None => break,
+            };
+            ids.push(id);
+            id = parent_id;
+        }
+        Ok(ids)
+    }
+
     pub fn list(path: &PathBuf, conn: &DbConnection) -> Result<(Vec<Folder>, Vec<Note>), String> {
-        let folder = Self::query(path, false, *&conn)?;
-        Self::list_optional_folder(folder.as_ref(), *&conn)
+        let folder = Self::query(path, false, conn)?;
+        Self::list_optional_folder(folder.as_ref(), conn)
     }
 
     fn list_optional_folder(
@@ -101,29 +202,105 @@ impl Folder {
         Ok((
             folders::table
                 .filter(folders::parent_id.eq(&parent_id))
-                .load::<Self>(*&conn)
+                .load::<Self>(conn)
                 .map_err(|e| e.to_string())?,
-            Note::list_parent_id(&parent_id, *&conn)?,
+            Note::list_parent_id(&parent_id, conn)?,
         ))
     }
 
+    /// book (ending with `/`) and note paths matching `pattern`, sorted.
+    ///
+    /// `*` and `?` match within a title, `**` matches any number of books and
+    /// pattern ending with `/` only matches books.
+    pub fn glob(pattern: &Path, conn: &DbConnection) -> Result<Vec<PathBuf>, String> {
+        let books_only = pattern.to_string_lossy().ends_with('/');
+        let parts = pattern
+            .iter()
+            .filter(|part| *part != "/")
+            .map(|part| part.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let mut found = vec![];
+        Self::glob_in(
+            None,
+            &PathBuf::from("/"),
+            &parts,
+            books_only,
+            conn,
+            &mut found,
+        )?;
+        found.sort();
+        found.dedup();
+        Ok(found)
+    }
+
+    fn glob_in(
+        folder: Option<&Self>,
+        path: &Path,
+        parts: &[String],
+        books_only: bool,
+        conn: &DbConnection,
+        found: &mut Vec<PathBuf>,
+    ) -> Result<(), String> {
+        let (part, rest) = match parts.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let (folders, notes) = Self::list_optional_folder(folder, conn)?;
+        if part == "**" {
+            // trailing `**` matches everything below
+            let any = ["*".to_string()];
+            let rest = if rest.is_empty() { &any[..] } else { rest };
+            Self::glob_in(folder, path, rest, books_only, conn, found)?;
+            for sub in &folders {
+                Self::glob_in(
+                    Some(sub),
+                    &path.join(&sub.title),
+                    parts,
+                    books_only,
+                    conn,
+                    found,
+                )?;
+            }
+            return Ok(());
+        }
+        for sub in &folders {
+            if !wildcard_match(part, &sub.title) {
+                continue;
+            }
+            let sub_path = path.join(&sub.title);
+            if rest.is_empty() {
+                found.push(format!("{}/", sub_path.to_string_lossy()).into());
+            } else {
+                Self::glob_in(Some(sub), &sub_path, rest, books_only, conn, found)?;
+            }
+        }
+        if rest.is_empty() && !books_only {
+            for note in notes {
+                if wildcard_match(part, &note.title) {
+                    found.push(path.join(&note.title));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn delete_folder(&self, recursive: bool, conn: &DbConnection) -> Result<usize, String> {
-        let (folders, notes) = Self::list_optional_folder(Some(self), *&conn)?;
+        let (folders, notes) = Self::list_optional_folder(Some(self), conn)?;
         if recursive {
             let mut rows = 0;
             for note in notes {
-                rows += note.delete_note(*&conn)?;
+                rows += note.delete_note(conn)?;
             }
             for folder in folders {
-                rows += folder.delete_folder(recursive, *&conn)?;
+                rows += folder.delete_folder(recursive, conn)?;
             }
             Ok(rows
                 + diesel::delete(folders::table.find(&self.id))
-                    .execute(*&conn)
+                    .execute(conn)
                     .map_err(|e| e.to_string())?)
         } else if folders.is_empty() && notes.is_empty() {
             diesel::delete(folders::table.find(&self.id))
-                .execute(*&conn)
+                .execute(conn)
                 .map_err(|e| e.to_string())
         } else {
             return Err("NonEmptyBook".into());
@@ -131,11 +308,11 @@ impl Folder {
     }
 
     pub fn delete(path: &PathBuf, recursive: bool, conn: &DbConnection) -> Result<usize, String> {
-        let folder = Self::query(path, false, *&conn)?;
+        let folder = Self::query(path, false, conn)?;
         if let Some(folder) = folder {
             conn.transaction::<_, DieselStringError, _>(|| {
                 folder
-                    .delete_folder(recursive, *&conn)
+                    .delete_folder(recursive, conn)
                     .map_err(|e| DieselStringError(e))
             })
             .map_err(|e| e.0)
@@ -145,9 +322,34 @@ impl Folder {
     }
 }
 
+/// `*` matches any characters, `?` one character.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position of last `*` and text matched by it, to backtrack
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::db::{establish_connection, DbConnection};
+    use crate::db::{establish_connection, models::Note, DbConnection};
 
     use super::Folder;
 
@@ -268,6 +470,46 @@ mod tests {
             assert_eq!(execute.unwrap(), 1, "{}: {}", msg, parent_folder);
             assert_folder_not_exists(&parent_folder, &msg, &conn);
         }
+
+        {
+            let msg = "glob matches books and notes";
+            for note in &[
+                "projects/a/meeting-1",
+                "projects/b/meeting-2",
+                "projects/b/draft",
+            ] {
+                Note::make(&note.into(), true, &conn).unwrap();
+            }
+            Note::make(&"inbox/draft".into(), true, &conn).unwrap();
+            let glob = |pattern: &str| {
+                Folder::glob(pattern.as_ref(), &conn)
+                    .unwrap()
+                    .into_iter()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                glob("projects/*/meeting-*"),
+                vec!["/projects/a/meeting-1", "/projects/b/meeting-2"],
+                "{}",
+                msg
+            );
+            assert_eq!(
+                glob("**/draft"),
+                vec!["/inbox/draft", "/projects/b/draft"],
+                "{}",
+                msg
+            );
+            assert_eq!(
+                glob("projects/*/"),
+                vec!["/projects/a/", "/projects/b/"],
+                "{}",
+                msg
+            );
+            assert_eq!(glob("projects/?/meeting-?").len(), 2, "{}", msg);
+            assert_eq!(glob("projects/**").len(), 5, "{}", msg);
+            assert!(glob("nothing/*").is_empty(), "{}", msg);
+        }
     }
 
     fn assert_folder_count(folder: &str, count: usize, msg: &str, conn: &DbConnection) {
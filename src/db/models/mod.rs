@@ -2,6 +2,14 @@ use super::schema;
 
 mod folder;
 mod note;
+mod resource;
+mod setting;
+mod sync_item;
+mod tag;
 
 pub use folder::Folder;
 pub use note::Note;
+pub use resource::Resource;
+pub use setting::Setting;
+pub use sync_item::SyncItem;
+pub use tag::Tag;
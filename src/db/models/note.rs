@@ -3,9 +3,9 @@ use std::path::PathBuf;
 use diesel::prelude::*;
 use uuid::Uuid;
 
-use crate::db::DbConnection;
+use crate::db::{crypto, DbConnection};
 
-use super::{schema::notes, Folder};
+use super::{schema::notes, Folder, Resource};
 
 #[derive(Queryable, Insertable, Identifiable, AsChangeset, Debug, PartialEq, Eq)]
 pub struct Note {
@@ -24,30 +24,100 @@ impl Note {
             id: Some(Uuid::new_v4().to_string()),
             parent_id: parent_id.into(),
             title,
-            body: String::new(),
+            body: crypto::seal(String::new(), parent_id, conn)?,
         };
         diesel::insert_or_ignore_into(notes::table)
             .values(&note)
-            .execute(*&conn)
+            .execute(conn)
             .map_err(|e| e.to_string())?;
 
         notes::table
             .filter(notes::title.eq(&note.title))
             .filter(notes::parent_id.eq(&note.parent_id))
-            .first(*&conn)
+            .first(conn)
             .map_err(|e| e.to_string())
     }
 
-    fn query(path: &PathBuf, conn: &DbConnection) -> Result<Self, String> {
-        let parent_id = Self::get_parent_id(path, false, *&conn)?;
+    pub fn query(path: &PathBuf, conn: &DbConnection) -> Result<Self, String> {
+        let parent_id = Self::get_parent_id(path, false, conn)?;
         let title = Self::get_title(&path);
         notes::table
             .filter(notes::title.eq(&title))
             .filter(notes::parent_id.eq(&parent_id))
-            .first(*&conn)
+            .first(conn)
             .map_err(|e| e.to_string())
     }
 
+    pub fn get_id(&self) -> &str {
+        self.id.as_deref().unwrap_or("")
+    }
+
+    pub fn from_raw(id: String, parent_id: String, title: String, body: String) -> Self {
+        Self {
+            id: Some(id),
+            parent_id,
+            title,
+            body,
+        }
+    }
+
+    pub fn all(conn: &DbConnection) -> Result<Vec<Self>, String> {
+        notes::table.load(conn).map_err(|e| e.to_string())
+    }
+
+    /// insert or update note keeping its id, title gets id suffix if taken.
+    pub fn save_raw(&mut self, conn: &DbConnection) -> Result<usize, String> {
+        let exists = notes::table
+            .find(&self.id)
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(|e| e.to_string())?
+            > 0;
+        let save = |note: &Self| {
+            if exists {
+                diesel::update(notes::table.find(&note.id))
+                    .set(note)
+                    .execute(conn)
+            } else {
+                diesel::insert_into(notes::table).values(note).execute(conn)
+            }
+        };
+        match save(self) {
+            Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => {
+                self.title = format!("{} ({})", self.title, &self.get_id()[..8]);
+                save(self).map_err(|e| e.to_string())
+            }
+            result => result.map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn delete_by_id(id: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::delete(notes::table.find(id))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn get_book_id(&self) -> &str {
+        &self.parent_id
+    }
+
+    /// body as stored, encrypted if encryption is enabled
+    pub fn get_raw_body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn read_body(&self) -> Result<String, String> {
+        crypto::open(&self.body)
+    }
+
+    /// body is encrypted with a key not unlocked in this session
+    pub fn is_locked(&self) -> bool {
+        matches!(crypto::key_id(self.body.as_bytes()), Some(key_id) if !crypto::is_unlocked(key_id))
+    }
+
     fn get_title(path: &PathBuf) -> String {
         path.file_name()
             .map(|p| p.to_string_lossy().to_string())
@@ -57,7 +127,7 @@ impl Note {
     fn get_parent_id(path: &PathBuf, parents: bool, conn: &DbConnection) -> Result<String, String> {
         match path.parent() {
             Some(path) if path.file_name().is_some() => Ok(Folder::get_id_cow(
-                &Folder::query(&path.to_path_buf(), parents, *&conn)?.as_ref(),
+                &Folder::query(&path.to_path_buf(), parents, conn)?.as_ref(),
             )
             .to_string()),
             _ => Err("BookLessNote".to_string()),
@@ -67,8 +137,8 @@ impl Note {
     pub fn make(path: &PathBuf, parents: bool, conn: &DbConnection) -> Result<Self, String> {
         Self::new(
             Self::get_title(&path),
-            &Self::get_parent_id(&path, parents, *&conn)?,
-            *&conn,
+            &Self::get_parent_id(path, parents, conn)?,
+            conn,
         )
     }
 
@@ -76,10 +146,10 @@ impl Note {
         parent_id: &str,
         conn: &DbConnection,
     ) -> Result<Vec<Self>, String> {
-        Ok(notes::table
+        notes::table
             .filter(notes::parent_id.eq(&parent_id))
-            .load::<Self>(*&conn)
-            .map_err(|e| e.to_string())?)
+            .load::<Self>(conn)
+            .map_err(|e| e.to_string())
     }
 
     pub(super) fn delete_note(&self, conn: &DbConnection) -> Result<usize, String> {
@@ -88,29 +158,68 @@ impl Note {
                 .filter(notes::title.eq(&self.title))
                 .filter(notes::parent_id.eq(&self.parent_id)),
         )
-        .execute(*&conn)
+        .execute(conn)
         .map_err(|e| e.to_string())
     }
 
     pub fn cat(path: &PathBuf, conn: &DbConnection) -> Result<String, String> {
-        Self::query(&path, *&conn).map(|n| n.body)
+        Self::query(path, conn)?.read_body()
     }
 
     fn update_self(&self, conn: &DbConnection) -> Result<usize, String> {
         diesel::update(notes::table.find(&self.id))
             .set(&*self)
-            .execute(*&conn)
+            .execute(conn)
             .map_err(|e| e.to_string())
     }
 
     pub fn update(path: &PathBuf, body: String, conn: &DbConnection) -> Result<usize, String> {
-        let mut note = Self::query(&path, *&conn)?;
-        note.body = body;
-        note.update_self(*&conn)
+        let mut note = Self::query(path, conn)?;
+        note.body = crypto::seal(body, &note.parent_id, conn)?;
+        note.update_self(conn)
+    }
+
+    /// replace stored body if reseal returns a new value.
+    pub(crate) fn reseal<F>(&mut self, reseal: F, conn: &DbConnection) -> Result<usize, String>
+    where
+        F: FnOnce(&str) -> Result<Option<String>, String>,
+    {
+        match reseal(&self.body)? {
+            Some(body) => {
+                self.body = body;
+                self.update_self(conn)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// replace stored bodies for which reseal returns a new value.
+    pub(crate) fn reseal_all<F>(mut reseal: F, conn: &DbConnection) -> Result<usize, String>
+    where
+        F: FnMut(&str) -> Result<Option<String>, String>,
+    {
+        let mut rows = 0;
+        for mut note in notes::table.load::<Self>(conn).map_err(|e| e.to_string())? {
+            rows += note.reseal(&mut reseal, conn)?;
+        }
+        Ok(rows)
+    }
+
+    /// encrypt body and attachments for the book note is moved to.
+    fn reseal_for(&mut self, parent_id: &str, conn: &DbConnection) -> Result<(), String> {
+        self.body = crypto::seal(self.read_body()?, parent_id, conn)?;
+        Resource::reseal_all(
+            |data| {
+                crypto::seal_bytes(crypto::open_bytes(data.to_vec())?, parent_id, conn).map(Some)
+            },
+            Some(self.get_id()),
+            conn,
+        )?;
+        Ok(())
     }
 
     pub fn delete(path: &PathBuf, conn: &DbConnection) -> Result<usize, String> {
-        Self::query(&path, *&conn)?.delete_note(*&conn)
+        Self::query(path, conn)?.delete_note(conn)
     }
 
     pub(super) fn move_self(
@@ -122,16 +231,19 @@ impl Note {
     ) -> Result<usize, String> {
         let mut path = dest.clone();
         path.push(&self.title);
-        match Self::query(&path, *&conn) {
+        match Self::query(&path, conn) {
             Ok(_) if !overwrite => Err("OverwriteNotAllowed".into()),
             Ok(mut dest_note) => {
-                dest_note.body = self.body.clone();
-                Ok(dest_note.update_self(*&conn)? + self.delete_note(*&conn)?)
+                dest_note.body = crypto::seal(self.read_body()?, &dest_note.parent_id, conn)?;
+                Ok(dest_note.update_self(conn)? + self.delete_note(conn)?)
             }
             Err(_) => {
-                let parent_id = Self::get_parent_id(&path, parents, *&conn)?;
+                let parent_id = Self::get_parent_id(&path, parents, conn)?;
+                if parent_id != self.parent_id {
+                    self.reseal_for(&parent_id, conn)?;
+                }
                 self.parent_id = parent_id;
-                self.update_self(*&conn)
+                self.update_self(conn)
             }
         }
     }
@@ -143,7 +255,7 @@ impl Note {
         parents: bool,
         conn: &DbConnection,
     ) -> Result<usize, String> {
-        let mut note = Self::query(&path, *&conn)?;
+        let mut note = Self::query(path, conn)?;
         note.move_self(dest, overwrite, parents, &*conn)
     }
 
@@ -156,13 +268,14 @@ impl Note {
     ) -> Result<usize, String> {
         let mut path = dest.clone();
         path.push(&self.title);
-        if Self::query(&path, *&conn).is_ok() && !overwrite {
+        if Self::query(&path, conn).is_ok() && !overwrite {
             return Err("OverwriteNotAllowed".into());
         }
-        let parent_id = Self::get_parent_id(&path, parents, *&conn)?;
+        let parent_id = Self::get_parent_id(&path, parents, conn)?;
+        let body = crypto::seal(self.read_body()?, &parent_id, conn)?;
         let mut note = Self::new(self.title, &parent_id, &conn)?;
-        note.body = self.body;
-        note.update_self(*&conn)
+        note.body = body;
+        note.update_self(conn)
     }
 
     pub fn copy_note(
@@ -172,7 +285,7 @@ impl Note {
         parents: bool,
         conn: &DbConnection,
     ) -> Result<usize, String> {
-        let note = Self::query(&path, *&conn)?;
+        let note = Self::query(path, conn)?;
         note.copy_self(dest, overwrite, parents, &*conn)
     }
 }
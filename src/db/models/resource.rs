@@ -0,0 +1,445 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{
+    schema::{notes, resources, undo_log},
+    Note,
+};
+use crate::db::{crypto, data_dir, DbConnection};
+
+/// blobs up to this size are kept in the database, bigger ones under data dir.
+const INLINE_LIMIT: usize = 64 * 1024;
+
+#[derive(Queryable, Insertable, Identifiable, Debug, PartialEq, Eq)]
+pub struct Resource {
+    id: Option<String>,
+    note_id: String,
+    pub title: String,
+    pub mime: String,
+    pub size: i64,
+    pub sha256: String,
+    blob: Option<Vec<u8>>,
+    file_path: Option<String>,
+}
+
+impl Resource {
+    fn resource_dir() -> Result<PathBuf, String> {
+        let mut path = data_dir()?;
+        path.push("resources");
+        if !path.exists() {
+            fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+        }
+        Ok(path)
+    }
+
+    pub fn get_id(&self) -> &str {
+        self.id.as_deref().unwrap_or("")
+    }
+
+    pub fn get_note_id(&self) -> &str {
+        &self.note_id
+    }
+
+    pub fn new(
+        title: String,
+        note: &Note,
+        data: Vec<u8>,
+        conn: &DbConnection,
+    ) -> Result<Self, String> {
+        if title.is_empty() {
+            return Err("NamelessResource".to_string());
+        }
+        let size = data.len() as i64;
+        let data = crypto::seal_bytes(data, note.get_book_id(), conn)?;
+        let mut resource = Self {
+            id: Some(Uuid::new_v4().to_string()),
+            note_id: note.get_id().into(),
+            mime: mime_guess::from_path(&title)
+                .first_or_octet_stream()
+                .to_string(),
+            title,
+            size,
+            sha256: String::new(),
+            blob: None,
+            file_path: None,
+        };
+        resource.store(data)?;
+        diesel::insert_into(resources::table)
+            .values(&resource)
+            .execute(conn)
+            .map_err(|e| e.to_string())?;
+        Ok(resource)
+    }
+
+    /// keep stored (possibly encrypted) data inline or under data dir, the
+    /// hash is of stored data so sealed attachments don't reveal their content.
+    fn store(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.sha256 = hex::encode(Sha256::digest(&data));
+        if data.len() <= INLINE_LIMIT {
+            self.blob = Some(data);
+            self.file_path = None;
+        } else {
+            // content addressed, so identical attachments share one file
+            let file_path = self.sha256.clone();
+            let mut path = Self::resource_dir()?;
+            path.push(&file_path);
+            if !path.exists() {
+                fs::write(&path, &data).map_err(|e| e.to_string())?;
+            }
+            self.blob = None;
+            self.file_path = Some(file_path);
+        }
+        Ok(())
+    }
+
+    /// data as stored, encrypted if encryption is enabled
+    pub fn read_stored(&self) -> Result<Vec<u8>, String> {
+        match (&self.blob, &self.file_path) {
+            (Some(blob), _) => Ok(blob.clone()),
+            (None, Some(file_path)) => {
+                let mut path = Self::resource_dir()?;
+                path.push(file_path);
+                fs::read(&path).map_err(|e| e.to_string())
+            }
+            (None, None) => Err("MissingBlob".into()),
+        }
+    }
+
+    pub fn from_raw(
+        id: String,
+        note_id: String,
+        title: String,
+        mime: String,
+        size: i64,
+        sha256: String,
+    ) -> Self {
+        Self {
+            id: Some(id),
+            note_id,
+            title,
+            mime,
+            size,
+            sha256,
+            blob: None,
+            file_path: None,
+        }
+    }
+
+    pub fn all(conn: &DbConnection) -> Result<Vec<Self>, String> {
+        resources::table.load(conn).map_err(|e| e.to_string())
+    }
+
+    /// insert or replace resource keeping its id, data is stored as is.
+    pub fn save_raw(&mut self, data: Vec<u8>, conn: &DbConnection) -> Result<usize, String> {
+        self.store(data)?;
+        diesel::replace_into(resources::table)
+            .values(&*self)
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn attach(note: &PathBuf, file: &PathBuf, conn: &DbConnection) -> Result<Self, String> {
+        let note = Note::query(note, conn)?;
+        let mut data = vec![];
+        OpenOptions::new()
+            .read(true)
+            .open(file)
+            .map_err(|e| e.to_string())?
+            .read_to_end(&mut data)
+            .map_err(|e| e.to_string())?;
+        let title = file
+            .file_name()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self::new(title, &note, data, conn)
+    }
+
+    pub fn get(id: &str, conn: &DbConnection) -> Result<Self, String> {
+        resources::table
+            .find(id)
+            .first(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn list(note: &PathBuf, conn: &DbConnection) -> Result<Vec<Self>, String> {
+        let note = Note::query(note, conn)?;
+        Self::list_note_id(note.get_id(), conn)
+    }
+
+    pub fn list_note_id(note_id: &str, conn: &DbConnection) -> Result<Vec<Self>, String> {
+        resources::table
+            .filter(resources::note_id.eq(note_id))
+            .load(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn read_data(&self) -> Result<Vec<u8>, String> {
+        let data = self.read_stored()?;
+        if hex::encode(Sha256::digest(&data)) != self.sha256 {
+            return Err("CorruptBlob".into());
+        }
+        crypto::open_bytes(data)
+    }
+
+    pub fn extract(id: &str, out: &PathBuf, conn: &DbConnection) -> Result<usize, String> {
+        let data = Self::get(id, conn)?.read_data()?;
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(out)
+            .map_err(|e| e.to_string())?
+            .write_all(&data)
+            .map_err(|e| e.to_string())?;
+        Ok(data.len())
+    }
+
+    pub fn detach(id: &str, conn: &DbConnection) -> Result<usize, String> {
+        let resource = Self::get(id, conn)?;
+        diesel::delete(resources::table.find(&resource.id))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// replace stored blobs (of note if given) for which reseal returns a new value.
+    pub(crate) fn reseal_all<F>(
+        mut reseal: F,
+        note_id: Option<&str>,
+        conn: &DbConnection,
+    ) -> Result<usize, String>
+    where
+        F: FnMut(&[u8]) -> Result<Option<Vec<u8>>, String>,
+    {
+        let mut rows = 0;
+        let resources = match note_id {
+            Some(note_id) => Self::list_note_id(note_id, conn)?,
+            None => resources::table
+                .load::<Self>(conn)
+                .map_err(|e| e.to_string())?,
+        };
+        for mut resource in resources {
+            if let Some(data) = reseal(&resource.read_stored()?)? {
+                resource.store(data)?;
+                rows += diesel::update(resources::table.find(&resource.id))
+                    .set((
+                        resources::sha256.eq(&resource.sha256),
+                        resources::blob.eq(&resource.blob),
+                        resources::file_path.eq(&resource.file_path),
+                    ))
+                    .execute(conn)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// remove resources whose note is gone, and blob files no resource points
+    /// to, nor a resource the undo journal can restore.
+    pub fn gc(conn: &DbConnection) -> Result<usize, String> {
+        let note_ids = notes::table.select(notes::id).load::<Option<String>>(conn);
+        let note_ids = note_ids
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        let mut rows = 0;
+        for resource in resources::table
+            .load::<Self>(conn)
+            .map_err(|e| e.to_string())?
+        {
+            if !note_ids.contains(&resource.note_id) {
+                rows += diesel::delete(resources::table.find(&resource.id))
+                    .execute(conn)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        let used_files = resources::table
+            .select(resources::file_path)
+            .load::<Option<String>>(conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        let journal = undo_log::table
+            .select(undo_log::statement)
+            .load::<String>(conn)
+            .map_err(|e| e.to_string())?;
+        for entry in fs::read_dir(Self::resource_dir()?).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let quoted = format!("'{}'", name);
+            if !used_files.contains(&name) && !journal.iter().any(|s| s.contains(&quoted)) {
+                fs::remove_file(entry.path()).map_err(|e| e.to_string())?;
+                rows += 1;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// file name used when a resource is written next to exported notes.
+    pub fn export_name(&self) -> String {
+        let ext = match self.title.rsplit_once('.') {
+            Some((_, ext)) => Some(ext),
+            None => {
+                mime_guess::get_mime_extensions_str(&self.mime).and_then(|e| e.first().copied())
+            }
+        };
+        match ext {
+            Some(ext) => format!("{}.{}", self.get_id(), ext),
+            None => self.get_id().to_string(),
+        }
+    }
+
+    /// rewrite markdown `:/resource-id` link targets with `replace`.
+    ///
+    /// replace returning None keeps the reference as is.
+    pub fn replace_references<F>(body: &str, mut replace: F) -> String
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut output = String::with_capacity(body.len());
+        let mut rest = body;
+        while let Some(start) = rest.find("(:/") {
+            output.push_str(&rest[..=start]);
+            rest = &rest[start + 3..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_hexdigit() || c == '-'))
+                .unwrap_or(rest.len());
+            let id = &rest[..end];
+            match Some(id).filter(|id| !id.is_empty()).and_then(&mut replace) {
+                Some(target) => output.push_str(&target),
+                _ => {
+                    output.push_str(":/");
+                    output.push_str(id);
+                }
+            }
+            rest = &rest[end..];
+        }
+        output.push_str(rest);
+        output
+    }
+
+    /// ids of resources referenced as `:/resource-id` in body.
+    pub fn references(body: &str) -> Vec<String> {
+        let mut ids = vec![];
+        Self::replace_references(body, |id| {
+            ids.push(id.to_string());
+            None
+        });
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        establish_connection,
+        models::{Folder, Note},
+    };
+
+    use super::Resource;
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Folder::make(&"book".into(), false, &conn).unwrap();
+        Note::make(&"book/note".into(), false, &conn).unwrap();
+
+        let mut small = std::env::temp_dir();
+        small.push(format!("{}_small.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&small, b"hello").unwrap();
+        let mut big = std::env::temp_dir();
+        big.push(format!("{}_big.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&big, vec![7u8; super::INLINE_LIMIT + 1]).unwrap();
+
+        {
+            let msg = "attach to missing note";
+            assert_eq!(
+                Resource::attach(&"book/missing".into(), &small, &conn),
+                Err("NotFound".into()),
+                "{}",
+                msg
+            );
+        }
+
+        let small_id;
+        let big_id;
+        {
+            let msg = "attach inline and external blobs";
+            let resource = Resource::attach(&"book/note".into(), &small, &conn).unwrap();
+            assert_eq!(resource.mime, "text/plain", "{}", msg);
+            assert_eq!(resource.size, 5, "{}", msg);
+            small_id = resource.get_id().to_string();
+            let resource = Resource::attach(&"book/note".into(), &big, &conn).unwrap();
+            assert!(resource.blob.is_none(), "{}", msg);
+            big_id = resource.get_id().to_string();
+            assert_eq!(
+                Resource::list(&"book/note".into(), &conn).unwrap().len(),
+                2,
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "extract round trips";
+            let mut out = std::env::temp_dir();
+            out.push(format!("{}_out", uuid::Uuid::new_v4()));
+            assert_eq!(Resource::extract(&small_id, &out, &conn), Ok(5), "{}", msg);
+            assert_eq!(std::fs::read(&out).unwrap(), b"hello", "{}", msg);
+            Resource::extract(&big_id, &out, &conn).unwrap();
+            assert_eq!(
+                std::fs::read(&out).unwrap().len(),
+                super::INLINE_LIMIT + 1,
+                "{}",
+                msg
+            );
+            std::fs::remove_file(out).unwrap();
+        }
+
+        {
+            let msg = "references are found and rewritten";
+            let body = format!("![a](:/{}) and [b](:/{} \"t\") (:/)", small_id, big_id);
+            assert_eq!(
+                Resource::references(&body),
+                vec![small_id.clone(), big_id.clone()],
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Resource::replace_references(&body, |id| Some(format!("r/{}", &id[..2]))),
+                format!(
+                    "![a](r/{}) and [b](r/{} \"t\") (:/)",
+                    &small_id[..2],
+                    &big_id[..2]
+                ),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "gc removes resources of deleted notes";
+            assert_eq!(Resource::detach(&small_id, &conn), Ok(1), "{}", msg);
+            Note::delete(&"book/note".into(), &conn).unwrap();
+            assert!(Resource::gc(&conn).unwrap() >= 1, "{}", msg);
+            assert_eq!(
+                Resource::get(&big_id, &conn),
+                Err("NotFound".into()),
+                "{}",
+                msg
+            );
+        }
+        std::fs::remove_file(small).unwrap();
+        std::fs::remove_file(big).unwrap();
+    }
+}
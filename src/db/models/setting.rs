@@ -0,0 +1,38 @@
+use diesel::prelude::*;
+
+use super::schema::settings;
+use crate::db::DbConnection;
+
+#[derive(Queryable, Insertable, Identifiable, AsChangeset, Debug, PartialEq, Eq)]
+#[primary_key(key)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+impl Setting {
+    pub fn get(key: &str, conn: &DbConnection) -> Result<Option<String>, String> {
+        settings::table
+            .find(key)
+            .select(settings::value)
+            .first(conn)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn set(key: &str, value: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::replace_into(settings::table)
+            .values(&Self {
+                key: key.into(),
+                value: value.into(),
+            })
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn unset(key: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::delete(settings::table.find(key))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use diesel::prelude::*;
+
+use super::schema::sync_items;
+use crate::db::DbConnection;
+
+/// state of item at last sync with target
+#[derive(Queryable, Insertable, Debug, PartialEq, Eq)]
+pub struct SyncItem {
+    pub target: String,
+    pub item_id: String,
+    /// hash of item content
+    pub hash: String,
+    /// revision of item on target
+    pub rev: String,
+}
+
+impl SyncItem {
+    /// item id to (hash, rev) for target
+    pub fn load(
+        target: &str,
+        conn: &DbConnection,
+    ) -> Result<BTreeMap<String, (String, String)>, String> {
+        Ok(sync_items::table
+            .filter(sync_items::target.eq(target))
+            .load::<Self>(conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|item| (item.item_id, (item.hash, item.rev)))
+            .collect())
+    }
+
+    pub fn save(
+        target: &str,
+        item_id: &str,
+        hash: &str,
+        rev: &str,
+        conn: &DbConnection,
+    ) -> Result<usize, String> {
+        diesel::replace_into(sync_items::table)
+            .values(&Self {
+                target: target.into(),
+                item_id: item_id.into(),
+                hash: hash.into(),
+                rev: rev.into(),
+            })
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// forget hash, so item is seen as changed locally
+    pub fn invalidate(target: &str, item_id: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::update(sync_items::table.find((target, item_id)))
+            .set(sync_items::hash.eq(""))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn remove(target: &str, item_id: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::delete(sync_items::table.find((target, item_id)))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+}
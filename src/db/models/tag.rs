@@ -0,0 +1,70 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use diesel::prelude::*;
+
+use super::{
+    schema::{notes, tags},
+    Note,
+};
+use crate::db::DbConnection;
+
+#[derive(Queryable, Insertable, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub note_id: String,
+    pub title: String,
+}
+
+impl Tag {
+    pub fn add(note_id: &str, title: &str, conn: &DbConnection) -> Result<usize, String> {
+        diesel::insert_or_ignore_into(tags::table)
+            .values(&Self {
+                note_id: note_id.into(),
+                title: title.into(),
+            })
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn list(note: &PathBuf, conn: &DbConnection) -> Result<Vec<String>, String> {
+        Self::list_note_id(Note::query(note, conn)?.get_id(), conn)
+    }
+
+    pub fn list_note_id(note_id: &str, conn: &DbConnection) -> Result<Vec<String>, String> {
+        tags::table
+            .filter(tags::note_id.eq(note_id))
+            .select(tags::title)
+            .order(tags::title)
+            .load(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// distinct tags of all notes
+    pub fn all(conn: &DbConnection) -> Result<Vec<String>, String> {
+        tags::table
+            .select(tags::title)
+            .distinct()
+            .order(tags::title)
+            .load(conn)
+            .map_err(|e| e.to_string())
+    }
+
+    /// remove tags whose note is gone.
+    pub fn gc(conn: &DbConnection) -> Result<usize, String> {
+        let note_ids = notes::table
+            .select(notes::id)
+            .load::<Option<String>>(conn)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .flatten()
+            .collect::<HashSet<_>>();
+        let mut rows = 0;
+        for tag in tags::table.load::<Self>(conn).map_err(|e| e.to_string())? {
+            if !note_ids.contains(&tag.note_id) {
+                rows += diesel::delete(tags::table.find((&tag.note_id, &tag.title)))
+                    .execute(conn)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(rows)
+    }
+}
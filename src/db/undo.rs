@@ -0,0 +1,312 @@
+use diesel::{dsl, prelude::*};
+
+use super::{
+    schema::{operations, undo_log},
+    DbConnection, DieselStringError,
+};
+
+/// undoable operations kept, older ones are dropped.
+const MAX_OPERATIONS: i64 = 100;
+
+/// command whose changes are the `undo_log` rows between `first_seq` and
+/// `last_seq`. rows are inverse statements logged by triggers, replaying
+/// them in reverse order undoes an operation, or redoes an undone one.
+#[derive(Queryable, Debug)]
+struct Operation {
+    id: i32,
+    title: String,
+    first_seq: i32,
+    last_seq: i32,
+    undone: bool,
+}
+
+fn last_seq(conn: &DbConnection) -> Result<i32, DieselStringError> {
+    Ok(undo_log::table
+        .select(dsl::max(undo_log::seq))
+        .first::<Option<i32>>(conn)?
+        .unwrap_or(0))
+}
+
+fn clear(conn: &DbConnection) -> Result<(), DieselStringError> {
+    diesel::delete(undo_log::table).execute(conn)?;
+    diesel::delete(operations::table).execute(conn)?;
+    Ok(())
+}
+
+/// clear journal after changes made outside of it, as operations may no
+/// longer apply.
+fn drop_stale(conn: &DbConnection) -> Result<(), DieselStringError> {
+    // rows of no operation are such changes
+    let recorded = operations::table
+        .select(dsl::max(operations::last_seq))
+        .first::<Option<i32>>(conn)?
+        .unwrap_or(0);
+    if last_seq(conn)? > recorded {
+        clear(conn)?;
+    }
+    Ok(())
+}
+
+fn journal(title: &str, from: i32, to: i32, conn: &DbConnection) -> Result<(), DieselStringError> {
+    conn.transaction::<_, DieselStringError, _>(|| {
+        // a new operation can not be followed by redone ones
+        diesel::delete(operations::table.filter(operations::undone.eq(true))).execute(conn)?;
+        diesel::insert_into(operations::table)
+            .values((
+                operations::title.eq(title),
+                operations::first_seq.eq(from),
+                operations::last_seq.eq(to),
+            ))
+            .execute(conn)?;
+        let oldest_kept = operations::table
+            .select(operations::id)
+            .order(operations::id.desc())
+            .offset(MAX_OPERATIONS - 1)
+            .first::<i32>(conn)
+            .optional()?;
+        if let Some(id) = oldest_kept {
+            diesel::delete(operations::table.filter(operations::id.lt(id))).execute(conn)?;
+        }
+        conn.execute(
+            "DELETE FROM undo_log WHERE NOT EXISTS \
+             (SELECT 1 FROM operations WHERE seq BETWEEN first_seq AND last_seq)",
+        )?;
+        Ok(())
+    })
+}
+
+/// run `f`, journaling its changes as operation `title` when `undoable`.
+///
+/// changes of other commands clear the journal too, inverse statements would
+/// keep bodies before encryption.
+pub fn record<T>(
+    title: &str,
+    undoable: bool,
+    conn: &DbConnection,
+    f: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    drop_stale(conn).map_err(|e| e.0)?;
+    let before = last_seq(conn).map_err(|e| e.0)?;
+    let result = f();
+    let after = last_seq(conn).map_err(|e| e.0)?;
+    if after > before {
+        if undoable {
+            journal(title, before + 1, after, conn)
+        } else {
+            clear(conn)
+        }
+        .map_err(|e| e.0)?;
+    }
+    result
+}
+
+/// replay inverse statements of `op` in one transaction, triggers log the
+/// statements reverting the replay as new range of `op`, which is dropped
+/// when nothing is logged.
+fn replay(op: &Operation, conn: &DbConnection) -> Result<(), DieselStringError> {
+    conn.transaction::<_, DieselStringError, _>(|| {
+        let range = undo_log::seq.between(op.first_seq, op.last_seq);
+        let statements = undo_log::table
+            .filter(range)
+            .order(undo_log::seq.desc())
+            .select(undo_log::statement)
+            .load::<String>(conn)?;
+        let start = last_seq(conn)?;
+        diesel::delete(undo_log::table.filter(range)).execute(conn)?;
+        for statement in statements {
+            conn.execute(&statement)?;
+        }
+        let end = last_seq(conn)?;
+        if end == start {
+            // a replay changing nothing can not be replayed back
+            diesel::delete(operations::table.find(op.id)).execute(conn)?;
+            return Ok(());
+        }
+        diesel::update(operations::table.find(op.id))
+            .set((
+                operations::first_seq.eq(start + 1),
+                operations::last_seq.eq(end),
+                operations::undone.eq(!op.undone),
+            ))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// undo newest operation not undone.
+pub fn undo(conn: &DbConnection) -> Result<String, String> {
+    drop_stale(conn).map_err(|e| e.0)?;
+    let op = operations::table
+        .filter(operations::undone.eq(false))
+        .order(operations::id.desc())
+        .first::<Operation>(conn)
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "NothingToUndo".to_string())?;
+    replay(&op, conn).map_err(|e| e.0)?;
+    Ok(format!("undone: {}", op.title))
+}
+
+/// redo oldest undone operation.
+pub fn redo(conn: &DbConnection) -> Result<String, String> {
+    drop_stale(conn).map_err(|e| e.0)?;
+    let op = operations::table
+        .filter(operations::undone.eq(true))
+        .order(operations::id.asc())
+        .first::<Operation>(conn)
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "NothingToRedo".to_string())?;
+    replay(&op, conn).map_err(|e| e.0)?;
+    Ok(format!("redone: {}", op.title))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        commands::Command,
+        db::{
+            establish_connection,
+            models::{Folder, Note, Resource},
+        },
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let note = PathBuf::from("/book/note");
+        let moved = PathBuf::from("/other/note");
+        let mut body_file = std::env::temp_dir();
+        body_file.push(format!("{}_undo_body", structopt::clap::crate_name!()));
+        std::fs::write(&body_file, "new body").unwrap();
+        let run = |command: Command| command.run(&conn);
+
+        {
+            let msg = "nothing is undone or redone before any command";
+            assert_eq!(
+                run(Command::Undo),
+                Err("NothingToUndo".to_string()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                run(Command::Redo),
+                Err("NothingToRedo".to_string()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "undo reverts rm, mv and update in reverse order";
+            run(Command::Make {
+                parents: true,
+                template: None,
+                vars: vec![],
+                path: note.clone(),
+            })
+            .unwrap();
+            run(Command::Update {
+                note: note.clone(),
+                in_file: Some(body_file.clone()),
+            })
+            .unwrap();
+            run(Command::Move {
+                parents: true,
+                overwrite: false,
+                yes: true,
+                src: note.clone(),
+                dest_book: "/other/".into(),
+            })
+            .unwrap();
+            run(Command::Remove {
+                recursive: false,
+                yes: true,
+                path: moved.clone(),
+            })
+            .unwrap();
+            assert!(Note::cat(&moved, &conn).is_err(), "{}", msg);
+
+            assert_eq!(
+                run(Command::Undo),
+                Ok("undone: rm /other/note".to_string()),
+                "{}",
+                msg
+            );
+            assert_eq!(Note::cat(&moved, &conn), Ok("new body".into()), "{}", msg);
+            run(Command::Undo).unwrap();
+            assert_eq!(Note::cat(&note, &conn), Ok("new body".into()), "{}", msg);
+            assert!(Note::cat(&moved, &conn).is_err(), "{}", msg);
+            run(Command::Undo).unwrap();
+            assert_eq!(Note::cat(&note, &conn), Ok("".into()), "{}", msg);
+        }
+
+        {
+            let msg = "redo replays undone commands oldest first";
+            assert_eq!(
+                run(Command::Redo),
+                Ok(format!("redone: update {}", note.display())),
+                "{}",
+                msg
+            );
+            run(Command::Redo).unwrap();
+            assert_eq!(Note::cat(&moved, &conn), Ok("new body".into()), "{}", msg);
+        }
+
+        {
+            let msg = "a new command drops undone ones";
+            run(Command::Copy {
+                parents: false,
+                overwrite: false,
+                yes: false,
+                src: moved.clone(),
+                dest_book: "/book/".into(),
+            })
+            .unwrap();
+            assert_eq!(
+                run(Command::Redo),
+                Err("NothingToRedo".to_string()),
+                "{}",
+                msg
+            );
+            run(Command::Undo).unwrap();
+            assert!(Note::cat(&note, &conn).is_err(), "{}", msg);
+        }
+
+        {
+            let msg = "gc keeps attachment files undo can restore";
+            let data = vec![b'x'; 100 * 1024];
+            std::fs::write(&body_file, &data).unwrap();
+            run(Command::Attach {
+                note: moved.clone(),
+                file: body_file.clone(),
+            })
+            .unwrap();
+            let id = Resource::list(&moved, &conn).unwrap()[0]
+                .get_id()
+                .to_string();
+            run(Command::Detach {
+                resource_id: id.clone(),
+            })
+            .unwrap();
+            run(Command::Gc).unwrap();
+            run(Command::Undo).unwrap();
+            let restored = Resource::get(&id, &conn).and_then(|r| r.read_data());
+            assert!(restored == Ok(data), "{}: {:?}", msg, restored.err());
+        }
+
+        {
+            let msg = "changes outside of journal clear it";
+            Folder::make(&"/outside/".into(), false, &conn).unwrap();
+            assert_eq!(
+                run(Command::Undo),
+                Err("NothingToUndo".to_string()),
+                "{}",
+                msg
+            );
+        }
+        std::fs::remove_file(body_file).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+use super::unescape;
+use crate::{
+    commands::resolve_path,
+    db::{
+        models::{Folder, Note, Resource},
+        DbConnection,
+    },
+};
+
+const RESOURCE_DIR: &str = "_resources";
+const INDEX: &str = "index.html";
+const STYLE: &str = "body{max-width:48em;margin:2em auto;padding:0 1em;\
+font-family:sans-serif;line-height:1.5;color:#222}\
+nav{font-size:.9em;margin-bottom:1em}\
+a{color:#0366d6}\
+pre,code{background:#f6f8fa;border-radius:3px}\
+pre{padding:.8em;overflow:auto}\
+code{padding:.1em .3em}\
+pre code{padding:0}\
+blockquote{margin:0;padding-left:1em;border-left:3px solid #ddd;color:#555}\
+table{border-collapse:collapse}\
+th,td{border:1px solid #ddd;padding:.3em .6em}\
+img{max-width:100%}";
+
+struct HtmlExport<'a> {
+    root: PathBuf,
+    /// note path in database to html file relative to root.
+    notes: HashMap<PathBuf, PathBuf>,
+    /// book path in database to directory relative to root.
+    books: HashMap<PathBuf, PathBuf>,
+    extracted: HashSet<String>,
+    conn: &'a DbConnection,
+}
+
+/// `path` without trailing `/`, as used for map keys.
+fn key(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().trim_end_matches('/'))
+}
+
+/// map key of note `title` in `book`.
+fn note_key(book: &Path, title: &str) -> PathBuf {
+    key(&Path::new("/").join(book).join(title))
+}
+
+/// link from file in `dir` to `target`, both relative to export root.
+fn relative(dir: &Path, target: &Path) -> String {
+    let dir = dir.components().collect::<Vec<_>>();
+    let target = target.components().collect::<Vec<_>>();
+    let common = dir
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut link = "../".repeat(dir.len() - common);
+    let rest = target[common..]
+        .iter()
+        .map(|c| encode(&c.as_os_str().to_string_lossy()))
+        .collect::<Vec<_>>();
+    link.push_str(&rest.join("/"));
+    link
+}
+
+/// path segment `title` percent-encoded for href.
+fn encode(title: &str) -> String {
+    title
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `<title><extension>`, suffixed with start of id when name is taken.
+fn unique(title: &str, id: &str, extension: &str, taken: &mut HashSet<String>) -> String {
+    let mut name = format!("{}{}", title, extension);
+    if taken.contains(&name) {
+        name = format!("{} ({}){}", title, id.get(..8).unwrap_or(id), extension);
+    }
+    taken.insert(name.clone());
+    name
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn page(title: &str, nav: &str, content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<nav>{}</nav>\n<main>\n{}</main>\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        nav,
+        content
+    )
+}
+
+impl<'a> HtmlExport<'a> {
+    /// fill note and book maps for book at `path` written to `dir`.
+    fn collect(&mut self, path: &PathBuf, dir: &Path) -> Result<(), String> {
+        self.books.insert(key(path), dir.to_path_buf());
+        let (folders, notes) = Folder::list(path, self.conn)?;
+        // generated index and resource dir at export root are taken
+        let mut taken = HashSet::new();
+        taken.insert(INDEX.to_string());
+        if dir.as_os_str().is_empty() {
+            taken.insert(RESOURCE_DIR.to_string());
+        }
+        for folder in folders {
+            let name = unique(&folder.title, folder.get_id(), "", &mut taken);
+            self.collect(&path.join(&folder.title), &dir.join(name))?;
+        }
+        for note in notes {
+            let name = unique(&note.title, note.get_id(), ".html", &mut taken);
+            self.notes
+                .insert(note_key(path, &note.title), dir.join(name));
+        }
+        Ok(())
+    }
+
+    /// link target of `dest` in note of `book` written to `dir`, None keeps it.
+    fn link(&mut self, dest: &str, book: &Path, dir: &Path) -> Result<Option<String>, String> {
+        if let Some(id) = dest.strip_prefix(":/") {
+            return self.resource(id, dir).map(Some);
+        }
+        if dest.is_empty() || dest.starts_with('#') || dest.contains(':') {
+            return Ok(None);
+        }
+        let (target, fragment) = match dest.split_once('#') {
+            Some((target, fragment)) => (target, format!("#{}", fragment)),
+            None => (dest, String::new()),
+        };
+        let target = unescape(target);
+        let target = resolve_path(book, Path::new(target.trim_end_matches(".md")));
+        let file = match self.notes.get(&key(&target)) {
+            Some(file) => file.clone(),
+            None => match self.books.get(&key(&target)) {
+                Some(dir) => dir.join(INDEX),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(format!("{}{}", relative(dir, &file), fragment)))
+    }
+
+    /// resource `id` extracted once into resource dir, link from `dir`.
+    fn resource(&mut self, id: &str, dir: &Path) -> Result<String, String> {
+        let resource = Resource::get(id, self.conn).map_err(|e| format!(":/{} {}", id, e))?;
+        let name = resource.export_name();
+        if !self.extracted.contains(id) {
+            let path = self.root.join(RESOURCE_DIR);
+            fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            fs::write(path.join(&name), resource.read_data()?).map_err(|e| e.to_string())?;
+            self.extracted.insert(id.to_string());
+        }
+        Ok(relative(dir, &Path::new(RESOURCE_DIR).join(name)))
+    }
+
+    fn note(&mut self, note: &Note, book: &Path, dir: &Path) -> Result<(), String> {
+        let body = note.read_body()?;
+        let mut events = vec![];
+        for event in Parser::new_ext(&body, Options::all()) {
+            let event = match event {
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => {
+                    let dest_url = self
+                        .link(&dest_url, book, dir)
+                        .map_err(|e| format!("{}: {}", note.title, e))?
+                        .map(CowStr::from)
+                        .unwrap_or(dest_url);
+                    Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    })
+                }
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => {
+                    let dest_url = self
+                        .link(&dest_url, book, dir)
+                        .map_err(|e| format!("{}: {}", note.title, e))?
+                        .map(CowStr::from)
+                        .unwrap_or(dest_url);
+                    Event::Start(Tag::Image {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    })
+                }
+                event => event,
+            };
+            events.push(event);
+        }
+        let mut content = String::new();
+        html::push_html(&mut content, events.into_iter());
+        let nav = if self.books.contains_key(book) {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                INDEX,
+                escape(&book.to_string_lossy())
+            )
+        } else {
+            String::new()
+        };
+        let file = self
+            .notes
+            .get(&note_key(book, &note.title))
+            .ok_or_else(|| "NotFound".to_string())?;
+        fs::write(self.root.join(file), page(&note.title, &nav, &content))
+            .map_err(|e| e.to_string())
+    }
+
+    fn book(&mut self, path: &PathBuf, dir: &Path, top: bool) -> Result<usize, String> {
+        fs::create_dir_all(self.root.join(dir)).map_err(|e| e.to_string())?;
+        let (folders, notes) = Folder::list(path, self.conn)?;
+        let book = key(path);
+        let mut content = String::from("<ul>\n");
+        for folder in &folders {
+            content.push_str(&format!(
+                "<li><a href=\"{}\">{}/</a></li>\n",
+                relative(
+                    dir,
+                    &self.books[&key(&path.join(&folder.title))].join(INDEX)
+                ),
+                escape(&folder.title)
+            ));
+        }
+        for note in &notes {
+            content.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                relative(dir, &self.notes[&note_key(path, &note.title)]),
+                escape(&note.title)
+            ));
+        }
+        content.push_str("</ul>\n");
+        let title = key(path).to_string_lossy().to_string();
+        let title = if title.is_empty() { "/".into() } else { title };
+        let nav = if top {
+            String::new()
+        } else {
+            format!("<a href=\"../{}\">..</a>", INDEX)
+        };
+        fs::write(
+            self.root.join(dir).join(INDEX),
+            page(
+                &title,
+                &nav,
+                &format!("<h1>{}</h1>\n{}", escape(&title), content),
+            ),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut count = 0;
+        for note in notes {
+            self.note(&note, &book, dir)?;
+            count += 1;
+        }
+        for folder in folders {
+            let dir = self.books[&key(&path.join(&folder.title))].clone();
+            count += self.book(&path.join(&folder.title), &dir, false)?;
+        }
+        Ok(count)
+    }
+}
+
+pub fn export(path: &PathBuf, dir: &PathBuf, conn: &DbConnection) -> Result<usize, String> {
+    let mut export = HtmlExport {
+        root: dir.clone(),
+        notes: HashMap::new(),
+        books: HashMap::new(),
+        extracted: HashSet::new(),
+        conn,
+    };
+    if path.to_string_lossy().ends_with('/') {
+        let mut title = path
+            .file_name()
+            .map(|p| PathBuf::from(p.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        if title == Path::new(RESOURCE_DIR) {
+            let id = Folder::get(path, conn)?.get_id().to_string();
+            let mut taken = HashSet::new();
+            taken.insert(RESOURCE_DIR.to_string());
+            title = PathBuf::from(unique(RESOURCE_DIR, &id, "", &mut taken));
+        }
+        export.collect(path, &title)?;
+        export.book(path, &title, true)
+    } else {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let note = Note::query(path, conn)?;
+        let book = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        export.notes.insert(
+            note_key(&book, &note.title),
+            PathBuf::from(format!("{}.html", note.title)),
+        );
+        export.note(&note, &book, Path::new(""))?;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::export;
+    use crate::db::{
+        establish_connection,
+        models::{Folder, Note, Resource},
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let dir = std::env::temp_dir().join(format!("md_cli_html_{}", uuid::Uuid::new_v4()));
+        Folder::make(&"/site/guide".into(), true, &conn).unwrap();
+        Note::make(&"/site/home".into(), false, &conn).unwrap();
+        Note::update(
+            &"/site/home".into(),
+            "# Home\n\nsee [setup](guide/set%20up), [faq](q&a%20%231%3F) and [web](https://example.com)"
+                .into(),
+            &conn,
+        )
+        .unwrap();
+        Note::make(&"/site/q&a #1?".into(), false, &conn).unwrap();
+        Note::make(&"/site/guide/set up".into(), false, &conn).unwrap();
+        Note::update(
+            &"/site/guide/set up".into(),
+            "back [home](/site/home#top), [missing](nothing)".into(),
+            &conn,
+        )
+        .unwrap();
+
+        {
+            let msg = "notes and index pages are written";
+            assert_eq!(export(&"/site/".into(), &dir, &conn), Ok(3), "{}", msg);
+            assert!(dir.join("site/index.html").exists(), "{}", msg);
+            assert!(dir.join("site/guide/index.html").exists(), "{}", msg);
+            let index = fs::read_to_string(dir.join("site/index.html")).unwrap();
+            assert!(index.contains("href=\"guide/index.html\""), "{}", msg);
+            assert!(index.contains("href=\"home.html\""), "{}", msg);
+        }
+
+        {
+            let msg = "titles are percent-encoded in links and escaped in text";
+            let index = fs::read_to_string(dir.join("site/index.html")).unwrap();
+            assert!(
+                index.contains("<a href=\"q%26a%20%231%3F.html\">q&amp;a #1?</a>"),
+                "{}",
+                msg
+            );
+            let home = fs::read_to_string(dir.join("site/home.html")).unwrap();
+            assert!(home.contains("href=\"q%26a%20%231%3F.html\""), "{}", msg);
+        }
+
+        {
+            let msg = "internal links are relative html links";
+            let home = fs::read_to_string(dir.join("site/home.html")).unwrap();
+            assert!(home.contains("<h1>Home</h1>"), "{}", msg);
+            assert!(home.contains("href=\"guide/set%20up.html\""), "{}", msg);
+            assert!(home.contains("href=\"https://example.com\""), "{}", msg);
+            let setup = fs::read_to_string(dir.join("site/guide/set up.html")).unwrap();
+            assert!(setup.contains("href=\"../home.html#top\""), "{}", msg);
+            assert!(setup.contains("href=\"nothing\""), "{}", msg);
+        }
+
+        {
+            let msg = "titles clashing with index and resource dir get distinct files";
+            let index = Note::make(&"/site/index".into(), false, &conn).unwrap();
+            Note::update(&"/site/index".into(), "[home](home)".into(), &conn).unwrap();
+            let book = Folder::make(&"/_resources".into(), false, &conn).unwrap();
+            Note::make(&"/_resources/kept".into(), false, &conn).unwrap();
+            let file = dir.with_extension("txt");
+            fs::write(&file, "attached").unwrap();
+            let resource = Resource::attach(&"/_resources/kept".into(), &file, &conn).unwrap();
+            let body = format!("[file](:/{})", resource.get_id());
+            Note::update(&"/_resources/kept".into(), body, &conn).unwrap();
+            let root = dir.join("root");
+            export(&"/".into(), &root, &conn).unwrap();
+
+            let name = format!("index ({}).html", &index.get_id()[..8]);
+            let site = fs::read_to_string(root.join("site/index.html")).unwrap();
+            assert!(site.contains("<h1>/site</h1>"), "{}", msg);
+            assert!(
+                site.contains(&format!(
+                    "href=\"index%20%28{}%29.html\"",
+                    &index.get_id()[..8]
+                )),
+                "{}",
+                msg
+            );
+            let note = fs::read_to_string(root.join("site").join(name)).unwrap();
+            assert!(note.contains("href=\"home.html\""), "{}", msg);
+            let book = root.join(format!("_resources ({})", &book.get_id()[..8]));
+            assert!(book.join("kept.html").exists(), "{}", msg);
+            assert!(
+                root.join("_resources")
+                    .join(resource.export_name())
+                    .exists(),
+                "{}",
+                msg
+            );
+            fs::remove_file(&file).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
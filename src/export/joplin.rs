@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::db::{
+    models::{Folder, Note, Resource, Tag},
+    DbConnection,
+};
+
+const NOTE: &str = "1";
+const FOLDER: &str = "2";
+const RESOURCE: &str = "4";
+const TAG: &str = "5";
+const NOTE_TAG: &str = "6";
+const RESOURCE_DIR: &str = "resources";
+/// title of notebook holding notes of root, Joplin notes need a notebook
+const ROOT_TITLE: &str = "root";
+
+/// Joplin id of md_cli id, an uuid without hyphens.
+fn joplin_id(id: &str) -> String {
+    id.replace('-', "")
+}
+
+/// stable Joplin id for items md_cli has no id for.
+fn derived_id(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))[..32].to_string()
+}
+
+struct JoplinExport<'a> {
+    dir: PathBuf,
+    /// created and updated time of all items, md_cli keeps no times
+    time: String,
+    conn: &'a DbConnection,
+}
+
+impl<'a> JoplinExport<'a> {
+    /// write RAW item, title and body followed by `key: value` properties.
+    fn write(
+        &self,
+        id: &str,
+        title: &str,
+        body: Option<&str>,
+        props: &[(&str, &str)],
+        kind: &str,
+    ) -> Result<(), String> {
+        let mut item = title.to_string();
+        if let Some(body) = body {
+            item.push_str("\n\n");
+            item.push_str(body);
+        }
+        item.push_str(&format!("\n\nid: {}\n", id));
+        for (key, value) in props {
+            item.push_str(&format!("{}: {}\n", key, value));
+        }
+        for key in [
+            "created_time",
+            "updated_time",
+            "user_created_time",
+            "user_updated_time",
+        ] {
+            item.push_str(&format!("{}: {}\n", key, self.time));
+        }
+        item.push_str(&format!("encryption_applied: 0\ntype_: {}", kind));
+        fs::write(self.dir.join(format!("{}.md", id)), item).map_err(|e| e.to_string())
+    }
+
+    fn folder(&self, folder: &Folder, parent_id: &str) -> Result<(), String> {
+        self.write(
+            &joplin_id(folder.get_id()),
+            &folder.title,
+            None,
+            &[("parent_id", &joplin_id(parent_id))],
+            FOLDER,
+        )
+    }
+
+    /// synthetic top notebook for notes of root, returns its id.
+    fn root(&self) -> Result<String, String> {
+        let id = derived_id("/");
+        self.write(&id, ROOT_TITLE, None, &[("parent_id", "")], FOLDER)?;
+        Ok(id)
+    }
+
+    fn note(&self, note: &Note) -> Result<(), String> {
+        let id = joplin_id(note.get_id());
+        let parent_id = match note.get_book_id() {
+            "" => self.root()?,
+            book_id => joplin_id(book_id),
+        };
+        let body = Resource::replace_references(&note.read_body()?, |id| {
+            Some(format!(":/{}", joplin_id(id)))
+        });
+        self.write(
+            &id,
+            &note.title,
+            Some(&body),
+            &[
+                ("parent_id", &parent_id),
+                ("is_todo", "0"),
+                ("markup_language", "1"),
+            ],
+            NOTE,
+        )?;
+        for resource in Resource::list_note_id(note.get_id(), self.conn)? {
+            let resource_id = joplin_id(resource.get_id());
+            let name = resource.export_name();
+            let extension = name
+                .rsplit_once('.')
+                .map(|(_, ext)| ext)
+                .unwrap_or_default();
+            fs::write(
+                self.dir
+                    .join(RESOURCE_DIR)
+                    .join(name.replacen(resource.get_id(), &resource_id, 1)),
+                resource.read_data()?,
+            )
+            .map_err(|e| e.to_string())?;
+            self.write(
+                &resource_id,
+                &resource.title,
+                None,
+                &[
+                    ("mime", &resource.mime),
+                    ("filename", ""),
+                    ("file_extension", extension),
+                    ("size", &resource.size.to_string()),
+                ],
+                RESOURCE,
+            )?;
+        }
+        for tag in Tag::list_note_id(note.get_id(), self.conn)? {
+            let tag_id = derived_id(&tag);
+            self.write(&tag_id, &tag, None, &[], TAG)?;
+            self.write(
+                &derived_id(&format!("{}{}", id, tag)),
+                "",
+                None,
+                &[("note_id", &id), ("tag_id", &tag_id)],
+                NOTE_TAG,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// write items under book at path, returns number of notes written.
+    fn book(&self, path: &PathBuf) -> Result<usize, String> {
+        let (folders, notes) = Folder::list(path, self.conn)?;
+        let mut count = 0;
+        for note in notes {
+            self.note(&note)?;
+            count += 1;
+        }
+        for folder in folders {
+            self.folder(&folder, folder.get_parent_id())?;
+            count += self.book(&path.join(&folder.title))?;
+        }
+        Ok(count)
+    }
+}
+
+pub fn export(path: &PathBuf, dir: &Path, conn: &DbConnection) -> Result<usize, String> {
+    fs::create_dir_all(dir.join(RESOURCE_DIR)).map_err(|e| e.to_string())?;
+    let export = JoplinExport {
+        dir: dir.to_path_buf(),
+        time: chrono::Utc::now()
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string(),
+        conn,
+    };
+    if path.to_string_lossy().ends_with('/') {
+        // exported book is a top notebook in Joplin
+        if let Ok(book) = Folder::get(path, conn) {
+            export.folder(&book, "")?;
+        }
+        export.book(path)
+    } else {
+        let note = Note::query(path, conn)?;
+        let book = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        if book.file_name().is_some() {
+            export.folder(&Folder::get(&book, conn)?, "")?;
+        }
+        export.note(&note)?;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::export;
+    use crate::{
+        db::{
+            establish_connection,
+            models::{Folder, Note, Resource, Tag},
+        },
+        import::{import, Source},
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let dir = std::env::temp_dir().join(format!("md_cli_raw_{}", uuid::Uuid::new_v4()));
+        let file = dir.with_extension("txt");
+        fs::write(&file, "attached").unwrap();
+        Folder::make(&"/team/specs".into(), true, &conn).unwrap();
+        let note = Note::make(&"/team/specs/api".into(), false, &conn).unwrap();
+        let resource = Resource::attach(&"/team/specs/api".into(), &file, &conn).unwrap();
+        let body = format!("# Api\n\n[spec](:/{})\n", resource.get_id());
+        Note::update(&"/team/specs/api".into(), body.clone(), &conn).unwrap();
+        Tag::add(note.get_id(), "draft", &conn).unwrap();
+        Note::make(&"/team/todo".into(), false, &conn).unwrap();
+        Note::make(&"/other/skipped".into(), true, &conn).unwrap();
+
+        {
+            let msg = "book is written as RAW items";
+            assert_eq!(export(&"/team/".into(), &dir, &conn), Ok(2), "{}", msg);
+            let id = note.get_id().replace('-', "");
+            let item = fs::read_to_string(dir.join(format!("{}.md", id))).unwrap();
+            assert!(item.starts_with("api\n\n# Api\n"), "{}", msg);
+            assert!(item.ends_with("type_: 1"), "{}", msg);
+            assert_eq!(
+                fs::read_dir(dir.join("resources")).unwrap().count(),
+                1,
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "Joplin import of export gives same books, notes and attachments";
+            let copy = establish_connection().expect("connection or migration failed!");
+            let report = import(Source::Joplin, &dir, &copy).unwrap();
+            assert!(report.skipped.is_empty(), "{}: {:?}", msg, report.skipped);
+            assert_eq!(
+                (report.books, report.notes, report.resources, report.tags),
+                (2, 2, 1, 1),
+                "{}",
+                msg
+            );
+            let api = "/team/specs/api".into();
+            assert_eq!(
+                Note::query(&api, &copy).unwrap().get_id(),
+                note.get_id(),
+                "{}",
+                msg
+            );
+            assert_eq!(Note::cat(&api, &copy), Ok(body), "{}", msg);
+            let resources = Resource::list(&api, &copy).unwrap();
+            assert_eq!(resources[0].get_id(), resource.get_id(), "{}", msg);
+            assert_eq!(
+                resources[0].read_data(),
+                Ok(b"attached".to_vec()),
+                "{}",
+                msg
+            );
+            assert_eq!(Tag::list(&api, &copy), Ok(vec!["draft".into()]), "{}", msg);
+            assert!(Note::query(&"/team/todo".into(), &copy).is_ok(), "{}", msg);
+            assert!(Folder::get(&"/other".into(), &copy).is_err(), "{}", msg);
+        }
+
+        {
+            let msg = "notes of root are exported into a root notebook";
+            let dir = dir.with_extension("root");
+            Note::from_raw(
+                uuid::Uuid::new_v4().to_string(),
+                String::new(),
+                "loose".into(),
+                String::new(),
+            )
+            .save_raw(&conn)
+            .unwrap();
+            assert_eq!(export(&"/".into(), &dir, &conn), Ok(4), "{}", msg);
+            let copy = establish_connection().expect("connection or migration failed!");
+            let report = import(Source::Joplin, &dir, &copy).unwrap();
+            assert!(report.skipped.is_empty(), "{}: {:?}", msg, report.skipped);
+            assert!(Note::query(&"/root/loose".into(), &copy).is_ok(), "{}", msg);
+            assert!(Note::query(&"/team/todo".into(), &copy).is_ok(), "{}", msg);
+            assert!(
+                Note::query(&"/other/skipped".into(), &copy).is_ok(),
+                "{}",
+                msg
+            );
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::db::{
+    models::{Folder, Note, Resource},
+    DbConnection,
+};
+
+const RESOURCE_DIR: &str = "_resources";
+
+struct MarkdownExport<'a> {
+    root: PathBuf,
+    extracted: HashSet<String>,
+    conn: &'a DbConnection,
+}
+
+impl<'a> MarkdownExport<'a> {
+    fn note(&mut self, note: &Note, dir: &Path, depth: usize) -> Result<(), String> {
+        let mut missing = None;
+        let body = Resource::replace_references(&note.read_body()?, |id| {
+            let resource = match Resource::get(id, self.conn) {
+                Ok(resource) => resource,
+                Err(e) => {
+                    missing = Some(format!("{}: :/{} {}", note.title, id, e));
+                    return None;
+                }
+            };
+            let name = resource.export_name();
+            if !self.extracted.contains(id) {
+                let mut path = self.root.join(RESOURCE_DIR);
+                if let Err(e) = fs::create_dir_all(&path) {
+                    missing = Some(e.to_string());
+                    return None;
+                }
+                path.push(&name);
+                if let Err(e) = resource
+                    .read_data()
+                    .and_then(|data| fs::write(&path, data).map_err(|e| e.to_string()))
+                {
+                    missing = Some(e);
+                    return None;
+                }
+                self.extracted.insert(id.to_string());
+            }
+            Some(format!("{}{}/{}", "../".repeat(depth), RESOURCE_DIR, name))
+        });
+        if let Some(err) = missing {
+            return Err(err);
+        }
+        fs::write(dir.join(format!("{}.md", note.title)), body).map_err(|e| e.to_string())
+    }
+
+    fn book(&mut self, path: &PathBuf, dir: &Path, depth: usize) -> Result<usize, String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let (folders, notes) = Folder::list(path, self.conn)?;
+        let mut count = 0;
+        for note in notes {
+            self.note(&note, dir, depth)?;
+            count += 1;
+        }
+        for folder in folders {
+            count += self.book(
+                &path.join(&folder.title),
+                &dir.join(&folder.title),
+                depth + 1,
+            )?;
+        }
+        Ok(count)
+    }
+}
+
+pub fn export(path: &PathBuf, dir: &PathBuf, conn: &DbConnection) -> Result<usize, String> {
+    let mut export = MarkdownExport {
+        root: dir.clone(),
+        extracted: HashSet::new(),
+        conn,
+    };
+    if path.to_string_lossy().ends_with('/') {
+        let title = path
+            .file_name()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let depth = if title.is_empty() { 0 } else { 1 };
+        export.book(path, &dir.join(title), depth)
+    } else {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        export.note(&Note::query(path, conn)?, dir, 0)?;
+        Ok(1)
+    }
+}
@@ -0,0 +1,66 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::db::DbConnection;
+
+mod html;
+mod joplin;
+mod markdown;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Joplin,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "joplin" => Ok(Self::Joplin),
+            _ => Err(format!("UnknownFormat: {}", s)),
+        }
+    }
+}
+
+/// decode `%XX` escapes of link destination.
+pub(crate) fn unescape(dest: &str) -> String {
+    let bytes = dest.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// export book (path ends with /) or note into dir, returns number of notes written.
+pub fn export(
+    format: Format,
+    path: &PathBuf,
+    dir: &PathBuf,
+    conn: &DbConnection,
+) -> Result<usize, String> {
+    match format {
+        Format::Markdown => markdown::export(path, dir, conn),
+        Format::Html => html::export(path, dir, conn),
+        Format::Joplin => joplin::export(path, dir, conn),
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use crate::db::{models::Folder, DbConnection};
+
+/// path matching a pattern, indices are of matched chars in path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub path: PathBuf,
+    pub score: i64,
+    pub indices: Vec<usize>,
+}
+
+/// all book (ending with `/`) and note paths.
+pub fn paths(conn: &DbConnection) -> Result<Vec<PathBuf>, String> {
+    Folder::glob(Path::new("/**"), conn)
+}
+
+/// best score of `pattern` chars found in order in `text`, ignoring case.
+///
+/// consecutive chars, chars starting a word and chars in the last title
+/// score higher, gaps and long texts lower.
+pub fn score(pattern: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    const NONE: i64 = i64::MIN / 2;
+    let text = text.chars().collect::<Vec<_>>();
+    let pattern = pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return Some((-(text.len() as i64), vec![]));
+    }
+    let last_title = text
+        .iter()
+        .take(text.len().saturating_sub(1))
+        .rposition(|c| *c == '/')
+        .map_or(0, |i| i + 1);
+    let bonus = |j: usize| {
+        let mut bonus = 1;
+        if j == 0 || matches!(text[j - 1], '/' | ' ' | '-' | '_' | '.') {
+            bonus += 4;
+        }
+        if j >= last_title {
+            bonus += 2;
+        }
+        bonus
+    };
+    // best score with pattern char i at text char j, and where char i - 1 was
+    let mut scores = vec![NONE; text.len()];
+    let mut from = vec![vec![0; text.len()]; pattern.len()];
+    for (i, p) in pattern.iter().enumerate() {
+        let mut row = vec![NONE; text.len()];
+        // best of previous row far enough for the gap penalty to be capped
+        let mut far = (NONE, 0);
+        for j in 0..text.len() {
+            if j >= 6 && scores[j - 6] > far.0 {
+                far = (scores[j - 6], j - 6);
+            }
+            if !text[j].to_lowercase().eq(Some(*p)) {
+                continue;
+            }
+            let best = if i == 0 {
+                (0, 0)
+            } else {
+                let near = (j.saturating_sub(5)..j).map(|k| match j - k - 1 {
+                    0 => (scores[k] + 5, k),
+                    gap => (scores[k] - gap as i64, k),
+                });
+                near.chain(Some((far.0 - 5, far.1)))
+                    .max_by_key(|(score, _)| *score)
+                    .unwrap_or((NONE, 0))
+            };
+            if best.0 > NONE / 2 {
+                row[j] = best.0 + bonus(j);
+                from[i][j] = best.1;
+            }
+        }
+        scores = row;
+    }
+    let (mut j, score) = scores
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, score)| **score)
+        .map(|(j, score)| (j, *score))
+        .filter(|(_, score)| *score > NONE / 2)?;
+    let mut indices = vec![0; pattern.len()];
+    for i in (0..pattern.len()).rev() {
+        indices[i] = j;
+        j = from[i][j];
+    }
+    Some((score * 10 - text.len() as i64, indices))
+}
+
+/// paths matching pattern, best first.
+pub fn rank(pattern: &str, paths: &[PathBuf]) -> Vec<Match> {
+    let mut matches = paths
+        .iter()
+        .filter_map(|path| {
+            let (score, indices) = score(pattern, &path.to_string_lossy())?;
+            Some(Match {
+                path: path.clone(),
+                score,
+                indices,
+            })
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    matches
+}
+
+pub fn find(pattern: &str, conn: &DbConnection) -> Result<Vec<Match>, String> {
+    Ok(rank(pattern, &paths(conn)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{find, rank, score};
+    use crate::db::{establish_connection, models::Note};
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+
+        {
+            let msg = "chars must be found in order";
+            assert_eq!(score("ba", "/abc"), None, "{}", msg);
+            assert_eq!(
+                score("AC", "/abc").map(|s| s.1),
+                Some(vec![1, 3]),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "word starts and last title rank first";
+            let paths = [
+                "/notes/roadmap",
+                "/archive/random",
+                "/work/road map",
+                "/work/",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+            let ranked = rank("rm", &paths)
+                .into_iter()
+                .map(|m| m.path.to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                ranked,
+                vec!["/work/road map", "/notes/roadmap", "/archive/random"],
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "books and notes of database are found";
+            Note::make(&"/work/plans/roadmap".into(), true, &conn).unwrap();
+            let found = find("plan", &conn)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.path)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                found,
+                vec![PathBuf::from("/work/plans/"), "/work/plans/roadmap".into()],
+                "{}",
+                msg
+            );
+        }
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+use super::{link, transaction, ImportReport};
+use crate::db::{
+    crypto,
+    models::{Folder, Note, Resource, Tag},
+    DbConnection,
+};
+
+const NOTE: &str = "1";
+const FOLDER: &str = "2";
+const RESOURCE: &str = "4";
+const TAG: &str = "5";
+const NOTE_TAG: &str = "6";
+const RESOURCE_DIR: &str = "resources";
+
+/// Joplin RAW item, title and body followed by `key: value` properties.
+pub(crate) struct Item {
+    pub title: String,
+    pub body: String,
+    pub props: HashMap<String, String>,
+}
+
+impl Item {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lines = text.lines().collect::<Vec<_>>();
+        let mut props = HashMap::new();
+        let mut end = lines.len();
+        while end > 0 {
+            let line = lines[end - 1].trim();
+            end -= 1;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("InvalidProperty: {}", line))?;
+            props.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        let title = lines.first().filter(|_| end > 0).copied().unwrap_or("");
+        let body = lines.get(2..end).unwrap_or_default().join("\n");
+        Ok(Self {
+            title: title.to_string(),
+            body,
+            props,
+        })
+    }
+
+    pub fn prop(&self, key: &str) -> &str {
+        self.props.get(key).map(String::as_str).unwrap_or("")
+    }
+}
+
+/// md_cli id of Joplin id, which is an uuid without hyphens.
+pub(crate) fn from_joplin_id(id: &str) -> Result<String, String> {
+    if id.is_empty() {
+        return Ok(String::new());
+    }
+    Uuid::parse_str(id)
+        .map(|id| id.to_hyphenated().to_string())
+        .map_err(|_| format!("InvalidId: {}", id))
+}
+
+/// files of a tar archive by name.
+fn untar(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let mut files = BTreeMap::new();
+    let mut offset = 0;
+    while offset + 512 <= data.len() {
+        let header = &data[offset..offset + 512];
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let field = |start: usize, end: usize| {
+            let field = &header[start..end];
+            let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).trim().to_string()
+        };
+        let mut name = field(0, 100);
+        let prefix = field(345, 500);
+        if &header[257..262] == b"ustar" && !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+        let size = usize::from_str_radix(&field(124, 136), 8).map_err(|_| "InvalidTar")?;
+        let start = offset + 512;
+        let end = start + size;
+        if end > data.len() {
+            return Err("InvalidTar".into());
+        }
+        if matches!(header[156], b'0' | 0) {
+            files.insert(
+                name.trim_start_matches("./").to_string(),
+                data[start..end].to_vec(),
+            );
+        }
+        offset = start + size.div_ceil(512) * 512;
+    }
+    Ok(files)
+}
+
+/// files of RAW export directory or `.jex` archive by name, resources under `resources/`.
+fn files(path: &Path) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    if !path.is_dir() {
+        return untar(&fs::read(path).map_err(|e| e.to_string())?);
+    }
+    let mut files = BTreeMap::new();
+    for (dir, prefix) in [
+        (path.to_path_buf(), ""),
+        (path.join(RESOURCE_DIR), "resources/"),
+    ] {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            if entry.path().is_file() {
+                files.insert(
+                    format!("{}{}", prefix, entry.file_name().to_string_lossy()),
+                    fs::read(entry.path()).map_err(|e| e.to_string())?,
+                );
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// title usable as part of a path.
+fn title(title: &str) -> String {
+    title.replace('/', "-")
+}
+
+/// import Joplin export at path in one transaction, books and notes changed
+/// locally since an earlier import are kept and reported.
+pub fn import(path: &Path, conn: &DbConnection) -> Result<ImportReport, String> {
+    let files = files(path)?;
+    transaction(conn, || import_files(&files, conn))
+}
+
+fn import_files(
+    files: &BTreeMap<String, Vec<u8>>,
+    conn: &DbConnection,
+) -> Result<ImportReport, String> {
+    let mut report = ImportReport::default();
+    let mut items: BTreeMap<&str, Vec<(String, Item)>> = BTreeMap::new();
+    for (name, data) in files {
+        if name.contains('/') || !name.ends_with(".md") {
+            continue;
+        }
+        let item = match std::str::from_utf8(data)
+            .map_err(|e| e.to_string())
+            .and_then(Item::parse)
+        {
+            Ok(item) => item,
+            Err(e) => {
+                report.skipped.push(format!("{}: {}", name, e));
+                continue;
+            }
+        };
+        if item.prop("encryption_applied") == "1" {
+            report
+                .skipped
+                .push(format!("{}: encrypted in Joplin", name));
+            continue;
+        }
+        let kind = match item.prop("type_") {
+            NOTE => NOTE,
+            FOLDER => FOLDER,
+            RESOURCE => RESOURCE,
+            TAG => TAG,
+            NOTE_TAG => NOTE_TAG,
+            kind => {
+                report
+                    .skipped
+                    .push(format!("{}: unsupported item type {}", name, kind));
+                continue;
+            }
+        };
+        items.entry(kind).or_default().push((name.clone(), item));
+    }
+    let take = |items: &mut BTreeMap<&str, Vec<(String, Item)>>, kind| {
+        items.remove(kind).unwrap_or_default()
+    };
+
+    // books first, so notes are sealed with keys of their books
+    let folders = take(&mut items, FOLDER);
+    let folder_ids = folders
+        .iter()
+        .map(|(_, item)| item.prop("id").to_string())
+        .collect::<HashSet<_>>();
+    let mut local_folders = Folder::all(conn)?
+        .into_iter()
+        .map(|folder| (folder.get_id().to_string(), folder))
+        .collect::<HashMap<_, _>>();
+    let mut saved_folders = HashMap::new();
+    for (name, item) in &folders {
+        let parent_id = match item.prop("parent_id") {
+            id if folder_ids.contains(id) => id,
+            "" => "",
+            _ => {
+                report.skipped.push(format!(
+                    "{}: parent of {} missing, imported at top",
+                    name, item.title
+                ));
+                ""
+            }
+        };
+        let mut folder = Folder::from_raw(
+            from_joplin_id(item.prop("id"))?,
+            title(&item.title),
+            from_joplin_id(parent_id)?,
+        );
+        match local_folders.remove(folder.get_id()) {
+            Some(local) if local != folder => {
+                report.skipped.push(format!(
+                    "{}: book {} changed locally, kept",
+                    name, item.title
+                ));
+                folder = local;
+            }
+            _ => {
+                folder.save_raw(conn)?;
+                report.books += 1;
+            }
+        }
+        saved_folders.insert(folder.get_id().to_string(), folder);
+    }
+    let folder_path = |id: &str| {
+        let mut titles = vec![];
+        let mut id = id;
+        while let Some(folder) = saved_folders.get(id) {
+            titles.push(folder.title.as_str());
+            id = folder.get_parent_id();
+        }
+        titles
+            .iter()
+            .rev()
+            .fold(PathBuf::from("/"), |path, title| path.join(title))
+    };
+
+    // notes are saved before their bodies are known, as titles may change
+    let mut local_notes = Note::all(conn)?
+        .into_iter()
+        .map(|note| (note.get_id().to_string(), note))
+        .collect::<HashMap<_, _>>();
+    let mut notes = vec![];
+    let mut note_paths = HashMap::new();
+    for (name, item) in take(&mut items, NOTE) {
+        let parent_id = from_joplin_id(item.prop("parent_id"))?;
+        if !saved_folders.contains_key(&parent_id) {
+            report
+                .skipped
+                .push(format!("{}: book of note {} missing", name, item.title));
+            continue;
+        }
+        let mut note = Note::from_raw(
+            from_joplin_id(item.prop("id"))?,
+            parent_id.clone(),
+            title(&item.title),
+            crypto::seal(String::new(), &parent_id, conn)?,
+        );
+        let local = local_notes.remove(note.get_id());
+        match &local {
+            Some(local) => note_paths.insert(
+                item.prop("id").to_string(),
+                folder_path(local.get_book_id()).join(&local.title),
+            ),
+            None => {
+                note.save_raw(conn)?;
+                note_paths.insert(
+                    item.prop("id").to_string(),
+                    folder_path(&parent_id).join(&note.title),
+                )
+            }
+        };
+        notes.push((name, item, note, local));
+    }
+
+    let resource_ids = items
+        .get(RESOURCE)
+        .map(|resources| {
+            resources
+                .iter()
+                .map(|(_, item)| item.prop("id").to_string())
+                .collect::<HashSet<_>>()
+        })
+        .unwrap_or_default();
+    // resource to first note referencing it
+    let mut owners: HashMap<String, (String, String)> = HashMap::new();
+    for (name, item, note, local) in &notes {
+        let mut missing = vec![];
+        let body = Resource::replace_references(&item.body, |id| {
+            if resource_ids.contains(id) {
+                owners
+                    .entry(id.to_string())
+                    .or_insert_with(|| (note.get_id().to_string(), note.get_book_id().to_string()));
+                return from_joplin_id(id).ok().map(|id| format!(":/{}", id));
+            }
+            match note_paths.get(id) {
+                Some(path) => Some(link(path)),
+                None => {
+                    missing.push(format!("{}: link to missing item :/{}", note.title, id));
+                    None
+                }
+            }
+        });
+        report.skipped.extend(missing);
+        if let Some(local) = local {
+            if (local.get_book_id(), &local.title) != (note.get_book_id(), &note.title)
+                || local.read_body()? != body
+            {
+                report.skipped.push(format!(
+                    "{}: note {} changed locally, kept",
+                    name, item.title
+                ));
+                continue;
+            }
+        }
+        let mut note = Note::from_raw(
+            note.get_id().to_string(),
+            note.get_book_id().to_string(),
+            note.title.clone(),
+            crypto::seal(body, note.get_book_id(), conn)?,
+        );
+        note.save_raw(conn)?;
+        report.notes += 1;
+    }
+
+    for (name, item) in take(&mut items, RESOURCE) {
+        let id = item.prop("id");
+        let (note_id, book_id) = match owners.get(id) {
+            Some(owner) => owner,
+            None => {
+                report.skipped.push(format!(
+                    "{}: attachment {} not used by any note",
+                    name, item.title
+                ));
+                continue;
+            }
+        };
+        let file = files
+            .iter()
+            .find(|(file, _)| {
+                file.strip_prefix("resources/")
+                    .and_then(|file| file.strip_prefix(id))
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            .map(|(_, data)| data);
+        let data = match file {
+            Some(data) => data.clone(),
+            None => {
+                report.skipped.push(format!(
+                    "{}: data of attachment {} missing",
+                    name, item.title
+                ));
+                continue;
+            }
+        };
+        let mime = match item.prop("mime") {
+            "" => mime_guess::from_path(&item.title)
+                .first_or_octet_stream()
+                .to_string(),
+            mime => mime.to_string(),
+        };
+        let mut resource = Resource::from_raw(
+            from_joplin_id(id)?,
+            note_id.clone(),
+            item.title.clone(),
+            mime,
+            data.len() as i64,
+            String::new(),
+        );
+        resource.save_raw(crypto::seal_bytes(data, book_id, conn)?, conn)?;
+        report.resources += 1;
+    }
+
+    let tags = take(&mut items, TAG)
+        .into_iter()
+        .map(|(_, item)| (item.prop("id").to_string(), item.title))
+        .collect::<HashMap<_, _>>();
+    for (name, item) in take(&mut items, NOTE_TAG) {
+        match (
+            tags.get(item.prop("tag_id")),
+            note_paths.get(item.prop("note_id")),
+        ) {
+            (Some(tag), Some(_)) => {
+                report.tags += Tag::add(&from_joplin_id(item.prop("note_id"))?, tag, conn)?;
+            }
+            _ => report
+                .skipped
+                .push(format!("{}: tag or note of tag missing", name)),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{import, untar, Item};
+    use crate::db::{
+        establish_connection,
+        models::{Folder, Note, Resource, Tag},
+    };
+
+    const BOOK: &str = "0123456789abcdef0123456789abcdef";
+    const CHILD: &str = "11111111111111111111111111111111";
+    const NOTE: &str = "22222222222222222222222222222222";
+    const OTHER: &str = "33333333333333333333333333333333";
+    const RESOURCE: &str = "44444444444444444444444444444444";
+    const TAG: &str = "55555555555555555555555555555555";
+
+    fn item(title: &str, body: Option<&str>, props: &[(&str, &str)]) -> String {
+        let mut item = title.to_string();
+        if let Some(body) = body {
+            item.push_str("\n\n");
+            item.push_str(body);
+        }
+        item.push_str("\n\n");
+        let props = props
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect::<Vec<_>>();
+        item.push_str(&props.join("\n"));
+        item
+    }
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let dir = std::env::temp_dir().join(format!("md_cli_joplin_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("resources")).unwrap();
+        let write = |id: &str, content: String| {
+            fs::write(dir.join(format!("{}.md", id)), content).unwrap();
+        };
+        write(
+            BOOK,
+            item(
+                "Work",
+                None,
+                &[("id", BOOK), ("parent_id", ""), ("type_", "2")],
+            ),
+        );
+        write(
+            CHILD,
+            item(
+                "Plans",
+                None,
+                &[("id", CHILD), ("parent_id", BOOK), ("type_", "2")],
+            ),
+        );
+        write(
+            NOTE,
+            item(
+                "Roadmap",
+                Some(&format!(
+                    "# Roadmap\n\n![chart](:/{})\nsee [minutes](:/{})",
+                    RESOURCE, OTHER
+                )),
+                &[
+                    ("id", NOTE),
+                    ("parent_id", CHILD),
+                    ("is_todo", "0"),
+                    ("type_", "1"),
+                ],
+            ),
+        );
+        write(
+            OTHER,
+            item(
+                "Weekly minutes",
+                Some("nothing yet"),
+                &[("id", OTHER), ("parent_id", BOOK), ("type_", "1")],
+            ),
+        );
+        write(
+            RESOURCE,
+            item(
+                "chart.png",
+                None,
+                &[("id", RESOURCE), ("mime", "image/png"), ("type_", "4")],
+            ),
+        );
+        fs::write(dir.join(format!("resources/{}.png", RESOURCE)), b"png").unwrap();
+        write(TAG, item("urgent", None, &[("id", TAG), ("type_", "5")]));
+        write(
+            "66666666666666666666666666666666",
+            item(
+                "",
+                None,
+                &[("note_id", NOTE), ("tag_id", TAG), ("type_", "6")],
+            ),
+        );
+        write(
+            "77777777777777777777777777777777",
+            item("", None, &[("id", "7"), ("type_", "13")]),
+        );
+
+        {
+            let msg = "item is parsed from title, body and properties";
+            let item = Item::parse("Title\n\nline 1\n\nline 2\n\nid: 1\ntype_: 1").unwrap();
+            assert_eq!(item.title, "Title", "{}", msg);
+            assert_eq!(item.body, "line 1\n\nline 2", "{}", msg);
+            assert_eq!(item.prop("type_"), "1", "{}", msg);
+        }
+
+        {
+            let msg = "hierarchy, bodies, attachments and tags are imported";
+            let report = import(&dir, &conn).unwrap();
+            assert_eq!(
+                (report.books, report.notes, report.resources, report.tags),
+                (2, 2, 1, 1),
+                "{}",
+                msg
+            );
+            assert_eq!(report.skipped.len(), 1, "{}", msg);
+            assert!(Folder::get(&"/Work/Plans".into(), &conn).is_ok(), "{}", msg);
+            let roadmap = "/Work/Plans/Roadmap".into();
+            let body = Note::cat(&roadmap, &conn).unwrap();
+            assert!(
+                body.contains("see [minutes](/Work/Weekly%20minutes)"),
+                "{}",
+                msg
+            );
+            let resources = Resource::list(&roadmap, &conn).unwrap();
+            assert_eq!(resources.len(), 1, "{}", msg);
+            assert!(
+                body.contains(&format!("![chart](:/{})", resources[0].get_id())),
+                "{}",
+                msg
+            );
+            assert_eq!(resources[0].read_data(), Ok(b"png".to_vec()), "{}", msg);
+            assert_eq!(
+                Tag::list(&roadmap, &conn),
+                Ok(vec!["urgent".into()]),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "import again updates items in place";
+            import(&dir, &conn).unwrap();
+            let (folders, notes) = Folder::list(&"/Work/".into(), &conn).unwrap();
+            assert_eq!((folders.len(), notes.len()), (1, 1), "{}", msg);
+        }
+
+        {
+            let msg = "import again keeps and reports local changes";
+            let roadmap = "/Work/Plans/Roadmap".into();
+            Note::update(&roadmap, "edited".into(), &conn).unwrap();
+            let report = import(&dir, &conn).unwrap();
+            assert_eq!(Note::cat(&roadmap, &conn), Ok("edited".into()), "{}", msg);
+            assert!(
+                report
+                    .skipped
+                    .contains(&format!("{}.md: note Roadmap changed locally, kept", NOTE)),
+                "{}",
+                msg
+            );
+            assert_eq!(report.notes, 1, "{}", msg);
+        }
+
+        {
+            let msg = "failed import leaves nothing behind";
+            let broken = dir.join("broken");
+            fs::create_dir_all(&broken).unwrap();
+            fs::write(
+                broken.join(format!("{}.md", BOOK)),
+                item(
+                    "Broken",
+                    None,
+                    &[("id", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"), ("type_", "2")],
+                ),
+            )
+            .unwrap();
+            fs::write(
+                broken.join(format!("{}.md", CHILD)),
+                item("Bad", None, &[("id", "bad"), ("type_", "2")]),
+            )
+            .unwrap();
+            assert_eq!(
+                import(&broken, &conn).map(|_| ()),
+                Err("InvalidId: bad".into()),
+                "{}",
+                msg
+            );
+            assert!(Folder::get(&"/Broken".into(), &conn).is_err(), "{}", msg);
+        }
+
+        {
+            let msg = "jex archive is read";
+            let mut tar = vec![0u8; 512];
+            tar[..5].copy_from_slice(b"a.txt");
+            tar[124..135].copy_from_slice(b"00000000003");
+            tar[156] = b'0';
+            tar.extend_from_slice(b"abc");
+            tar.resize(2048, 0);
+            let files = untar(&tar).unwrap();
+            assert_eq!(files.get("a.txt"), Some(&b"abc".to_vec()), "{}", msg);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use diesel::Connection;
+
+use crate::db::DbConnection;
+
+mod joplin;
+mod obsidian;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Joplin,
+    Obsidian,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "joplin" => Ok(Self::Joplin),
+            "obsidian" | "logseq" => Ok(Self::Obsidian),
+            _ => Err(format!("UnknownSource: {}", s)),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ImportReport {
+    pub books: usize,
+    pub notes: usize,
+    pub resources: usize,
+    pub tags: usize,
+    /// items or references that could not be mapped
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "books: {}, notes: {}, attachments: {}, tags: {}",
+            self.books, self.notes, self.resources, self.tags
+        )?;
+        for skipped in &self.skipped {
+            write!(f, "\nskipped {}", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// markdown link target of note at path.
+fn link(path: &Path) -> String {
+    path.to_string_lossy().replace(' ', "%20")
+}
+
+/// run import `f` in one transaction, so a failed import leaves nothing behind.
+fn transaction<T>(conn: &DbConnection, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let mut error = None;
+    conn.transaction(|| {
+        f().map_err(|e| {
+            error = Some(e);
+            diesel::result::Error::RollbackTransaction
+        })
+    })
+    .map_err(|e| error.take().unwrap_or_else(|| e.to_string()))
+}
+
+/// import notes exported by another application at path.
+pub fn import(source: Source, path: &Path, conn: &DbConnection) -> Result<ImportReport, String> {
+    match source {
+        Source::Joplin => joplin::import(path, conn),
+        Source::Obsidian => obsidian::import(path, conn),
+    }
+}
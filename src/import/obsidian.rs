@@ -0,0 +1,464 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::{link, transaction, ImportReport};
+use crate::{
+    commands::resolve_path,
+    db::{
+        models::{Folder, Note, Resource, Tag},
+        DbConnection,
+    },
+    export::unescape,
+};
+
+const NOTE_EXTENSION: &str = "md";
+
+/// config directories of Obsidian and Logseq, not part of the notes.
+fn is_config(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    name.starts_with('.') || (name == "logseq" && path.join("config.edn").exists())
+}
+
+fn is_note(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == NOTE_EXTENSION)
+}
+
+/// `tags` (or `tag`) of front matter, as list or comma separated string.
+fn front_matter_tags(body: &str) -> Vec<String> {
+    let front_matter = body
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---").map(|(yaml, _)| yaml));
+    let docs = match front_matter.map(YamlLoader::load_from_str) {
+        Some(Ok(docs)) => docs,
+        _ => return vec![],
+    };
+    let doc = match docs.first() {
+        Some(doc) => doc,
+        None => return vec![],
+    };
+    let tags = match &doc["tags"] {
+        Yaml::BadValue => &doc["tag"],
+        tags => tags,
+    };
+    let tags = match tags {
+        Yaml::Array(tags) => tags
+            .iter()
+            .filter_map(|tag| tag.as_str().map(String::from))
+            .collect(),
+        Yaml::String(tags) => tags
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .map(String::from)
+            .collect(),
+        _ => vec![],
+    };
+    tags.iter()
+        .map(|tag| tag.trim().trim_start_matches('#').to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+struct Vault<'a> {
+    root: PathBuf,
+    /// book vault root is imported as
+    book: PathBuf,
+    /// vault relative paths of notes
+    notes: Vec<PathBuf>,
+    /// vault relative paths of other files
+    files: Vec<PathBuf>,
+    report: ImportReport,
+    conn: &'a DbConnection,
+}
+
+impl<'a> Vault<'a> {
+    /// make empty notes, and books holding them, for vault dir `dir`.
+    ///
+    /// returns if dir holds notes.
+    fn scan(&mut self, dir: &Path) -> Result<bool, String> {
+        let mut entries = fs::read_dir(self.root.join(dir))
+            .map_err(|e| e.to_string())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        entries.sort();
+        let mut has_notes = false;
+        for path in entries {
+            if is_config(&path) {
+                continue;
+            }
+            let rel = dir.join(path.file_name().unwrap_or_default());
+            if path.is_dir() {
+                has_notes |= self.scan(&rel)?;
+            } else if is_note(&path) {
+                Note::make(&self.note_path(&rel), true, self.conn)?;
+                self.notes.push(rel);
+                has_notes = true;
+            } else {
+                self.files.push(rel);
+            }
+        }
+        if has_notes {
+            self.report.books += 1;
+        }
+        Ok(has_notes)
+    }
+
+    /// md_cli path of vault relative note.
+    fn note_path(&self, rel: &Path) -> PathBuf {
+        self.book.join(rel.with_extension(""))
+    }
+
+    /// vault relative note or file `target` of wikilink or markdown link in note `from`.
+    ///
+    /// targets with `/` or of markdown links are paths, others names found
+    /// anywhere, nearest to `from` first.
+    fn resolve(&self, target: &str, from: &Path, relative: bool) -> Option<PathBuf> {
+        let dir = from.parent().unwrap_or_else(|| Path::new(""));
+        let target = target.trim();
+        let candidates = [target.to_string(), format!("{}.{}", target, NOTE_EXTENSION)];
+        for target in &candidates {
+            let items = if is_note(Path::new(target)) {
+                &self.notes
+            } else {
+                &self.files
+            };
+            if relative {
+                let path = resolve_path(&Path::new("/").join(dir), Path::new(target));
+                let path = path.strip_prefix("/").unwrap_or(&path);
+                if let Some(item) = items.iter().find(|item| *item == path) {
+                    return Some(item.clone());
+                }
+                continue;
+            }
+            let target = target.to_lowercase();
+            let mut found = items
+                .iter()
+                .filter(|item| {
+                    let item = item.to_string_lossy().to_lowercase();
+                    item == target || item.ends_with(&format!("/{}", target))
+                })
+                .collect::<Vec<_>>();
+            found.sort_by_key(|item| (item.parent() != Some(dir), item.components().count()));
+            if let Some(item) = found.first() {
+                return Some(item.to_path_buf());
+            }
+        }
+        None
+    }
+
+    /// markdown link to vault item `item` from `note`, attaching files once.
+    fn link(
+        &mut self,
+        item: &Path,
+        note: &Path,
+        attached: &mut HashMap<PathBuf, String>,
+    ) -> Result<String, String> {
+        if is_note(item) {
+            return Ok(link(&self.note_path(item)));
+        }
+        if let Some(id) = attached.get(item) {
+            return Ok(format!(":/{}", id));
+        }
+        let resource = Resource::attach(&self.note_path(note), &self.root.join(item), self.conn)?;
+        self.report.resources += 1;
+        attached.insert(item.to_path_buf(), resource.get_id().to_string());
+        Ok(format!(":/{}", resource.get_id()))
+    }
+
+    /// `[[target#heading|alias]]` (embedded with `!`) as markdown link.
+    fn wikilink(
+        &mut self,
+        inner: &str,
+        embed: bool,
+        note: &Path,
+        attached: &mut HashMap<PathBuf, String>,
+    ) -> Result<Option<String>, String> {
+        let (target, alias) = match inner.split_once('|') {
+            Some((target, alias)) => (target, Some(alias)),
+            None => (inner, None),
+        };
+        let (name, fragment) = match target.split_once('#') {
+            Some((name, fragment)) => (name, format!("#{}", fragment.replace(' ', "%20"))),
+            None => (target, String::new()),
+        };
+        let item = match self.resolve(name, note, false) {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let dest = self.link(&item, note, attached)?;
+        Ok(Some(if is_note(&item) {
+            format!("[{}]({}{})", alias.unwrap_or(target), dest, fragment)
+        } else if embed {
+            // alias of embedded image is its size
+            format!("![{}]({})", name, dest)
+        } else {
+            format!("[{}]({})", alias.unwrap_or(name), dest)
+        }))
+    }
+
+    /// body of note with wikilinks and relative markdown links converted.
+    fn convert(&mut self, body: &str, note: &Path) -> Result<String, String> {
+        let mut attached = HashMap::new();
+        let mut output = String::with_capacity(body.len());
+        let mut code = false;
+        for line in body.split_inclusive('\n') {
+            let fence = line.trim_start();
+            if fence.starts_with("```") || fence.starts_with("~~~") {
+                code = !code;
+            }
+            if code {
+                output.push_str(line);
+                continue;
+            }
+            let mut rest = line;
+            loop {
+                let wiki = rest.find("[[");
+                let markdown = rest.find("](");
+                match (wiki, markdown) {
+                    (Some(start), markdown) if markdown.is_none_or(|m| start < m) => {
+                        let end = match rest[start..].find("]]") {
+                            Some(end) => start + end,
+                            None => break,
+                        };
+                        let embed = rest[..start].ends_with('!');
+                        let inner = &rest[start + 2..end];
+                        let prefix = &rest[..start - embed as usize];
+                        output.push_str(prefix);
+                        match self.wikilink(inner, embed, note, &mut attached)? {
+                            Some(converted) => output.push_str(&converted),
+                            None => {
+                                self.report.skipped.push(format!(
+                                    "{}: unresolved link [[{}]]",
+                                    note.display(),
+                                    inner
+                                ));
+                                output.push_str(&rest[prefix.len()..end + 2]);
+                            }
+                        }
+                        rest = &rest[end + 2..];
+                    }
+                    (_, Some(start)) => {
+                        let end = match rest[start..].find(')') {
+                            Some(end) => start + end,
+                            None => break,
+                        };
+                        output.push_str(&rest[..start + 2]);
+                        let dest = &rest[start + 2..end];
+                        let target = dest.trim().trim_start_matches('<').trim_end_matches('>');
+                        let (target, fragment) = match target.split_once('#') {
+                            Some((target, fragment)) => (target, format!("#{}", fragment)),
+                            None => (target, String::new()),
+                        };
+                        let external =
+                            target.is_empty() || target.contains(':') || target.starts_with('/');
+                        match self.resolve(&unescape(target), note, true) {
+                            Some(item) if !external => {
+                                output.push_str(&self.link(&item, note, &mut attached)?);
+                                output.push_str(&fragment);
+                            }
+                            found => {
+                                if found.is_none() && !external {
+                                    self.report.skipped.push(format!(
+                                        "{}: unresolved link {}",
+                                        note.display(),
+                                        target
+                                    ));
+                                }
+                                output.push_str(dest);
+                            }
+                        }
+                        output.push(')');
+                        rest = &rest[end + 1..];
+                    }
+                    _ => break,
+                }
+            }
+            output.push_str(rest);
+        }
+        Ok(output)
+    }
+}
+
+pub fn import(path: &Path, conn: &DbConnection) -> Result<ImportReport, String> {
+    let root = fs::canonicalize(path).map_err(|e| e.to_string())?;
+    if !root.is_dir() {
+        return Err("NotADirectory".into());
+    }
+    let title = root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "vault".into());
+    let book = PathBuf::from("/").join(title);
+    if Folder::get(&book, conn).is_ok() {
+        return Err("AlreadyExists".into());
+    }
+    transaction(conn, || import_vault(root, book, conn))
+}
+
+/// import vault at `root` as new `book`.
+fn import_vault(root: PathBuf, book: PathBuf, conn: &DbConnection) -> Result<ImportReport, String> {
+    let mut vault = Vault {
+        root,
+        book,
+        notes: vec![],
+        files: vec![],
+        report: ImportReport::default(),
+        conn,
+    };
+    vault.scan(Path::new(""))?;
+    for note in vault.notes.clone() {
+        let body = fs::read_to_string(vault.root.join(&note)).map_err(|e| e.to_string())?;
+        let path = vault.note_path(&note);
+        Note::update(&path, vault.convert(&body, &note)?, conn)?;
+        vault.report.notes += 1;
+        let note_id = Note::query(&path, conn)?.get_id().to_string();
+        for tag in front_matter_tags(&body) {
+            vault.report.tags += Tag::add(&note_id, &tag, conn)?;
+        }
+    }
+    Ok(vault.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{front_matter_tags, import};
+    use crate::db::{
+        establish_connection,
+        models::{Folder, Note, Resource, Tag},
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let dir = std::env::temp_dir().join(format!("md_cli_vault_{}", uuid::Uuid::new_v4()));
+        let vault = dir.join("Vault");
+        for sub in [".obsidian", "Projects", "assets"] {
+            fs::create_dir_all(vault.join(sub)).unwrap();
+        }
+        fs::write(vault.join(".obsidian/app.md"), "config").unwrap();
+        fs::write(vault.join("assets/chart.png"), b"png").unwrap();
+        fs::write(
+            vault.join("Home.md"),
+            "---\ntags: [work, \"#plans\"]\n---\n\
+             See [[Road map|the roadmap]], [[Road map#Goals]] and [[Nowhere]].\n\
+             ![[chart.png|300]]\n[same](assets/chart.png) [web](https://example.com)\n\
+             ```\n[[Nowhere]]\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            vault.join("Projects/Road map.md"),
+            "back to [home](../Home.md)",
+        )
+        .unwrap();
+
+        {
+            let msg = "front matter tags";
+            assert_eq!(
+                front_matter_tags("---\ntag: a, b\n---\nbody"),
+                vec!["a".to_string(), "b".to_string()],
+                "{}",
+                msg
+            );
+            assert!(front_matter_tags("no front matter").is_empty(), "{}", msg);
+        }
+
+        {
+            let msg = "vault folders become books and files notes";
+            let report = import(&vault, &conn).unwrap();
+            assert_eq!(
+                (report.books, report.notes, report.resources, report.tags),
+                (2, 2, 1, 2),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                report.skipped,
+                vec!["Home.md: unresolved link [[Nowhere]]".to_string()],
+                "{}",
+                msg
+            );
+            assert!(
+                Folder::get(&"/Vault/Projects".into(), &conn).is_ok(),
+                "{}",
+                msg
+            );
+            assert!(
+                Folder::get(&"/Vault/.obsidian".into(), &conn).is_err(),
+                "{}",
+                msg
+            );
+            assert!(
+                Folder::get(&"/Vault/assets".into(), &conn).is_err(),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "links are converted to paths and attachments";
+            let home = "/Vault/Home".into();
+            let body = Note::cat(&home, &conn).unwrap();
+            let id = Resource::list(&home, &conn).unwrap()[0]
+                .get_id()
+                .to_string();
+            assert!(
+                body.contains("See [the roadmap](/Vault/Projects/Road%20map), [Road map#Goals](/Vault/Projects/Road%20map#Goals) and [[Nowhere]]."),
+                "{}",
+                msg
+            );
+            assert!(body.contains(&format!("![chart.png](:/{})", id)), "{}", msg);
+            assert!(body.contains(&format!("[same](:/{})", id)), "{}", msg);
+            assert!(body.contains("[web](https://example.com)"), "{}", msg);
+            assert!(body.contains("```\n[[Nowhere]]\n```"), "{}", msg);
+            assert_eq!(
+                Note::cat(&"/Vault/Projects/Road map".into(), &conn),
+                Ok("back to [home](/Vault/Home)".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Tag::list(&home, &conn),
+                Ok(vec!["plans".into(), "work".into()]),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "re-import into existing book fails before writing";
+            Note::update(&"/Vault/Home".into(), "edited".into(), &conn).unwrap();
+            assert_eq!(
+                import(&vault, &conn).map(|_| ()),
+                Err("AlreadyExists".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Note::cat(&"/Vault/Home".into(), &conn),
+                Ok("edited".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "failed import leaves nothing behind";
+            let broken = dir.join("Broken");
+            fs::create_dir_all(broken.join("Sub")).unwrap();
+            fs::write(broken.join("Sub/a.md"), "a").unwrap();
+            fs::write(broken.join("z.md"), b"\xff\xfe").unwrap();
+            assert!(import(&broken, &conn).is_err(), "{}", msg);
+            assert!(Folder::get(&"/Broken".into(), &conn).is_err(), "{}", msg);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,34 +1,326 @@
-use rustyline::{error::ReadlineError, Editor};
-use structopt::StructOpt;
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use crate::{commands::Command, db::DbConnection};
+use diesel::Connection;
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use structopt::{
+    clap::{App, ErrorKind},
+    StructOpt,
+};
+
+use crate::{
+    commands::{resolve_path, Command},
+    db::{data_dir, models::Folder, DbConnection},
+};
+
+pub(crate) const HISTORY_FILE: &str = "history";
 
 pub struct Interpreter {
-    prompt: String,
-    conn: DbConnection,
-    reader: Editor<()>,
+    prompt: fn(&Path) -> String,
+    conn: Rc<DbConnection>,
+    reader: Editor<InterpreterHelper>,
+    cwd: PathBuf,
+    previous: PathBuf,
+}
+
+/// completes subcommands, flags and book/note paths relative to `cwd`.
+struct InterpreterHelper {
+    conn: Rc<DbConnection>,
+    commands: CommandSpec,
+    cwd: PathBuf,
+}
+
+/// what session does after a line
+enum Flow {
+    Output(String),
+    Quit,
 }
 
 #[derive(StructOpt)]
 enum InterpreterCommand {
     #[structopt(flatten)]
     Command(Command),
+    #[structopt(about = "change current book, `-` for previous book")]
+    Cd { book: Option<PathBuf> },
+    #[structopt(about = "print current book")]
+    Pwd,
     #[structopt(visible_alias = "q", about = "quit")]
     Quit,
 }
 
+/// start of word under cursor, spaces escaped with `\` do not end words.
+pub(crate) fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c.is_whitespace() {
+            start = i + c.len_utf8();
+        }
+    }
+    start
+}
+
+impl InterpreterHelper {
+    fn new(conn: Rc<DbConnection>) -> Self {
+        Self {
+            conn,
+            commands: CommandSpec::new(&InterpreterCommand::clap()),
+            cwd: PathBuf::from("/"),
+        }
+    }
+}
+
+/// names, flags and subcommands of a command for completion, read from its
+/// help as clap 2 has no public accessors for them.
+#[derive(Default)]
+pub(crate) struct CommandSpec {
+    /// name followed by visible aliases
+    names: Vec<String>,
+    flags: Vec<String>,
+    subcommands: Vec<CommandSpec>,
+}
+
+impl CommandSpec {
+    pub(crate) fn new(app: &App<'static, 'static>) -> Self {
+        Self::read(app, vec![])
+    }
+
+    /// spec of subcommand at `path` of `app`.
+    fn read(app: &App<'static, 'static>, path: Vec<String>) -> Self {
+        let mut args = path.clone();
+        args.push("--help".into());
+        let help = match app.clone().get_matches_from_safe(args) {
+            Err(e) if e.kind == ErrorKind::HelpDisplayed => e.message,
+            _ => String::new(),
+        };
+        let mut spec = Self {
+            names: path.last().cloned().into_iter().collect(),
+            ..Default::default()
+        };
+        let mut section = "";
+        for line in help.lines() {
+            if !line.starts_with(' ') {
+                section = line;
+                continue;
+            }
+            let entry = line.trim_start();
+            match section {
+                "FLAGS:" | "OPTIONS:" => spec.flags.extend(
+                    entry
+                        .split_whitespace()
+                        .take_while(|word| word.starts_with('-'))
+                        .map(|word| word.trim_end_matches(',').to_string())
+                        .filter(|flag| flag != "-h" && flag != "--help"),
+                ),
+                // deeper indented lines are wrapped descriptions
+                "SUBCOMMANDS:" if line.len() - entry.len() == 4 => {
+                    let name = entry.split_whitespace().next().unwrap_or("");
+                    if name == "help" {
+                        continue;
+                    }
+                    let mut sub_path = path.clone();
+                    sub_path.push(name.into());
+                    let mut sub = Self::read(app, sub_path);
+                    let aliases = entry
+                        .split_once("[aliases: ")
+                        .or_else(|| entry.split_once("[alias: "))
+                        .and_then(|(_, rest)| rest.split_once(']'))
+                        .map_or("", |(aliases, _)| aliases);
+                    for alias in aliases.split(", ").filter(|alias| !alias.is_empty()) {
+                        if !sub.names.iter().any(|name| name == alias) {
+                            sub.names.push(alias.into());
+                        }
+                    }
+                    spec.subcommands.push(sub);
+                }
+                _ => {}
+            }
+        }
+        spec.flags.sort();
+        spec
+    }
+}
+
+fn subcommands(spec: &CommandSpec, word: &str) -> Vec<Pair> {
+    let mut names = spec
+        .subcommands
+        .iter()
+        .flat_map(|sub| &sub.names)
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| Pair {
+            display: name.clone(),
+            replacement: format!("{} ", name),
+        })
+        .collect()
+}
+
+fn flags(spec: &CommandSpec, word: &str) -> Vec<Pair> {
+    spec.flags
+        .iter()
+        .filter(|flag| flag.starts_with(word))
+        .map(|flag| Pair {
+            display: flag.clone(),
+            replacement: format!("{} ", flag),
+        })
+        .collect()
+}
+
+fn paths(cwd: &Path, word: &str, conn: &DbConnection) -> Vec<Pair> {
+    let word = word.replace('\\', "");
+    let (book, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word.as_str()),
+    };
+    let path = resolve_path(cwd, Path::new(book));
+    let (folders, notes) = match Folder::list(&path, conn) {
+        Ok(items) => items,
+        Err(_) => return vec![],
+    };
+    let folders = folders
+        .into_iter()
+        .map(|folder| (format!("{}/", folder.title), ""));
+    let notes = notes.into_iter().map(|note| (note.title, " "));
+    let mut paths = folders
+        .chain(notes)
+        .filter(|(title, _)| title.starts_with(prefix))
+        .map(|(title, end)| Pair {
+            replacement: format!(
+                "{}{}",
+                shellwords::escape(&format!("{}{}", book, title)),
+                end
+            ),
+            display: title,
+        })
+        .collect::<Vec<_>>();
+    paths.sort_by(|a, b| a.display.cmp(&b.display));
+    paths
+}
+
+/// start of last word of `line` and its completions, subcommands and flags
+/// of `spec` or book/note paths relative to `cwd`.
+pub(crate) fn complete(
+    spec: &CommandSpec,
+    cwd: &Path,
+    line: &str,
+    conn: &DbConnection,
+) -> (usize, Vec<Pair>) {
+    let start = word_start(line);
+    let word = &line[start..];
+    let mut spec = spec;
+    let mut in_subcommand = true;
+    for arg in line[..start].split_whitespace() {
+        match spec
+            .subcommands
+            .iter()
+            .find(|sub| sub.names.iter().any(|name| name == arg))
+        {
+            Some(sub) if in_subcommand => spec = sub,
+            _ => in_subcommand = false,
+        }
+    }
+    let candidates = if word.starts_with('-') {
+        flags(spec, word)
+    } else if in_subcommand && !spec.subcommands.is_empty() {
+        subcommands(spec, word)
+    } else {
+        paths(cwd, word, conn)
+    };
+    (start, candidates)
+}
+
+impl Completer for InterpreterHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete(
+            &self.commands,
+            &self.cwd,
+            &line[..pos],
+            &self.conn,
+        ))
+    }
+}
+
+impl Hinter for InterpreterHelper {
+    type Hint = String;
+}
+
+impl Highlighter for InterpreterHelper {}
+
+impl Validator for InterpreterHelper {}
+
+impl Helper for InterpreterHelper {}
+
 impl Interpreter {
-    pub fn new(prompt: &str, conn: DbConnection) -> Self {
-        let reader = Editor::<()>::new();
+    pub fn new(prompt: fn(&Path) -> String, conn: DbConnection) -> Self {
+        let conn = Rc::new(conn);
+        let mut reader = Editor::<InterpreterHelper>::new();
+        reader.set_helper(Some(InterpreterHelper::new(conn.clone())));
+        if let Ok(dir) = data_dir() {
+            // no history yet on first run
+            let _ = reader.load_history(&dir.join(HISTORY_FILE));
+        }
         Self {
-            prompt: prompt.to_string(),
+            prompt,
             conn,
             reader,
+            cwd: PathBuf::from("/"),
+            previous: PathBuf::from("/"),
+        }
+    }
+
+    fn cd(&mut self, book: Option<PathBuf>) -> Result<String, String> {
+        let book = match book {
+            Some(book) if book == Path::new("-") => self.previous.clone(),
+            Some(book) => resolve_path(&self.cwd, &book),
+            None => PathBuf::from("/"),
+        };
+        let book = Path::new("/").join(book.to_string_lossy().trim_matches('/'));
+        if book.parent().is_some() {
+            Folder::get(&book, &self.conn)?;
+        }
+        self.previous = std::mem::replace(&mut self.cwd, book);
+        if let Some(helper) = self.reader.helper_mut() {
+            helper.cwd = self.cwd.clone();
+        }
+        Ok(self.cwd.to_string_lossy().to_string())
+    }
+
+    fn save_history(&mut self) {
+        if let Err(err) = data_dir().and_then(|dir| {
+            self.reader
+                .append_history(&dir.join(HISTORY_FILE))
+                .map_err(|e| e.to_string())
+        }) {
+            eprintln!("{}", err);
         }
     }
 
     pub fn read_line(&mut self) -> Result<String, ReadlineError> {
-        match self.reader.readline(self.prompt.as_str()) {
+        match self.reader.readline(&(self.prompt)(&self.cwd)) {
             Ok(line) => {
                 self.reader.add_history_entry(&line);
                 Ok(line)
@@ -37,34 +329,343 @@ impl Interpreter {
         }
     }
 
+    /// execute one line, blank lines and `#` comments do nothing.
+    fn execute(&mut self, line: &str) -> Result<Flow, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(Flow::Output(String::new()));
+        }
+        let args = shellwords::split(line).map_err(|e| e.to_string())?;
+        match InterpreterCommand::from_iter_safe(args) {
+            Ok(InterpreterCommand::Command(mut c)) => {
+                c.resolve(&self.cwd);
+                c.run_unlocking(&self.conn).map(Flow::Output)
+            }
+            Ok(InterpreterCommand::Cd { book }) => {
+                self.cd(book).map(|_| Flow::Output(String::new()))
+            }
+            Ok(InterpreterCommand::Pwd) => Ok(Flow::Output(self.cwd.to_string_lossy().to_string())),
+            Ok(InterpreterCommand::Quit) => Ok(Flow::Quit),
+            Err(e) if matches!(e.kind, structopt::clap::ErrorKind::HelpDisplayed) => {
+                Ok(Flow::Output(e.message))
+            }
+            Err(e) => Err(format!("Error: {:?}\nInfo: {:?}", e.kind, e.info)),
+        }
+    }
+
     pub fn run(&mut self) {
         while let Ok(line) = self.read_line() {
-            let args = match shellwords::split(&line) {
-                Ok(args) => args,
+            match self.execute(&line) {
+                Ok(Flow::Output(msg)) if msg.is_empty() => {}
+                Ok(Flow::Output(msg)) => println!("{}", msg),
+                Ok(Flow::Quit) => break,
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+        self.save_history();
+    }
+
+    fn run_lines(&mut self, name: &str, script: &str, keep_going: bool) -> bool {
+        let mut ok = true;
+        for (number, line) in script.lines().enumerate() {
+            match self.execute(line) {
+                Ok(Flow::Output(msg)) if msg.is_empty() => {}
+                Ok(Flow::Output(msg)) => println!("{}", msg),
+                Ok(Flow::Quit) => break,
                 Err(err) => {
-                    eprintln!("{}", err);
-                    return;
-                }
-            };
-            match InterpreterCommand::from_iter_safe(args) {
-                Ok(InterpreterCommand::Command(c)) => match c.execute(&self.conn) {
-                    Err(err) => eprintln!("{}", err),
-                    Ok(msg) => println!("{}", msg),
-                },
-                Ok(InterpreterCommand::Quit) => {
-                    break;
-                }
-                Err(e) => {
-                    if matches!(e.kind, structopt::clap::ErrorKind::HelpDisplayed) {
-                        eprintln!("{}", e.message);
-                    } else {
-                        eprintln!("Error: {:?}", e.kind);
-                        eprintln!("Info: {:?}", e.info);
-                        eprintln!("\n==============================");
-                        InterpreterCommand::clap().print_long_help().unwrap();
+                    eprintln!("{}:{}: {}", name, number + 1, err);
+                    ok = false;
+                    if !keep_going {
+                        break;
                     }
                 }
             }
         }
+        ok
+    }
+
+    /// run `script` line by line, false if any line failed.
+    ///
+    /// with `transaction` database changes of failed script are rolled back.
+    pub fn run_script(
+        &mut self,
+        name: &str,
+        script: &str,
+        keep_going: bool,
+        transaction: bool,
+    ) -> bool {
+        if !transaction {
+            return self.run_lines(name, script, keep_going);
+        }
+        let conn = self.conn.clone();
+        match conn.transaction(|| match self.run_lines(name, script, keep_going) {
+            true => Ok(()),
+            false => Err(diesel::result::Error::RollbackTransaction),
+        }) {
+            Ok(()) => true,
+            Err(diesel::result::Error::RollbackTransaction) => {
+                eprintln!("{}: changes rolled back", name);
+                false
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, rc::Rc};
+
+    use rustyline::{completion::Completer, history::History, Context};
+
+    use super::{Interpreter, InterpreterHelper};
+    use crate::{
+        commands::{resolve_path, Command},
+        db::{
+            establish_connection,
+            models::{Folder, Note},
+        },
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Folder::make(&"my book".into(), false, &conn).unwrap();
+        Note::make(&"my book/note".into(), false, &conn).unwrap();
+        Note::make(&"my book/other".into(), false, &conn).unwrap();
+        let helper = InterpreterHelper::new(Rc::new(conn));
+        let history = History::new();
+        let complete = |line: &str| {
+            let (start, pairs) = helper
+                .complete(line, line.len(), &Context::new(&history))
+                .unwrap();
+            (
+                start,
+                pairs.into_iter().map(|p| p.replacement).collect::<Vec<_>>(),
+            )
+        };
+
+        {
+            let msg = "subcommands are completed";
+            assert_eq!(complete("ma"), (0, vec!["make ".into()]), "{}", msg);
+            assert_eq!(complete("sync c"), (5, vec!["config ".into()]), "{}", msg);
+            assert_eq!(
+                complete("m"),
+                (
+                    0,
+                    vec!["make ".into(), "mk ".into(), "move ".into(), "mv ".into()]
+                ),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                complete("mk -"),
+                (3, vec!["--template ".into(), "--var ".into(), "-p ".into()]),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "flags are completed";
+            assert_eq!(complete("ls -"), (3, vec!["-r ".into()]), "{}", msg);
+            assert_eq!(
+                complete("sync config --we"),
+                (12, vec!["--webdav ".into()]),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "paths are completed";
+            assert_eq!(complete("cat m"), (4, vec!["my\\ book/".into()]), "{}", msg);
+            assert_eq!(
+                complete("cat my\\ book/n"),
+                (4, vec!["my\\ book/note ".into()]),
+                "{}",
+                msg
+            );
+            assert_eq!(complete("cat x/"), (4, vec![]), "{}", msg);
+        }
+    }
+
+    #[test]
+    pub fn cd_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Folder::make(&"book/sub".into(), true, &conn).unwrap();
+        Note::make(&"book/sub/note".into(), false, &conn).unwrap();
+        let mut interpreter = Interpreter::new(|_| String::new(), conn);
+
+        {
+            let msg = "paths are resolved against current book";
+            let cwd = Path::new("/book");
+            assert_eq!(
+                resolve_path(cwd, "sub/note".as_ref()),
+                Path::new("/book/sub/note"),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                resolve_path(cwd, "../x/".as_ref()).to_str(),
+                Some("/x/"),
+                "{}",
+                msg
+            );
+            assert_eq!(resolve_path(cwd, "/x".as_ref()), Path::new("/x"), "{}", msg);
+            assert_eq!(
+                resolve_path(cwd, "../..".as_ref()).to_str(),
+                Some("/"),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "cd changes current book";
+            assert_eq!(
+                interpreter.cd(Some("book".into())),
+                Ok("/book".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                interpreter.cd(Some("sub/".into())),
+                Ok("/book/sub".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                interpreter.cd(Some("..".into())),
+                Ok("/book".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                interpreter.cd(Some("-".into())),
+                Ok("/book/sub".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                interpreter.cd(Some("missing".into())),
+                Err("NotFound".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(interpreter.cwd, Path::new("/book/sub"), "{}", msg);
+        }
+
+        {
+            let msg = "commands use current book";
+            let mut command = Command::Cat {
+                render: false,
+                raw: false,
+                note: "note".into(),
+                out_file: None,
+            };
+            command.resolve(&interpreter.cwd);
+            assert!(
+                matches!(&command, Command::Cat { note, .. } if note == Path::new("/book/sub/note")),
+                "{}",
+                msg
+            );
+            let helper = interpreter.reader.helper().unwrap();
+            let (_, pairs) = helper
+                .complete("cat n", 5, &Context::new(&History::new()))
+                .unwrap();
+            assert_eq!(pairs[0].replacement, "note ", "{}", msg);
+            interpreter.cd(None).unwrap();
+            assert_eq!(interpreter.cwd, Path::new("/"), "{}", msg);
+        }
+    }
+
+    #[test]
+    pub fn script_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let mut interpreter = Interpreter::new(|_| String::new(), conn);
+        let script = "# skeleton\n\nmk work/\ncd work\nmk \"todo\nmk done\n";
+
+        {
+            let msg = "script stops at failing line";
+            assert!(
+                !interpreter.run_script("test", script, false, false),
+                "{}",
+                msg
+            );
+            assert!(
+                Note::query(&"work/done".into(), &interpreter.conn).is_err(),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "script continues after failing line";
+            interpreter.cd(None).unwrap();
+            let script = script.replace("work", "home");
+            assert!(
+                !interpreter.run_script("test", &script, true, false),
+                "{}",
+                msg
+            );
+            assert!(
+                Note::query(&"home/done".into(), &interpreter.conn).is_ok(),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "existing titles with wildcards are taken literally";
+            interpreter.cd(None).unwrap();
+            let script = "mk -p globs/a*\nmk globs/ab\nrm -y globs/a*\n";
+            assert!(
+                interpreter.run_script("test", script, false, false),
+                "{}",
+                msg
+            );
+            assert!(
+                Note::query(&"globs/a*".into(), &interpreter.conn).is_err(),
+                "{}",
+                msg
+            );
+            assert!(
+                Note::query(&"globs/ab".into(), &interpreter.conn).is_ok(),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "failed script in transaction is rolled back";
+            interpreter.cd(None).unwrap();
+            let script = script.replace("work", "office");
+            assert!(
+                !interpreter.run_script("test", &script, true, true),
+                "{}",
+                msg
+            );
+            assert!(
+                Folder::get(&"office".into(), &interpreter.conn).is_err(),
+                "{}",
+                msg
+            );
+            interpreter.cd(None).unwrap();
+            let script = "mk office/\nmk office/done\n";
+            assert!(
+                interpreter.run_script("test", script, false, true),
+                "{}",
+                msg
+            );
+            assert!(
+                Note::query(&"office/done".into(), &interpreter.conn).is_ok(),
+                "{}",
+                msg
+            );
+        }
     }
 }
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::{
+    db::{
+        models::{Folder, Note, Setting},
+        DbConnection,
+    },
+    template,
+};
+
+const BOOK: &str = "journal.book";
+const TEMPLATE: &str = "journal.template";
+const DEFAULT_BOOK: &str = "/journal";
+const DEFAULT_TEMPLATE: &str = "journal";
+
+pub fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+pub fn configure(
+    book: Option<&Path>,
+    template: Option<&str>,
+    conn: &DbConnection,
+) -> Result<(), String> {
+    if let Some(book) = book {
+        let book = book.to_string_lossy();
+        Setting::set(BOOK, book.trim_end_matches('/'), conn)?;
+    }
+    if let Some(template) = template {
+        template::path(template)?;
+        Setting::set(TEMPLATE, template, conn)?;
+    }
+    Ok(())
+}
+
+fn book(conn: &DbConnection) -> Result<PathBuf, String> {
+    Ok(Setting::get(BOOK, conn)?
+        .unwrap_or_else(|| DEFAULT_BOOK.into())
+        .into())
+}
+
+/// book of entries in month, e.g. `journal/2026/10`.
+fn month_book(year: i32, month: u32, conn: &DbConnection) -> Result<PathBuf, String> {
+    Ok(book(conn)?
+        .join(format!("{:04}", year))
+        .join(format!("{:02}", month)))
+}
+
+/// entry of `date`, e.g. `journal/2026/10/2026-10-18`.
+pub fn entry(date: NaiveDate, conn: &DbConnection) -> Result<PathBuf, String> {
+    Ok(month_book(date.year(), date.month(), conn)?.join(date.format("%Y-%m-%d").to_string()))
+}
+
+/// entry of `date`, made from journal template if missing.
+pub fn open(date: NaiveDate, conn: &DbConnection) -> Result<PathBuf, String> {
+    let note = entry(date, conn)?;
+    match Note::query(&note, conn) {
+        Ok(_) => return Ok(note),
+        Err(e) if e != "NotFound" => return Err(e),
+        Err(_) => {}
+    }
+    let name = Setting::get(TEMPLATE, conn)?.unwrap_or_else(|| DEFAULT_TEMPLATE.into());
+    let vars = [("date".to_string(), date.format("%Y-%m-%d").to_string())];
+    let body = match template::render(&name, &template::variables(&note, &vars)) {
+        Ok(body) => body,
+        Err(e) if e == "NotFound" => String::new(),
+        Err(e) => return Err(e),
+    };
+    if let Some(month) = note.parent() {
+        Folder::make(&month.to_path_buf(), true, conn)?;
+    }
+    Note::make(&note, false, conn)?;
+    Note::update(&note, body, conn)?;
+    Ok(note)
+}
+
+/// days of month with an entry, marked by calendar of termui.
+#[cfg(any(feature = "termui", test))]
+pub fn days(year: i32, month: u32, conn: &DbConnection) -> Result<Vec<u32>, String> {
+    let (_, notes) = match Folder::list(&month_book(year, month, conn)?, conn) {
+        Ok(items) => items,
+        Err(e) if e == "NotFound" => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut days = notes
+        .iter()
+        .filter_map(|note| NaiveDate::parse_from_str(&note.title, "%Y-%m-%d").ok())
+        .filter(|date| date.year() == year && date.month() == month)
+        .map(|date| date.day())
+        .collect::<Vec<_>>();
+    days.sort_unstable();
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::NaiveDate;
+
+    use super::{configure, days, open};
+    use crate::{
+        db::{establish_connection, models::Note},
+        template,
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+
+        {
+            let msg = "entry is made in dated book";
+            let note = open(date, &conn).unwrap();
+            assert_eq!(
+                note.to_string_lossy(),
+                "/journal/2026/10/2026-10-18",
+                "{}",
+                msg
+            );
+            assert_eq!(Note::cat(&note, &conn), Ok("".into()), "{}", msg);
+        }
+
+        {
+            let msg = "existing entry is kept";
+            let note = open(date, &conn).unwrap();
+            Note::update(&note, "written".into(), &conn).unwrap();
+            assert_eq!(open(date, &conn), Ok(note.clone()), "{}", msg);
+            assert_eq!(Note::cat(&note, &conn), Ok("written".into()), "{}", msg);
+        }
+
+        {
+            let msg = "configured book and template are used";
+            let name = format!("journal_{}", uuid::Uuid::new_v4());
+            fs::write(template::path(&name).unwrap(), "# {{date}}").unwrap();
+            configure(Some("/diary/".as_ref()), Some(&name), &conn).unwrap();
+            let date = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+            let note = open(date, &conn).unwrap();
+            assert_eq!(
+                note.to_string_lossy(),
+                "/diary/2026/10/2026-10-01",
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Note::cat(&note, &conn),
+                Ok("# 2026-10-01".into()),
+                "{}",
+                msg
+            );
+            fs::remove_file(template::path(&name).unwrap()).unwrap();
+        }
+
+        {
+            let msg = "days with entries are listed";
+            open(NaiveDate::from_ymd_opt(2026, 10, 5).unwrap(), &conn).unwrap();
+            assert_eq!(days(2026, 10, &conn), Ok(vec![1, 5]), "{}", msg);
+            assert_eq!(days(2026, 11, &conn), Ok(vec![]), "{}", msg);
+        }
+    }
+}
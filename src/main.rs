@@ -6,11 +6,23 @@ extern crate diesel_migrations;
 
 mod commands;
 pub mod db;
+mod export;
+mod find;
+mod import;
 mod interpreter;
+mod journal;
+mod render;
+mod sync;
+mod template;
 
 #[cfg(feature = "termui")]
 mod termui;
 
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
 use commands::Command;
 use structopt::StructOpt;
 
@@ -19,27 +31,87 @@ pub enum SingleCommand {
     #[structopt(flatten)]
     Single(Command),
     Tui,
+    #[structopt(about = "run commands from script file, `-` for stdin")]
+    Run {
+        #[structopt(long, help = "stop at first failing command (default)")]
+        stop_on_error: bool,
+        #[structopt(
+            long = "continue",
+            conflicts_with = "stop-on-error",
+            help = "run remaining commands after a failure"
+        )]
+        keep_going: bool,
+        #[structopt(long, help = "roll back all changes if script fails")]
+        transaction: bool,
+        script: PathBuf,
+    },
+}
+
+fn read_script(script: &Path) -> Result<String, String> {
+    let mut buffer = String::new();
+    if script == Path::new("-") {
+        std::io::stdin()
+            .read_to_string(&mut buffer)
+            .map_err(|e| e.to_string())?;
+    } else {
+        buffer = std::fs::read_to_string(script).map_err(|e| e.to_string())?;
+    }
+    Ok(buffer)
 }
 
 fn main() {
-    let args = ::std::env::args();
+    let mut args = ::std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("-") {
+        // `md_cli -` runs stdin
+        args.insert(1, "run".into());
+    }
     match db::establish_connection() {
         Ok(connection) => {
             if args.len() < 2 {
-                let prompt = format!("{}% ", structopt::clap::crate_name!());
-                interpreter::Interpreter::new(&prompt, connection).run();
+                let prompt = |book: &Path| {
+                    format!("{}:{}% ", structopt::clap::crate_name!(), book.display())
+                };
+                interpreter::Interpreter::new(prompt, connection).run();
             } else {
                 match SingleCommand::from_iter_safe(args.into_iter().skip(1)) {
-                    Ok(SingleCommand::Single(c)) => match c.execute(&connection) {
-                        Err(err) => eprintln!("{}", err),
-                        Ok(msg) => println!("{}", msg),
-                    },
+                    Ok(SingleCommand::Single(mut c)) => {
+                        c.resolve(Path::new("/"));
+                        match c.run_unlocking(&connection) {
+                            Err(err) => eprintln!("{}", err),
+                            Ok(msg) => println!("{}", msg),
+                        }
+                    }
                     Ok(SingleCommand::Tui) => {
                         #[cfg(not(feature = "termui"))]
                         eprintln!("use --features termui to enable tui.");
                         #[cfg(feature = "termui")]
                         termui::AppContext::new(connection).run()
                     }
+                    Ok(SingleCommand::Run {
+                        keep_going,
+                        transaction,
+                        script,
+                        ..
+                    }) => {
+                        let ok = match read_script(&script) {
+                            Ok(lines) => {
+                                interpreter::Interpreter::new(|_| String::new(), connection)
+                                    .run_script(
+                                        &script.to_string_lossy(),
+                                        &lines,
+                                        keep_going,
+                                        transaction,
+                                    )
+                            }
+                            Err(err) => {
+                                eprintln!("{}: {}", script.display(), err);
+                                false
+                            }
+                        };
+                        if !ok {
+                            std::process::exit(1);
+                        }
+                    }
                     Err(e) => match e.kind {
                         structopt::clap::ErrorKind::HelpDisplayed => {
                             eprintln!("{}", e.message);
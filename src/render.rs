@@ -0,0 +1,452 @@
+use std::sync::OnceLock;
+
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::{
+    easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet,
+    util::as_24_bit_terminal_escaped,
+};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "1";
+const ITALIC: &str = "3";
+const UNDERLINE: &str = "4";
+const STRIKE: &str = "9";
+const HEADING: &str = "36";
+const CODE: &str = "33";
+const LINK: &str = "34";
+const FAINT: &str = "2";
+const DEFAULT_WIDTH: usize = 80;
+const THEME: &str = "base16-ocean.dark";
+
+/// syntaxes and themes of highlighting, loaded on first code block.
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+/// width of terminal on stdout, 80 when not a terminal.
+pub fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(width, _)| width.0 as usize)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// markdown rendered with ANSI styles, wrapped to `width`.
+///
+/// links are numbered and listed as footnotes after the text.
+pub fn render(markdown: &str, width: usize) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut renderer = Renderer {
+        width: width.max(20),
+        ..Default::default()
+    };
+    for event in Parser::new_ext(markdown, options) {
+        renderer.event(event);
+    }
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct Table {
+    rows: Vec<Vec<String>>,
+    cell: String,
+}
+
+#[derive(Default)]
+struct Renderer {
+    width: usize,
+    out: String,
+    line: String,
+    line_width: usize,
+    /// continuation prefix of each open block quote and list item
+    prefixes: Vec<String>,
+    /// bullet replacing last prefix on first line of list item
+    bullet: Option<String>,
+    styles: Vec<&'static str>,
+    lists: Vec<Option<u64>>,
+    links: Vec<String>,
+    code: Option<(String, String)>,
+    table: Option<Table>,
+}
+
+impl Renderer {
+    fn prefix(&mut self) -> String {
+        let mut prefix = self.prefixes.concat();
+        if let Some(bullet) = self.bullet.take() {
+            let last = self.prefixes.last().map_or(0, |last| last.len());
+            prefix.truncate(prefix.len() - last);
+            prefix.push_str(&bullet);
+        }
+        prefix
+    }
+
+    fn start_line(&mut self) {
+        if self.line_width == 0 && self.line.is_empty() {
+            let prefix = self.prefix();
+            if !prefix.is_empty() {
+                self.line_width = prefix.chars().count();
+                self.line = format!("{}{}{}", esc(FAINT), prefix, RESET);
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.is_empty() {
+            self.out.push_str(self.line.trim_end());
+            self.out.push('\n');
+        }
+        self.line.clear();
+        self.line_width = 0;
+    }
+
+    fn blank_line(&mut self) {
+        self.end_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            let prefix = self.prefixes.concat();
+            self.out.push_str(prefix.trim_end());
+            self.out.push('\n');
+        }
+    }
+
+    fn styled(&self, text: &str) -> String {
+        if self.styles.is_empty() {
+            return text.into();
+        }
+        format!("{}{}{}", esc(&self.styles.join(";")), text, RESET)
+    }
+
+    /// append text word by word, wrapping at width.
+    fn text(&mut self, text: &str) {
+        if let Some(table) = &mut self.table {
+            table.cell.push_str(text);
+            return;
+        }
+        let mut first = true;
+        for word in text.split(' ') {
+            if !first {
+                self.space();
+            }
+            first = false;
+            if word.is_empty() {
+                continue;
+            }
+            self.start_line();
+            let word_width = word.chars().count();
+            let prefix_width = self.prefixes.concat().chars().count();
+            if self.line_width + word_width > self.width && self.line_width > prefix_width {
+                let trailing = self.line.ends_with(' ');
+                if trailing {
+                    self.line.pop();
+                }
+                self.end_line();
+                self.start_line();
+            }
+            let styled = self.styled(word);
+            self.line.push_str(&styled);
+            self.line_width += word_width;
+        }
+    }
+
+    fn space(&mut self) {
+        if self.line_width > 0 && !self.line.ends_with(' ') && self.line_width < self.width {
+            self.line.push(' ');
+            self.line_width += 1;
+        }
+    }
+
+    fn code_block(&mut self, lang: &str, code: &str) {
+        self.end_line();
+        let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+        let themes = THEMES.get_or_init(ThemeSet::load_defaults);
+        let syntax = syntaxes
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        let mut highlighter = HighlightLines::new(syntax, &themes.themes[THEME]);
+        let prefix = match self.prefixes.concat() {
+            prefix if prefix.is_empty() => prefix,
+            prefix => format!("{}{}{}", esc(FAINT), prefix, RESET),
+        };
+        for line in code.lines() {
+            let highlighted = match highlighter.highlight_line(line, syntaxes) {
+                Ok(ranges) => as_24_bit_terminal_escaped(&ranges, false),
+                Err(_) => line.to_string(),
+            };
+            self.out
+                .push_str(&format!("{}    {}{}\n", prefix, highlighted, RESET));
+        }
+        self.blank_line();
+    }
+
+    fn table(&mut self, table: Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+        let prefix = self.prefixes.concat();
+        for (i, row) in table.rows.iter().enumerate() {
+            let cells = (0..columns)
+                .map(|column| {
+                    let cell = row.get(column).map_or("", String::as_str);
+                    let padded = format!("{:width$}", cell, width = widths[column]);
+                    match i {
+                        0 => format!("{}{}{}", esc(BOLD), padded, RESET),
+                        _ => padded,
+                    }
+                })
+                .collect::<Vec<_>>();
+            self.out
+                .push_str(&format!("{}{}\n", prefix, cells.join(" │ ").trim_end()));
+            if i == 0 {
+                let rule = widths.iter().map(|w| "─".repeat(*w)).collect::<Vec<_>>();
+                self.out
+                    .push_str(&format!("{}{}\n", prefix, rule.join("─┼─")));
+            }
+        }
+        self.blank_line();
+    }
+
+    fn event(&mut self, event: Event) {
+        if let Some((_, code)) = &mut self.code {
+            match event {
+                Event::Text(text) => return code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let (lang, code) = self.code.take().unwrap_or_default();
+                    return self.code_block(&lang, &code);
+                }
+                _ => return,
+            }
+        }
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                self.blank_line();
+                self.styles.push(BOLD);
+                self.styles.push(HEADING);
+                if level == HeadingLevel::H1 {
+                    self.styles.push(UNDERLINE);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                self.styles.clear();
+                self.blank_line();
+            }
+            Event::Start(Tag::Paragraph) => {}
+            Event::End(TagEnd::Paragraph) => match self.lists.is_empty() {
+                true => self.blank_line(),
+                false => self.end_line(),
+            },
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.end_line();
+                self.prefixes.push("│ ".into());
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.end_line();
+                // drop blank line after last paragraph of quote
+                let blank = format!("\n{}\n", self.prefixes.concat().trim_end());
+                if self.out.ends_with(&blank) {
+                    self.out.truncate(self.out.len() - blank.len() + 1);
+                }
+                self.prefixes.pop();
+                self.blank_line();
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.split(',').next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Event::Start(Tag::List(start)) => {
+                self.end_line();
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.end_line();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            Event::Start(Tag::Item) => {
+                self.end_line();
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".into(),
+                };
+                self.prefixes.push(" ".repeat(bullet.chars().count()));
+                self.bullet = Some(bullet);
+            }
+            Event::End(TagEnd::Item) => {
+                self.end_line();
+                self.prefixes.pop();
+                self.bullet = None;
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::Start(Tag::Table(_)) => {
+                self.end_line();
+                self.table = Some(Table::default());
+            }
+            Event::End(TagEnd::Table) => {
+                if let Some(table) = self.table.take() {
+                    self.table(table);
+                }
+            }
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(vec![]);
+                }
+            }
+            Event::End(TagEnd::TableCell) => {
+                if let Some(table) = &mut self.table {
+                    let cell = std::mem::take(&mut table.cell);
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell.trim().to_string());
+                    }
+                }
+            }
+            Event::Start(Tag::Emphasis) => self.styles.push(ITALIC),
+            Event::Start(Tag::Strong) => self.styles.push(BOLD),
+            Event::Start(Tag::Strikethrough) => self.styles.push(STRIKE),
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => {
+                self.links.push(dest_url.to_string());
+                self.styles.push(LINK);
+                self.styles.push(UNDERLINE);
+            }
+            Event::End(TagEnd::Emphasis)
+            | Event::End(TagEnd::Strong)
+            | Event::End(TagEnd::Strikethrough) => {
+                self.styles.pop();
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                self.styles.pop();
+                self.styles.pop();
+                let footnote = format!("[{}]", self.links.len());
+                self.styles.push(FAINT);
+                self.text(&footnote);
+                self.styles.pop();
+            }
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                self.styles.push(CODE);
+                self.text(&code);
+                self.styles.pop();
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            Event::SoftBreak => self.space(),
+            Event::HardBreak => self.end_line(),
+            Event::Rule => {
+                self.end_line();
+                let rule = "─".repeat(self.width.saturating_sub(self.line_width));
+                self.out
+                    .push_str(&format!("{}{}{}\n", esc(FAINT), rule, RESET));
+                self.blank_line();
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.end_line();
+        while self.out.ends_with("\n\n") {
+            self.out.pop();
+        }
+        if !self.links.is_empty() {
+            self.out.push('\n');
+            for (i, link) in self.links.iter().enumerate() {
+                self.out
+                    .push_str(&format!("{}[{}]: {}{}\n", esc(FAINT), i + 1, link, RESET));
+            }
+        }
+        self.out
+    }
+}
+
+fn esc(code: &str) -> String {
+    format!("\x1b[{}m", code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    /// rendered text without ANSI escapes
+    fn plain(text: &str) -> String {
+        let mut plain = String::new();
+        let mut in_escape = false;
+        for c in text.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => {}
+                _ => plain.push(c),
+            }
+        }
+        plain
+    }
+
+    #[test]
+    pub fn integration_test() {
+        {
+            let msg = "headings and emphasis are styled";
+            let output = render("# Title\n\nsome **bold** and *italic*", 80);
+            assert!(
+                output.contains("\x1b[1;36;4mTitle"),
+                "{}: {:?}",
+                msg,
+                output
+            );
+            assert!(output.contains("\x1b[1mbold"), "{}: {:?}", msg, output);
+            assert_eq!(plain(&output), "Title\n\nsome bold and italic\n", "{}", msg);
+        }
+
+        {
+            let msg = "text is wrapped to width";
+            let output = plain(&render("one two three four five six", 20));
+            assert!(
+                output.lines().all(|line| line.chars().count() <= 20),
+                "{}: {}",
+                msg,
+                output
+            );
+            assert_eq!(output.lines().count(), 2, "{}: {}", msg, output);
+        }
+
+        {
+            let msg = "lists and quotes are prefixed";
+            let output = plain(&render("- a\n- b\n\n1. one\n2. two\n\n> quoted", 80));
+            assert_eq!(
+                output, "• a\n• b\n\n1. one\n2. two\n\n│ quoted\n",
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "links are shown as footnotes";
+            let output = plain(&render("see [site](https://example.com)", 80));
+            assert_eq!(
+                output, "see site[1]\n\n[1]: https://example.com\n",
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "code blocks are highlighted and tables aligned";
+            let output = render(
+                "```rust\nfn main() {}\n```\n\n| a | bb |\n|---|---|\n| ccc | d |",
+                80,
+            );
+            assert!(output.contains("\x1b[38;2;"), "{}: {:?}", msg, output);
+            let output = plain(&output);
+            assert!(output.contains("    fn main() {}\n"), "{}: {}", msg, output);
+            assert!(output.contains("a   │ bb\n"), "{}: {}", msg, output);
+            assert!(output.contains("ccc │ d\n"), "{}: {}", msg, output);
+        }
+    }
+}
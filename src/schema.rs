@@ -15,7 +15,66 @@ table! {
     }
 }
 
+table! {
+    operations (id) {
+        id -> Integer,
+        title -> Text,
+        first_seq -> Integer,
+        last_seq -> Integer,
+        undone -> Bool,
+    }
+}
+
+table! {
+    resources (id) {
+        id -> Nullable<Text>,
+        note_id -> Text,
+        title -> Text,
+        mime -> Text,
+        size -> BigInt,
+        sha256 -> Text,
+        blob -> Nullable<Binary>,
+        file_path -> Nullable<Text>,
+    }
+}
+
+table! {
+    settings (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
+table! {
+    sync_items (target, item_id) {
+        target -> Text,
+        item_id -> Text,
+        hash -> Text,
+        rev -> Text,
+    }
+}
+
+table! {
+    tags (note_id, title) {
+        note_id -> Text,
+        title -> Text,
+    }
+}
+
+table! {
+    undo_log (seq) {
+        seq -> Integer,
+        statement -> Text,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
     folders,
     notes,
+    operations,
+    resources,
+    settings,
+    sync_items,
+    tags,
+    undo_log,
 );
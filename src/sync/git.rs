@@ -0,0 +1,653 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{
+    item::{hash, Item, KEY_PREFIX},
+    SyncTarget, EXTENSION,
+};
+use crate::db::models::{Folder, Note, Resource};
+
+const REMOTE: &str = "origin";
+const BRANCH: &str = "main";
+/// file in book directory holding its id
+const BOOK_FILE: &str = ".book";
+/// `<id>/<title>` attachment files with a `.resource` file of their fields
+const RESOURCES: &str = "_resources";
+const RESOURCE_FILE: &str = ".resource";
+/// `<book id>/` holds items pushed before their book
+const ORPHANS: &str = "_orphans";
+/// `<key id>` files holding salt and check value of encryption keys
+const KEYS: &str = "_keys";
+
+/// git repository target keeping the notebook as a markdown tree.
+///
+/// books are directories, notes are `<title>.md` files with their id in front
+/// matter and attachments are kept under `_resources`. bodies and attachments
+/// are written as stored, encrypted ones stay sealed as history can not be
+/// rewritten. changes are committed on each sync and exchanged with remote.
+pub struct GitTarget {
+    dir: PathBuf,
+    remote: Option<String>,
+    /// items by id with their path relative to dir, from last scan
+    items: BTreeMap<String, (PathBuf, Item)>,
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("GitError: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(format!(
+            "GitError: git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// names in dir, none if it does not exist.
+fn entries(dir: &Path) -> Result<Vec<String>, String> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let name = entry.map_err(|e| e.to_string())?.file_name();
+        names.push(name.to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// `key: value` lines.
+fn fields(text: &str) -> BTreeMap<&str, &str> {
+    text.lines()
+        .filter_map(|line| line.split_once(": "))
+        .collect()
+}
+
+fn note_file(id: &str, body: &str) -> String {
+    format!("---\nid: {}\n---\n{}", id, body)
+}
+
+/// id and body of note file.
+fn parse_note_file(text: &str) -> Option<(String, String)> {
+    let rest = text.strip_prefix("---\nid: ")?;
+    let (id, body) = rest.split_once("\n---\n")?;
+    Some((id.to_string(), body.to_string()))
+}
+
+fn write(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, data).map_err(|e| e.to_string())
+}
+
+impl GitTarget {
+    pub fn new(dir: PathBuf, remote: Option<String>) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        if !dir.join(".git").exists() {
+            git(&dir, &["init", "-q"])?;
+            git(
+                &dir,
+                &["symbolic-ref", "HEAD", &format!("refs/heads/{}", BRANCH)],
+            )?;
+        }
+        for (key, value) in &[("user.name", "md_cli"), ("user.email", "md_cli@localhost")] {
+            if git(&dir, &["config", key]).is_err() {
+                git(&dir, &["config", key, value])?;
+            }
+        }
+        if let Some(remote) = &remote {
+            if git(&dir, &["remote", "set-url", REMOTE, remote]).is_err() {
+                git(&dir, &["remote", "add", REMOTE, remote])?;
+            }
+        }
+        Ok(Self {
+            dir,
+            remote,
+            items: BTreeMap::new(),
+        })
+    }
+
+    fn has_commits(&self, rev: &str) -> bool {
+        git(&self.dir, &["rev-parse", "--verify", "-q", rev]).is_ok()
+    }
+
+    /// read items of work tree.
+    fn scan(&mut self) -> Result<(), String> {
+        self.items.clear();
+        self.scan_book(Path::new(""), "")?;
+        for book_id in entries(&self.dir.join(ORPHANS))? {
+            self.scan_book(&Path::new(ORPHANS).join(&book_id), &book_id)?;
+        }
+        for key_id in entries(&self.dir.join(KEYS))? {
+            let path = Path::new(KEYS).join(&key_id);
+            let text = fs::read_to_string(self.dir.join(&path)).map_err(|e| e.to_string())?;
+            let fields = fields(&text);
+            if let (Some(salt), Some(check)) = (fields.get("salt"), fields.get("check")) {
+                let item = Item::Key {
+                    id: format!("{}{}", KEY_PREFIX, key_id),
+                    salt: salt.to_string(),
+                    check: check.to_string(),
+                };
+                self.items.insert(item.id().to_string(), (path, item));
+            }
+        }
+        for id in entries(&self.dir.join(RESOURCES))? {
+            let path = Path::new(RESOURCES).join(&id);
+            if let Some(resource) = self.read_resource(&id, &path)? {
+                self.items.insert(id, (path, resource));
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_book(&mut self, book: &Path, book_id: &str) -> Result<(), String> {
+        for name in entries(&self.dir.join(book))? {
+            if book.as_os_str().is_empty() && [".git", RESOURCES, ORPHANS, KEYS].contains(&&*name) {
+                continue;
+            }
+            let path = book.join(&name);
+            let full = self.dir.join(&path);
+            if full.is_dir() {
+                let id = fs::read_to_string(full.join(BOOK_FILE))
+                    .ok()
+                    .and_then(|text| fields(&text).get("id").map(|id| id.to_string()));
+                // directories without id are not books of notebook
+                if let Some(id) = id {
+                    let folder = Folder::from_raw(id.clone(), name, book_id.into());
+                    self.items
+                        .insert(id.clone(), (path.clone(), Item::Folder(folder)));
+                    self.scan_book(&path, &id)?;
+                }
+            } else if let Some(title) = name.strip_suffix(EXTENSION) {
+                let note = fs::read_to_string(&full)
+                    .ok()
+                    .and_then(|text| parse_note_file(&text));
+                if let Some((id, body)) = note {
+                    let note = Note::from_raw(id.clone(), book_id.into(), title.into(), body);
+                    self.items.insert(id, (path, Item::Note(note)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_resource(&self, id: &str, path: &Path) -> Result<Option<Item>, String> {
+        let dir = self.dir.join(path);
+        let meta = match fs::read_to_string(dir.join(RESOURCE_FILE)) {
+            Ok(meta) => meta,
+            Err(_) => return Ok(None),
+        };
+        let meta = fields(&meta);
+        let title = match entries(&dir)?
+            .into_iter()
+            .find(|name| name != RESOURCE_FILE)
+        {
+            Some(title) => title,
+            None => return Ok(None),
+        };
+        let data = fs::read(dir.join(&title)).map_err(|e| e.to_string())?;
+        let field = |key: &str| meta.get(key).map(|v| v.to_string()).unwrap_or_default();
+        let resource = Resource::from_raw(
+            id.into(),
+            field("note_id"),
+            title,
+            field("mime"),
+            field("size").parse().unwrap_or(data.len() as i64),
+            match meta.get("sha256") {
+                Some(sha256) => sha256.to_string(),
+                None => hex::encode(Sha256::digest(&data)),
+            },
+        );
+        Ok(Some(Item::Resource(resource, data)))
+    }
+
+    fn rev(&self, id: &str) -> Option<String> {
+        self.items.get(id).map(|(_, item)| hash(&item.serialize()))
+    }
+
+    /// directory of book, books not written yet hold their items under
+    /// `_orphans` until they are.
+    fn book_dir(&self, book_id: &str) -> PathBuf {
+        match self.items.get(book_id) {
+            _ if book_id.is_empty() => PathBuf::new(),
+            Some((path, Item::Folder(_))) => path.clone(),
+            _ => Path::new(ORPHANS).join(book_id),
+        }
+    }
+
+    /// move `from` to `to`, paths of items below follow.
+    fn relocate(&mut self, from: &Path, to: &Path) -> Result<(), String> {
+        let target = self.dir.join(to);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::rename(self.dir.join(from), &target).map_err(|e| e.to_string())?;
+        for (path, _) in self.items.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = to.join(rest);
+            }
+        }
+        self.prune(from)
+    }
+
+    /// remove empty directories holding `path`.
+    fn prune(&self, path: &Path) -> Result<(), String> {
+        for dir in path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() || !entries(&self.dir.join(dir))?.is_empty() {
+                break;
+            }
+            fs::remove_dir(self.dir.join(dir)).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// write files of item, returns its path.
+    fn write_item(&mut self, item: &Item) -> Result<PathBuf, String> {
+        let old = self.items.get(item.id()).map(|(path, _)| path.clone());
+        let path = match item {
+            Item::Folder(folder) => self.book_dir(folder.get_parent_id()).join(&folder.title),
+            Item::Note(note) => self
+                .book_dir(note.get_book_id())
+                .join(format!("{}{}", note.title, EXTENSION)),
+            Item::Resource(..) => Path::new(RESOURCES).join(item.id()),
+            Item::Key { .. } => Path::new(KEYS).join(item.title()),
+        };
+        match (&old, item) {
+            (Some(old), Item::Folder(_)) if *old != path => self.relocate(old, &path)?,
+            (Some(old), Item::Note(_)) if *old != path => {
+                fs::remove_file(self.dir.join(old)).map_err(|e| e.to_string())?;
+                self.prune(old)?;
+            }
+            _ => {}
+        }
+        let full = self.dir.join(&path);
+        match item {
+            Item::Folder(folder) => {
+                write(
+                    &full.join(BOOK_FILE),
+                    format!("id: {}\n", folder.get_id()).as_bytes(),
+                )?;
+                let orphans = Path::new(ORPHANS).join(folder.get_id());
+                for name in entries(&self.dir.join(&orphans))? {
+                    self.relocate(&orphans.join(&name), &path.join(&name))?;
+                }
+            }
+            Item::Note(note) => write(
+                &full,
+                note_file(note.get_id(), note.get_raw_body()).as_bytes(),
+            )?,
+            Item::Resource(resource, data) => {
+                if full.exists() {
+                    fs::remove_dir_all(&full).map_err(|e| e.to_string())?;
+                }
+                let meta = format!(
+                    "note_id: {}\nmime: {}\nsize: {}\nsha256: {}\n",
+                    resource.get_note_id(),
+                    resource.mime,
+                    resource.size,
+                    resource.sha256
+                );
+                write(&full.join(RESOURCE_FILE), meta.as_bytes())?;
+                write(&full.join(&resource.title), data)?;
+            }
+            Item::Key { salt, check, .. } => write(
+                &full,
+                format!("salt: {}\ncheck: {}\n", salt, check).as_bytes(),
+            )?,
+        }
+        Ok(path)
+    }
+
+    /// commit message summarising changed notes, None if nothing changed.
+    fn summary(&self) -> Result<Option<String>, String> {
+        let status = git(&self.dir, &["diff", "--cached", "--name-status"])?;
+        let mut changes = 0;
+        let mut notes = vec![];
+        for line in status.lines() {
+            changes += 1;
+            let mut columns = line.split('\t');
+            let action = match columns.next().and_then(|state| state.chars().next()) {
+                Some('A') => "added",
+                Some('D') => "deleted",
+                Some('R') => "moved",
+                _ => "updated",
+            };
+            let path = Path::new(columns.next_back().unwrap_or(""));
+            if path.starts_with(RESOURCES) || path.starts_with(KEYS) {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(title) = name.strip_suffix(EXTENSION) {
+                notes.push(format!("{}: {}", action, title));
+            }
+        }
+        if changes == 0 {
+            return Ok(None);
+        }
+        notes.sort();
+        Ok(Some(format!(
+            "sync: {} files changed, {} notes\n\n{}",
+            changes,
+            notes.len(),
+            notes.join("\n")
+        )))
+    }
+
+    fn commit(&self) -> Result<(), String> {
+        git(&self.dir, &["add", "-A"])?;
+        if let Some(message) = self.summary()? {
+            git(&self.dir, &["commit", "-q", "-m", &message])?;
+        }
+        Ok(())
+    }
+
+    /// item names of conflicting files.
+    fn conflict_items(&mut self, conflicts: &[String]) -> Result<Vec<String>, String> {
+        self.scan()?;
+        let mut names = vec![];
+        for conflict in conflicts {
+            let conflict = Path::new(conflict);
+            let find = |matches: &dyn Fn(&Path) -> bool| {
+                self.items
+                    .iter()
+                    .find(|(_, (path, _))| matches(path))
+                    .map(|(id, _)| format!("{}{}", id, EXTENSION))
+            };
+            // note files are items, book and resource files are in their item
+            if let Some(name) = find(&|path| path == conflict)
+                .or_else(|| find(&|path| conflict.parent() == Some(path)))
+            {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+impl SyncTarget for GitTarget {
+    fn key(&self) -> String {
+        format!("git:{}", self.dir.to_string_lossy())
+    }
+
+    fn prepare(&mut self) -> Result<Vec<String>, String> {
+        // left over by an interrupted sync
+        self.commit()?;
+        let remote_branch = format!("{}/{}", REMOTE, BRANCH);
+        if self.remote.is_none() {
+            return Ok(vec![]);
+        }
+        git(&self.dir, &["fetch", "-q", REMOTE])?;
+        if !self.has_commits(&remote_branch) {
+            return Ok(vec![]);
+        }
+        if !self.has_commits("HEAD") {
+            git(&self.dir, &["reset", "-q", "--hard", &remote_branch])?;
+            return Ok(vec![]);
+        }
+        let merge = git(
+            &self.dir,
+            &[
+                "merge",
+                "-q",
+                "--no-edit",
+                "--allow-unrelated-histories",
+                &remote_branch,
+            ],
+        );
+        if merge.is_ok() {
+            return Ok(vec![]);
+        }
+        // take their side, sync then sees our side as changed and keeps a conflict copy
+        let conflicts = git(&self.dir, &["diff", "--name-only", "--diff-filter=U"])?
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if conflicts.is_empty() {
+            return merge.map(|_| vec![]);
+        }
+        for name in &conflicts {
+            // deleted on their side
+            if git(&self.dir, &["checkout", "--theirs", "--", name]).is_err() {
+                git(&self.dir, &["rm", "-q", "--", name])?;
+            } else {
+                git(&self.dir, &["add", "--", name])?;
+            }
+        }
+        git(&self.dir, &["commit", "-q", "--no-edit"])?;
+        self.conflict_items(&conflicts)
+    }
+
+    fn list(&mut self) -> Result<BTreeMap<String, String>, String> {
+        self.scan()?;
+        Ok(self
+            .items
+            .iter()
+            .map(|(id, (_, item))| (format!("{}{}", id, EXTENSION), hash(&item.serialize())))
+            .collect())
+    }
+
+    fn get(&mut self, name: &str) -> Result<(Vec<u8>, String), String> {
+        let id = name.strip_suffix(EXTENSION).unwrap_or(name);
+        let (_, item) = self.items.get(id).ok_or_else(|| "NotFound".to_string())?;
+        let data = item.serialize();
+        let rev = hash(&data);
+        Ok((data, rev))
+    }
+
+    fn put(&mut self, name: &str, data: &[u8], expected: Option<&str>) -> Result<String, String> {
+        let id = name.strip_suffix(EXTENSION).unwrap_or(name);
+        if self.rev(id).as_deref() != expected {
+            return Err("RemoteChanged".into());
+        }
+        let item = Item::parse(data)?;
+        let path = self.write_item(&item)?;
+        let rev = hash(&item.serialize());
+        self.items.insert(id.to_string(), (path, item));
+        Ok(rev)
+    }
+
+    fn delete(&mut self, name: &str, expected: Option<&str>) -> Result<(), String> {
+        let id = name.strip_suffix(EXTENSION).unwrap_or(name);
+        if self.rev(id).as_deref() != expected {
+            return Err("RemoteChanged".into());
+        }
+        if let Some((path, item)) = self.items.remove(id) {
+            let full = self.dir.join(&path);
+            match item {
+                // items left in book are deleted by their own delete
+                Item::Folder(_) => {
+                    fs::remove_file(full.join(BOOK_FILE)).map_err(|e| e.to_string())?;
+                    if entries(&full)?.is_empty() {
+                        fs::remove_dir(&full).map_err(|e| e.to_string())?;
+                    }
+                }
+                Item::Note(_) | Item::Key { .. } => {
+                    fs::remove_file(&full).map_err(|e| e.to_string())?
+                }
+                Item::Resource(..) => fs::remove_dir_all(&full).map_err(|e| e.to_string())?,
+            }
+            self.prune(&path)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.commit()?;
+        if self.remote.is_some() && self.has_commits("HEAD") {
+            git(
+                &self.dir,
+                &["push", "-q", REMOTE, &format!("HEAD:{}", BRANCH)],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{git, GitTarget};
+    use crate::{
+        db::{
+            crypto, establish_connection,
+            models::{Folder, Note, Resource},
+        },
+        sync::sync,
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("git_sync_{}", uuid::Uuid::new_v4()));
+        let remote = dir.join("remote.git");
+        std::fs::create_dir_all(&remote).unwrap();
+        git(&remote, &["init", "-q", "--bare"]).unwrap();
+        let remote = Some(remote.to_string_lossy().to_string());
+        let first = establish_connection().expect("connection or migration failed!");
+        let second = establish_connection().expect("connection or migration failed!");
+        let mut first_repo = GitTarget::new(dir.join("first"), remote.clone()).unwrap();
+        let mut second_repo = GitTarget::new(dir.join("second"), remote).unwrap();
+        let last_message = |repo: &Path| git(repo, &["log", "-1", "--format=%B"]).unwrap();
+
+        {
+            let msg = "notes are committed, pushed and imported";
+            Folder::make(&"book".into(), false, &first).unwrap();
+            Note::make(&"book/note".into(), false, &first).unwrap();
+            Note::update(&"book/note".into(), "first".into(), &first).unwrap();
+            let report = sync(&mut first_repo, &first).unwrap();
+            assert_eq!(report.pushed, 2, "{}: {}", msg, report);
+            let message = last_message(&dir.join("first"));
+            assert!(message.contains("added: note"), "{}: {}", msg, message);
+            let report = sync(&mut second_repo, &second).unwrap();
+            assert_eq!(report.pulled, 2, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/note".into(), &second),
+                Ok("first".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "repository holds notebook as markdown tree";
+            let id = Note::query(&"book/note".into(), &first)
+                .unwrap()
+                .get_id()
+                .to_string();
+            let read = |path: &str| std::fs::read_to_string(dir.join("first").join(path)).ok();
+            assert_eq!(
+                read("book/note.md"),
+                Some(format!("---\nid: {}\n---\nfirst", id)),
+                "{}",
+                msg
+            );
+            Folder::make(&"book/sub".into(), false, &first).unwrap();
+            Note::make(&"book/sub/deep".into(), false, &first).unwrap();
+            let file = dir.join("attachment.txt");
+            std::fs::write(&file, "data").unwrap();
+            let resource = Resource::attach(&"book/sub/deep".into(), &file, &first).unwrap();
+            sync(&mut first_repo, &first).unwrap();
+            assert!(read("book/sub/deep.md").is_some(), "{}", msg);
+            assert_eq!(
+                read(&format!("_resources/{}/attachment.txt", resource.get_id())),
+                Some("data".into()),
+                "{}",
+                msg
+            );
+            assert!(!dir.join("first/_orphans").exists(), "{}", msg);
+
+            Note::move_note(
+                &"book/sub/deep".into(),
+                &"book".into(),
+                false,
+                false,
+                &first,
+            )
+            .unwrap();
+            sync(&mut first_repo, &first).unwrap();
+            assert!(read("book/deep.md").is_some(), "{}", msg);
+            assert!(read("book/sub/deep.md").is_none(), "{}", msg);
+            let message = last_message(&dir.join("first"));
+            assert!(message.contains("moved: deep"), "{}: {}", msg, message);
+            sync(&mut second_repo, &second).unwrap();
+            assert_eq!(
+                Resource::list(&"book/deep".into(), &second).map(|r| r.len()),
+                Ok(1),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "edits on both sides produce conflict copy";
+            Note::update(&"book/note".into(), "first edit".into(), &first).unwrap();
+            Note::update(&"book/note".into(), "second edit".into(), &second).unwrap();
+            sync(&mut first_repo, &first).unwrap();
+            let report = sync(&mut second_repo, &second).unwrap();
+            assert_eq!(report.conflicts, 1, "{}: {}", msg, report);
+            sync(&mut first_repo, &first).unwrap();
+            assert_eq!(
+                Note::cat(&"book/note (conflict)".into(), &first),
+                Ok("second edit".into()),
+                "{}",
+                msg
+            );
+            let message = last_message(&dir.join("second"));
+            assert!(
+                message.contains("added: note (conflict)"),
+                "{}: {}",
+                msg,
+                message
+            );
+        }
+
+        {
+            let msg = "encrypted notes and attachments stay sealed in repository";
+            Folder::make(&"secret".into(), false, &first).unwrap();
+            crypto::encrypt_book(&"secret/".into(), "passphrase", &first).unwrap();
+            Note::make(&"secret/plan".into(), false, &first).unwrap();
+            Note::update(&"secret/plan".into(), "hidden words".into(), &first).unwrap();
+            let file = dir.join("hidden.txt");
+            std::fs::write(&file, "hidden bytes").unwrap();
+            Resource::attach(&"secret/plan".into(), &file, &first).unwrap();
+            sync(&mut first_repo, &first).unwrap();
+            let mut contents = git(&dir.join("first"), &["log", "-p", "--all"]).unwrap();
+            let mut dirs = vec![dir.join("first")];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(dir).unwrap() {
+                    let path = entry.unwrap().path();
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else {
+                        contents.push_str(&String::from_utf8_lossy(&std::fs::read(path).unwrap()));
+                    }
+                }
+            }
+            assert!(contents.contains("secret/plan.md"), "{}", msg);
+            assert!(!contents.contains("hidden words"), "{}", msg);
+            assert!(!contents.contains("hidden bytes"), "{}", msg);
+            assert_eq!(
+                Note::cat(&"secret/plan".into(), &first),
+                Ok("hidden words".into()),
+                "{}",
+                msg
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
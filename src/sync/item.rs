@@ -0,0 +1,208 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::db::{
+    crypto,
+    models::{Folder, Note, Resource},
+    schema::notes,
+    DbConnection,
+};
+
+/// ids of key items are `key-<key id>`
+pub(super) const KEY_PREFIX: &str = "key-";
+
+/// synced row, serialized as `key: value` header lines, empty line and body.
+///
+/// note bodies and resource data are synced as stored, so encrypted items
+/// stay encrypted on target. salt and check value of encryption keys are
+/// synced too, so other databases derive the keys from the same passphrases.
+pub enum Item {
+    Folder(Folder),
+    Note(Note),
+    Resource(Resource, Vec<u8>),
+    Key {
+        id: String,
+        salt: String,
+        check: String,
+    },
+}
+
+impl Item {
+    pub fn all(conn: &DbConnection) -> Result<Vec<Self>, String> {
+        let mut items = vec![];
+        items.extend(Folder::all(conn)?.into_iter().map(Item::Folder));
+        items.extend(Note::all(conn)?.into_iter().map(Item::Note));
+        for resource in Resource::all(conn)? {
+            let data = resource.read_stored()?;
+            items.push(Item::Resource(resource, data));
+        }
+        for (key_id, salt, check) in crypto::stored_keys(conn)? {
+            items.push(Item::Key {
+                id: format!("{}{}", KEY_PREFIX, key_id),
+                salt,
+                check,
+            });
+        }
+        Ok(items)
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Item::Folder(folder) => folder.get_id(),
+            Item::Note(note) => note.get_id(),
+            Item::Resource(resource, _) => resource.get_id(),
+            Item::Key { id, .. } => id,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            Item::Folder(folder) => &folder.title,
+            Item::Note(note) => &note.title,
+            Item::Resource(resource, _) => &resource.title,
+            Item::Key { id, .. } => id.strip_prefix(KEY_PREFIX).unwrap_or(id),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let (header, body) = match self {
+            Item::Folder(folder) => (
+                vec![
+                    ("type", "folder".to_string()),
+                    ("parent_id", folder.get_parent_id().to_string()),
+                ],
+                String::new(),
+            ),
+            Item::Note(note) => (
+                vec![
+                    ("type", "note".to_string()),
+                    ("parent_id", note.get_book_id().to_string()),
+                ],
+                note.get_raw_body().to_string(),
+            ),
+            Item::Resource(resource, data) => (
+                vec![
+                    ("type", "resource".to_string()),
+                    ("note_id", resource.get_note_id().to_string()),
+                    ("mime", resource.mime.clone()),
+                    ("size", resource.size.to_string()),
+                    ("sha256", resource.sha256.clone()),
+                ],
+                STANDARD.encode(data),
+            ),
+            Item::Key { salt, check, .. } => (
+                vec![
+                    ("type", "key".to_string()),
+                    ("salt", salt.clone()),
+                    ("check", check.clone()),
+                ],
+                String::new(),
+            ),
+        };
+        let mut output = format!("id: {}\ntitle: {}\n", self.id(), self.title());
+        for (key, value) in header {
+            output.push_str(&format!("{}: {}\n", key, value));
+        }
+        output.push('\n');
+        output.push_str(&body);
+        output.into_bytes()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let data = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+        let (header, body) = data.split_once("\n\n").unwrap_or((data, ""));
+        let header = header
+            .lines()
+            .filter_map(|line| line.split_once(": ").or_else(|| line.split_once(':')))
+            .collect::<std::collections::HashMap<_, _>>();
+        let field = |key: &str| {
+            header
+                .get(key)
+                .map(|v| v.to_string())
+                .ok_or_else(|| format!("MissingField: {}", key))
+        };
+        match header.get("type").copied() {
+            Some("folder") => Ok(Item::Folder(Folder::from_raw(
+                field("id")?,
+                field("title")?,
+                field("parent_id")?,
+            ))),
+            Some("note") => Ok(Item::Note(Note::from_raw(
+                field("id")?,
+                field("parent_id")?,
+                field("title")?,
+                body.to_string(),
+            ))),
+            Some("resource") => Ok(Item::Resource(
+                Resource::from_raw(
+                    field("id")?,
+                    field("note_id")?,
+                    field("title")?,
+                    field("mime")?,
+                    field("size")?
+                        .parse()
+                        .map_err(|_| "InvalidSize".to_string())?,
+                    field("sha256")?,
+                ),
+                STANDARD.decode(body.trim()).map_err(|e| e.to_string())?,
+            )),
+            Some("key") => Ok(Item::Key {
+                id: field("id")?,
+                salt: field("salt")?,
+                check: field("check")?,
+            }),
+            _ => Err("UnknownItemType".into()),
+        }
+    }
+
+    /// save item, plain bodies and data pulled into encrypted books are
+    /// encrypted first.
+    pub fn save(&mut self, conn: &DbConnection) -> Result<usize, String> {
+        match self {
+            Item::Note(note) if crypto::key_id(note.get_raw_body().as_bytes()).is_none() => {
+                let body = crypto::seal(note.get_raw_body().into(), note.get_book_id(), conn)?;
+                *note = Note::from_raw(
+                    note.get_id().into(),
+                    note.get_book_id().into(),
+                    note.title.clone(),
+                    body,
+                );
+            }
+            Item::Resource(resource, data) if crypto::key_id(data).is_none() => {
+                let book_id = notes::table
+                    .find(resource.get_note_id())
+                    .select(notes::parent_id)
+                    .first::<String>(conn)
+                    .optional()
+                    .map_err(|e| e.to_string())?
+                    .unwrap_or_default();
+                *data = crypto::seal_bytes(std::mem::take(data), &book_id, conn)?;
+            }
+            _ => {}
+        }
+        match self {
+            Item::Folder(folder) => folder.save_raw(conn),
+            Item::Note(note) => note.save_raw(conn),
+            Item::Resource(resource, data) => resource.save_raw(data.clone(), conn),
+            Item::Key { id, salt, check } => {
+                let key_id = id.strip_prefix(KEY_PREFIX).unwrap_or(id);
+                crypto::save_stored_key(key_id, salt, check, conn)
+            }
+        }
+    }
+
+    pub fn delete(&self, conn: &DbConnection) -> Result<usize, String> {
+        match self {
+            Item::Folder(folder) => Folder::delete_by_id(folder.get_id(), conn),
+            Item::Note(note) => Note::delete_by_id(note.get_id(), conn),
+            Item::Resource(resource, _) => Resource::detach(resource.get_id(), conn),
+            // data sealed with key could no longer be opened
+            Item::Key { .. } => Err("KeyKept".into()),
+        }
+    }
+}
+
+pub fn hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
@@ -0,0 +1,72 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use super::{item::hash, SyncTarget};
+
+/// directory target, e.g. a shared mount or a syncthing folder.
+///
+/// revision of an item is the hash of its file.
+pub struct LocalTarget {
+    dir: PathBuf,
+}
+
+impl LocalTarget {
+    pub fn new(dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(Self { dir })
+    }
+
+    fn rev(&self, name: &str) -> Result<Option<String>, String> {
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(hash(&fs::read(path).map_err(|e| e.to_string())?)))
+    }
+
+    fn check_rev(&self, name: &str, expected: Option<&str>) -> Result<(), String> {
+        if self.rev(name)?.as_deref() != expected {
+            return Err("RemoteChanged".into());
+        }
+        Ok(())
+    }
+}
+
+impl SyncTarget for LocalTarget {
+    fn key(&self) -> String {
+        format!("local:{}", self.dir.to_string_lossy())
+    }
+
+    fn list(&mut self) -> Result<BTreeMap<String, String>, String> {
+        let mut items = BTreeMap::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| e.to_string())? {
+            let name = entry.map_err(|e| e.to_string())?.file_name();
+            let name = name.to_string_lossy();
+            if name.ends_with(super::EXTENSION) {
+                if let Some(rev) = self.rev(&name)? {
+                    items.insert(name.to_string(), rev);
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    fn get(&mut self, name: &str) -> Result<(Vec<u8>, String), String> {
+        let data = fs::read(self.dir.join(name)).map_err(|e| e.to_string())?;
+        let rev = hash(&data);
+        Ok((data, rev))
+    }
+
+    fn put(&mut self, name: &str, data: &[u8], expected: Option<&str>) -> Result<String, String> {
+        self.check_rev(name, expected)?;
+        // write then rename, so readers never see half written items
+        let temp = self.dir.join(format!(".{}.tmp", name));
+        fs::write(&temp, data).map_err(|e| e.to_string())?;
+        fs::rename(&temp, self.dir.join(name)).map_err(|e| e.to_string())?;
+        Ok(hash(data))
+    }
+
+    fn delete(&mut self, name: &str, expected: Option<&str>) -> Result<(), String> {
+        self.check_rev(name, expected)?;
+        fs::remove_file(self.dir.join(name)).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    commands::read_passphrase,
+    db::{
+        crypto,
+        models::{Folder, Note, Resource, Setting, SyncItem},
+        DbConnection,
+    },
+};
+
+use self::item::{hash, Item};
+
+mod git;
+mod item;
+mod local;
+mod webdav;
+
+pub use git::GitTarget;
+pub use local::LocalTarget;
+pub use webdav::WebDavTarget;
+
+/// items are stored on target as `<id>.md`
+pub const EXTENSION: &str = ".md";
+const BACKEND: &str = "sync.backend";
+const TARGET: &str = "sync.target";
+const USER: &str = "sync.user";
+pub(crate) const PASSWORD: &str = "sync.password";
+const REMOTE: &str = "sync.remote";
+
+/// place items are mirrored to.
+///
+/// revisions are opaque to the sync engine, they only need to change
+/// whenever item changes on target.
+pub trait SyncTarget {
+    /// identifies target in sync state
+    fn key(&self) -> String;
+
+    /// item names with their revisions
+    fn list(&mut self) -> Result<BTreeMap<String, String>, String>;
+
+    /// item content and its revision
+    fn get(&mut self, name: &str) -> Result<(Vec<u8>, String), String>;
+
+    /// write item if its revision is still expected (None if it should not exist),
+    /// returns new revision.
+    fn put(&mut self, name: &str, data: &[u8], expected: Option<&str>) -> Result<String, String>;
+
+    /// delete item if its revision is still expected.
+    fn delete(&mut self, name: &str, expected: Option<&str>) -> Result<(), String>;
+
+    /// called before sync, returns items whose local changes must not be
+    /// assumed synced (e.g. merged over on target).
+    fn prepare(&mut self) -> Result<Vec<String>, String> {
+        Ok(vec![])
+    }
+
+    /// called after sync
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SyncReport {
+    pub pushed: usize,
+    pub pulled: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts: usize,
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pushed: {}, pulled: {}, deleted local: {}, deleted remote: {}, conflicts: {}",
+            self.pushed, self.pulled, self.deleted_local, self.deleted_remote, self.conflicts
+        )?;
+        for error in &self.errors {
+            write!(f, "\n{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+struct Sync<'a> {
+    target: &'a mut dyn SyncTarget,
+    key: String,
+    report: SyncReport,
+    /// books deleted on target, deleted after the items in them
+    deleted_books: Vec<Item>,
+    conn: &'a DbConnection,
+}
+
+impl<'a> Sync<'a> {
+    fn pass(&mut self) -> Result<(), String> {
+        let mut local = Item::all(self.conn)?
+            .into_iter()
+            .map(|item| (item.id().to_string(), item))
+            .collect::<BTreeMap<_, _>>();
+        let remote = self
+            .target
+            .list()?
+            .into_iter()
+            .filter_map(|(name, rev)| Some((name.strip_suffix(EXTENSION)?.to_string(), rev)))
+            .collect::<BTreeMap<_, _>>();
+        let state = SyncItem::load(&self.key, self.conn)?;
+        let ids = local
+            .keys()
+            .chain(remote.keys())
+            .chain(state.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for id in ids {
+            let result = self.item(&id, local.remove(&id), remote.get(&id), state.get(&id));
+            if let Err(err) = result {
+                self.report.errors.push(format!("{}: {}", id, err));
+            }
+        }
+        self.delete_books();
+        Ok(())
+    }
+
+    /// delete books deleted on target once empty, books still holding local
+    /// items are pushed again.
+    fn delete_books(&mut self) {
+        let mut books = std::mem::take(&mut self.deleted_books);
+        loop {
+            let count = books.len();
+            let mut kept = vec![];
+            for book in books {
+                match Folder::is_empty_by_id(book.id(), self.conn) {
+                    Ok(true) => {
+                        if let Err(err) = self.delete_local(&book) {
+                            self.report.errors.push(format!("{}: {}", book.id(), err));
+                        }
+                    }
+                    Ok(false) => kept.push(book),
+                    Err(err) => self.report.errors.push(format!("{}: {}", book.id(), err)),
+                }
+            }
+            books = kept;
+            if books.len() == count {
+                break;
+            }
+        }
+        for book in books {
+            let name = format!("{}{}", book.id(), EXTENSION);
+            if let Err(err) = self.push(&name, &book, None) {
+                self.report.errors.push(format!("{}: {}", book.id(), err));
+            }
+        }
+    }
+
+    fn delete_local(&mut self, item: &Item) -> Result<(), String> {
+        item.delete(self.conn)?;
+        SyncItem::remove(&self.key, item.id(), self.conn)?;
+        self.report.deleted_local += 1;
+        Ok(())
+    }
+
+    fn item(
+        &mut self,
+        id: &str,
+        local: Option<Item>,
+        remote: Option<&String>,
+        state: Option<&(String, String)>,
+    ) -> Result<(), String> {
+        let name = format!("{}{}", id, EXTENSION);
+        match (local, remote, state) {
+            (Some(item), None, None) => self.push(&name, &item, None),
+            (None, Some(_), None) => self.pull(&name, None),
+            (Some(item), Some(_), None) => self.merge(&name, item),
+            (Some(item), Some(rev), Some((last_hash, last_rev))) => {
+                let local_changed = hash(&item.serialize()) != *last_hash;
+                match (local_changed, rev != last_rev) {
+                    (false, false) => Ok(()),
+                    (true, false) => self.push(&name, &item, Some(last_rev)),
+                    (false, true) => self.pull(&name, None),
+                    (true, true) => self.merge(&name, item),
+                }
+            }
+            (Some(item), None, Some((last_hash, _))) => {
+                if hash(&item.serialize()) != *last_hash || matches!(item, Item::Key { .. }) {
+                    // changed here after deleted there, keep it, keys are
+                    // never deleted
+                    self.push(&name, &item, None)
+                } else if let Item::Folder(_) = item {
+                    self.deleted_books.push(item);
+                    Ok(())
+                } else {
+                    self.delete_local(&item)
+                }
+            }
+            (None, Some(rev), Some((_, last_rev))) => {
+                if rev == last_rev {
+                    self.target.delete(&name, Some(rev))?;
+                    SyncItem::remove(&self.key, id, self.conn)?;
+                    self.report.deleted_remote += 1;
+                    Ok(())
+                } else {
+                    // changed there after deleted here, keep it
+                    self.pull(&name, None)
+                }
+            }
+            (None, None, _) => SyncItem::remove(&self.key, id, self.conn).map(|_| ()),
+        }
+    }
+
+    fn push(&mut self, name: &str, item: &Item, expected: Option<&str>) -> Result<(), String> {
+        let data = item.serialize();
+        let rev = self.target.put(name, &data, expected)?;
+        SyncItem::save(&self.key, item.id(), &hash(&data), &rev, self.conn)?;
+        self.report.pushed += 1;
+        Ok(())
+    }
+
+    fn pull(&mut self, name: &str, fetched: Option<(Vec<u8>, String)>) -> Result<(), String> {
+        let (data, rev) = match fetched {
+            Some(fetched) => fetched,
+            None => self.target.get(name)?,
+        };
+        let mut item = Item::parse(&data)?;
+        item.save(self.conn)?;
+        // saved item may differ (renamed on title clash), next sync pushes it
+        SyncItem::save(
+            &self.key,
+            item.id(),
+            &hash(&item.serialize()),
+            &rev,
+            self.conn,
+        )?;
+        self.report.pulled += 1;
+        Ok(())
+    }
+
+    /// both sides changed, remote wins. local notes and attachments are kept
+    /// as conflict copies, a book only keeps its remote title and place.
+    fn merge(&mut self, name: &str, item: Item) -> Result<(), String> {
+        let (data, rev) = self.target.get(name)?;
+        let local_hash = hash(&item.serialize());
+        if hash(&data) == local_hash {
+            SyncItem::save(&self.key, item.id(), &local_hash, &rev, self.conn)?;
+            return Ok(());
+        }
+        match item {
+            // data sealed on one side could not be opened on the other
+            Item::Key { .. } => return Err("KeyMismatch".into()),
+            Item::Note(note) => {
+                Note::from_raw(
+                    Uuid::new_v4().to_string(),
+                    note.get_book_id().to_string(),
+                    format!("{} (conflict)", note.title),
+                    note.get_raw_body().to_string(),
+                )
+                .save_raw(self.conn)?;
+            }
+            Item::Resource(resource, stored) => {
+                Resource::from_raw(
+                    Uuid::new_v4().to_string(),
+                    resource.get_note_id().to_string(),
+                    format!("{} (conflict)", resource.title),
+                    resource.mime.clone(),
+                    resource.size,
+                    resource.sha256.clone(),
+                )
+                .save_raw(stored, self.conn)?;
+            }
+            Item::Folder(folder) => self.report.errors.push(format!(
+                "{}: book {} changed on both sides, remote kept",
+                folder.get_id(),
+                folder.title
+            )),
+        }
+        self.report.conflicts += 1;
+        self.pull(name, Some((data, rev)))
+    }
+}
+
+/// mirror database to target, changes on both sides are merged.
+pub fn sync(target: &mut dyn SyncTarget, conn: &DbConnection) -> Result<SyncReport, String> {
+    let key = target.key();
+    for name in target.prepare()? {
+        if let Some(id) = name.strip_suffix(EXTENSION) {
+            SyncItem::invalidate(&key, id, conn)?;
+        }
+    }
+    let mut sync = Sync {
+        key,
+        target,
+        report: SyncReport::default(),
+        deleted_books: vec![],
+        conn,
+    };
+    sync.pass()?;
+    if sync.report.conflicts > 0 {
+        // push conflict copies made by first pass
+        sync.pass()?;
+    }
+    sync.target.finish()?;
+    Ok(sync.report)
+}
+
+pub fn configure_local(dir: &PathBuf, conn: &DbConnection) -> Result<(), String> {
+    let dir = std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join(dir);
+    Setting::set(BACKEND, "local", conn)?;
+    Setting::set(TARGET, &dir.to_string_lossy(), conn)?;
+    Ok(())
+}
+
+/// password is only kept sealed with master key, without encryption it is
+/// asked on sync. returns whether password was stored.
+pub fn configure_webdav(
+    url: &str,
+    user: Option<&str>,
+    password: Option<&str>,
+    conn: &DbConnection,
+) -> Result<bool, String> {
+    Setting::set(BACKEND, "webdav", conn)?;
+    Setting::set(TARGET, url, conn)?;
+    match user {
+        Some(user) => Setting::set(USER, user, conn)?,
+        None => Setting::unset(USER, conn)?,
+    };
+    match password {
+        Some(password) => crypto::set_secret(PASSWORD, password, conn),
+        None => Setting::unset(PASSWORD, conn).map(|_| false),
+    }
+}
+
+pub fn configure_git(
+    dir: &PathBuf,
+    remote: Option<&str>,
+    conn: &DbConnection,
+) -> Result<(), String> {
+    let dir = std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join(dir);
+    Setting::set(BACKEND, "git", conn)?;
+    Setting::set(TARGET, &dir.to_string_lossy(), conn)?;
+    match remote {
+        Some(remote) => Setting::set(REMOTE, remote, conn)?,
+        None => Setting::unset(REMOTE, conn)?,
+    };
+    Ok(())
+}
+
+/// target configured with `sync config`
+pub fn configured_target(conn: &DbConnection) -> Result<Box<dyn SyncTarget>, String> {
+    let target = Setting::get(TARGET, conn)?.ok_or_else(|| "SyncNotConfigured".to_string())?;
+    match Setting::get(BACKEND, conn)?.as_deref() {
+        Some("local") => Ok(Box::new(LocalTarget::new(target.into())?)),
+        Some("git") => Ok(Box::new(GitTarget::new(
+            target.into(),
+            Setting::get(REMOTE, conn)?,
+        )?)),
+        Some("webdav") => {
+            let user = Setting::get(USER, conn)?;
+            let password = match (&user, crypto::get_secret(PASSWORD, conn)?) {
+                (Some(_), None) => Some(read_passphrase("webdav password: ")?),
+                (_, password) => password,
+            };
+            Ok(Box::new(WebDavTarget::new(
+                &target,
+                user.as_deref(),
+                password.as_deref(),
+            )))
+        }
+        _ => Err("SyncNotConfigured".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        crypto, establish_connection,
+        models::{Folder, Note, Resource},
+    };
+    use sha2::{Digest, Sha256};
+
+    use super::{sync, LocalTarget};
+
+    #[test]
+    pub fn integration_test() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("sync_{}", uuid::Uuid::new_v4()));
+        let first = establish_connection().expect("connection or migration failed!");
+        let second = establish_connection().expect("connection or migration failed!");
+        let mut target = LocalTarget::new(dir.clone()).unwrap();
+
+        {
+            let msg = "items are pushed and pulled";
+            Folder::make(&"book".into(), false, &first).unwrap();
+            Note::make(&"book/note".into(), false, &first).unwrap();
+            Note::update(&"book/note".into(), "first".into(), &first).unwrap();
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.pushed, 2, "{}: {}", msg, report);
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.pulled, 2, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/note".into(), &second),
+                Ok("first".into()),
+                "{}",
+                msg
+            );
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.pushed + report.pulled, 0, "{}: {}", msg, report);
+        }
+
+        {
+            let msg = "concurrent edits produce conflict copy";
+            Note::update(&"book/note".into(), "first edit".into(), &first).unwrap();
+            Note::update(&"book/note".into(), "second edit".into(), &second).unwrap();
+            sync(&mut target, &first).unwrap();
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.conflicts, 1, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/note".into(), &second),
+                Ok("first edit".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Note::cat(&"book/note (conflict)".into(), &second),
+                Ok("second edit".into()),
+                "{}",
+                msg
+            );
+            sync(&mut target, &first).unwrap();
+            assert_eq!(
+                Note::cat(&"book/note (conflict)".into(), &first),
+                Ok("second edit".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "deletes are mirrored";
+            Note::delete(&"book/note".into(), &first).unwrap();
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.deleted_remote, 1, "{}: {}", msg, report);
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.deleted_local, 1, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/note".into(), &second),
+                Err("NotFound".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "book deleted on target is kept while it holds local notes";
+            Note::make(&"gone/old".into(), true, &first).unwrap();
+            sync(&mut target, &first).unwrap();
+            sync(&mut target, &second).unwrap();
+            Folder::delete(&"gone/".into(), true, &second).unwrap();
+            sync(&mut target, &second).unwrap();
+            Note::make(&"gone/new".into(), false, &first).unwrap();
+            let report = sync(&mut target, &first).unwrap();
+            assert!(report.errors.is_empty(), "{}: {}", msg, report);
+            assert!(Note::query(&"gone/old".into(), &first).is_err(), "{}", msg);
+            assert!(Note::query(&"gone/new".into(), &first).is_ok(), "{}", msg);
+            sync(&mut target, &second).unwrap();
+            assert!(Note::query(&"gone/new".into(), &second).is_ok(), "{}", msg);
+        }
+
+        {
+            let msg = "attachments changed on both sides keep a conflict copy";
+            let file = dir.with_extension("txt");
+            std::fs::write(&file, "data").unwrap();
+            let id = Resource::attach(&"book/note (conflict)".into(), &file, &first)
+                .unwrap()
+                .get_id()
+                .to_string();
+            sync(&mut target, &first).unwrap();
+            sync(&mut target, &second).unwrap();
+            for (conn, data) in [(&first, "first"), (&second, "second")] {
+                let resource = Resource::get(&id, conn).unwrap();
+                Resource::from_raw(
+                    id.clone(),
+                    resource.get_note_id().into(),
+                    resource.title,
+                    resource.mime,
+                    data.len() as i64,
+                    hex::encode(Sha256::digest(data)),
+                )
+                .save_raw(data.into(), conn)
+                .unwrap();
+            }
+            sync(&mut target, &first).unwrap();
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.conflicts, 1, "{}: {}", msg, report);
+            let mut data = Resource::list(&"book/note (conflict)".into(), &second)
+                .unwrap()
+                .iter()
+                .map(|resource| (resource.title.clone(), resource.read_data().unwrap()))
+                .collect::<Vec<_>>();
+            data.sort();
+            let title = file.file_name().unwrap().to_string_lossy().to_string();
+            assert_eq!(
+                data,
+                vec![
+                    (title.clone(), b"first".to_vec()),
+                    (format!("{} (conflict)", title), b"second".to_vec()),
+                ],
+                "{}",
+                msg
+            );
+            std::fs::remove_file(file).unwrap();
+        }
+
+        {
+            let msg = "encrypted books are unlocked with same passphrase after pull";
+            Folder::make(&"vault".into(), false, &first).unwrap();
+            crypto::encrypt_book(&"vault/".into(), "passphrase", &first).unwrap();
+            Note::make(&"vault/code".into(), false, &first).unwrap();
+            Note::update(&"vault/code".into(), "1234".into(), &first).unwrap();
+            sync(&mut target, &first).unwrap();
+            let report = sync(&mut target, &second).unwrap();
+            assert!(report.errors.is_empty(), "{}: {}", msg, report);
+            crypto::lock(Some(&"vault/".into()), &second).unwrap();
+            assert_eq!(
+                Note::cat(&"vault/code".into(), &second),
+                Err("BookLocked".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                crypto::unlock(Some(&"vault/".into()), "wrong", &second),
+                Err("WrongPassphrase".into()),
+                "{}",
+                msg
+            );
+            crypto::unlock(Some(&"vault/".into()), "passphrase", &second).unwrap();
+            assert_eq!(
+                Note::cat(&"vault/code".into(), &second),
+                Ok("1234".into()),
+                "{}",
+                msg
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+use std::{collections::BTreeMap, io::Read};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::{SyncTarget, EXTENSION};
+
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/></d:prop></d:propfind>"#;
+
+/// webdav collection target, e.g. nextcloud `remote.php/dav/files/<user>/notes`.
+///
+/// revision of an item is its etag, writes use `If-Match` so concurrent
+/// changes on server are never overwritten.
+pub struct WebDavTarget {
+    url: String,
+    auth: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl WebDavTarget {
+    pub fn new(url: &str, user: Option<&str>, password: Option<&str>) -> Self {
+        Self {
+            url: format!("{}/", url.trim_end_matches('/')),
+            auth: user.map(|user| {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password.unwrap_or("")))
+                )
+            }),
+            agent: ureq::AgentBuilder::new()
+                .timeout(std::time::Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    fn request(&self, method: &str, name: &str) -> ureq::Request {
+        let request = self.agent.request(method, &format!("{}{}", self.url, name));
+        match &self.auth {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    fn with_rev(request: ureq::Request, expected: Option<&str>) -> ureq::Request {
+        match expected {
+            Some(rev) => request.set("If-Match", rev),
+            None => request.set("If-None-Match", "*"),
+        }
+    }
+
+    fn propfind(&self, name: &str, depth: &str) -> Result<String, String> {
+        self.request("PROPFIND", name)
+            .set("Depth", depth)
+            .set("Content-Type", "application/xml")
+            .send_string(PROPFIND)
+            .map_err(error)?
+            .into_string()
+            .map_err(|e| e.to_string())
+    }
+
+    /// etag of item, servers not sending etag on PUT are asked again
+    fn etag(&self, name: &str) -> Result<String, String> {
+        let xml = self.propfind(name, "0")?;
+        elements(&xml, "getetag")
+            .first()
+            .map(|etag| text(etag))
+            .ok_or_else(|| "MissingEtag".to_string())
+    }
+}
+
+fn error(error: ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(404, _) => "NotFound".into(),
+        ureq::Error::Status(412, _) => "RemoteChanged".into(),
+        ureq::Error::Status(code, response) => {
+            format!("HttpError: {} {}", code, response.status_text())
+        }
+        ureq::Error::Transport(transport) => transport.to_string(),
+    }
+}
+
+/// contents of elements with local name `name`, ignoring namespace prefixes.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let tag = &rest[..end];
+        if tag.rsplit(':').next() != Some(name) {
+            continue;
+        }
+        let open_end = match rest.find('>') {
+            Some(open_end) => open_end,
+            None => break,
+        };
+        if rest[..open_end].ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let content = &rest[open_end + 1..];
+        match content.find(&format!("</{}>", tag)) {
+            Some(close) => {
+                found.push(content[..close].trim());
+                rest = &content[close..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// text content with entity and character references decoded.
+fn text(raw: &str) -> String {
+    let mut decoded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let c = match &rest[1..end] {
+            "quot" => Some('"'),
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "apos" => Some('\''),
+            reference => match reference.strip_prefix('#') {
+                Some(hex) if hex.starts_with('x') || hex.starts_with('X') => {
+                    u32::from_str_radix(&hex[1..], 16).ok()
+                }
+                Some(decimal) => decimal.parse().ok(),
+                None => None,
+            }
+            .and_then(std::char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+impl SyncTarget for WebDavTarget {
+    fn key(&self) -> String {
+        format!("webdav:{}", self.url)
+    }
+
+    fn list(&mut self) -> Result<BTreeMap<String, String>, String> {
+        let xml = match self.propfind("", "1") {
+            Err(e) if e == "NotFound" => {
+                self.request("MKCOL", "").call().map_err(error)?;
+                return Ok(BTreeMap::new());
+            }
+            xml => xml?,
+        };
+        let mut items = BTreeMap::new();
+        for response in elements(&xml, "response") {
+            let href = text(elements(response, "href").first().copied().unwrap_or(""));
+            let name = href.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            if let (true, Some(etag)) = (
+                name.ends_with(EXTENSION),
+                elements(response, "getetag").first(),
+            ) {
+                items.insert(name.to_string(), text(etag));
+            }
+        }
+        Ok(items)
+    }
+
+    fn get(&mut self, name: &str) -> Result<(Vec<u8>, String), String> {
+        let response = self.request("GET", name).call().map_err(error)?;
+        let etag = response.header("ETag").map(|etag| etag.to_string());
+        let mut data = vec![];
+        response
+            .into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| e.to_string())?;
+        let etag = match etag {
+            Some(etag) => etag,
+            None => self.etag(name)?,
+        };
+        Ok((data, etag))
+    }
+
+    fn put(&mut self, name: &str, data: &[u8], expected: Option<&str>) -> Result<String, String> {
+        let response = Self::with_rev(self.request("PUT", name), expected)
+            .send_bytes(data)
+            .map_err(error)?;
+        match response.header("ETag") {
+            Some(etag) => Ok(etag.to_string()),
+            None => self.etag(name),
+        }
+    }
+
+    fn delete(&mut self, name: &str, expected: Option<&str>) -> Result<(), String> {
+        match Self::with_rev(self.request("DELETE", name), expected).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use tiny_http::{Header, Response, Server};
+
+    use super::WebDavTarget;
+    use crate::{
+        db::{
+            establish_connection,
+            models::{Folder, Note},
+        },
+        sync::sync,
+    };
+
+    /// files by name with etag, and number of puts to accept before failing
+    #[derive(Default)]
+    pub struct DavState {
+        files: BTreeMap<String, (Vec<u8>, u64)>,
+        next_etag: u64,
+        puts_left: Option<usize>,
+    }
+
+    /// minimal in process webdav server, returns its url.
+    pub fn serve(state: Arc<Mutex<DavState>>) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dav/notes", server.server_addr().to_ip().unwrap());
+        thread::spawn(move || {
+            while let Ok(mut request) = server.recv() {
+                let name = request.url().rsplit('/').next().unwrap_or("").to_string();
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.equiv(name))
+                        .map(|h| h.value.as_str().trim_matches('"').to_string())
+                };
+                let (if_match, if_none_match) = (header("If-Match"), header("If-None-Match"));
+                let mut body = vec![];
+                request.as_reader().read_to_end(&mut body).unwrap();
+                let mut state = state.lock().unwrap();
+                let current = state.files.get(&name).map(|(_, etag)| etag.to_string());
+                let precondition = match (&if_match, &if_none_match, &current) {
+                    (Some(expected), _, current) => Some(expected) == current.as_ref(),
+                    (_, Some(_), current) => current.is_none(),
+                    _ => true,
+                };
+                let etag_header =
+                    |etag: u64| Header::from_bytes("ETag", format!("\"{}\"", etag)).unwrap();
+                let response = match request.method().as_str() {
+                    "PROPFIND" => {
+                        let mut xml =
+                            String::from(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">"#);
+                        for (name, (_, etag)) in &state.files {
+                            xml.push_str(&format!(
+                                "<d:response><d:href>/dav/notes/{}</d:href><d:propstat><d:prop><d:getetag>&quot;{}&quot;</d:getetag></d:prop></d:propstat></d:response>",
+                                name, etag
+                            ));
+                        }
+                        xml.push_str("</d:multistatus>");
+                        Response::from_data(xml).with_status_code(207)
+                    }
+                    "GET" => match state.files.get(&name) {
+                        Some((data, etag)) => {
+                            Response::from_data(data.clone()).with_header(etag_header(*etag))
+                        }
+                        None => Response::from_data(vec![]).with_status_code(404),
+                    },
+                    _ if !precondition => Response::from_data(vec![]).with_status_code(412),
+                    "PUT" => match state.puts_left {
+                        Some(0) => Response::from_data(vec![]).with_status_code(503),
+                        puts_left => {
+                            state.puts_left = puts_left.map(|left| left - 1);
+                            state.next_etag += 1;
+                            let etag = state.next_etag;
+                            state.files.insert(name, (body, etag));
+                            Response::from_data(vec![])
+                                .with_status_code(201)
+                                .with_header(etag_header(etag))
+                        }
+                    },
+                    "DELETE" => {
+                        state.files.remove(&name);
+                        Response::from_data(vec![]).with_status_code(204)
+                    }
+                    _ => Response::from_data(vec![]).with_status_code(201),
+                };
+                let _ = request.respond(response);
+            }
+        });
+        url
+    }
+
+    #[test]
+    pub fn integration_test() {
+        let state = Arc::new(Mutex::new(DavState::default()));
+        let url = serve(state.clone());
+        let first = establish_connection().expect("connection or migration failed!");
+        let second = establish_connection().expect("connection or migration failed!");
+        let mut target = WebDavTarget::new(&url, Some("user"), Some("pass"));
+
+        Folder::make(&"book".into(), false, &first).unwrap();
+        for note in &["book/a", "book/b", "book/c"] {
+            Note::make(&note.into(), false, &first).unwrap();
+            Note::update(&note.into(), note.to_string(), &first).unwrap();
+        }
+
+        {
+            let msg = "interrupted upload resumes";
+            state.lock().unwrap().puts_left = Some(2);
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.pushed, 2, "{}: {}", msg, report);
+            assert_eq!(report.errors.len(), 2, "{}: {}", msg, report);
+            state.lock().unwrap().puts_left = None;
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.pushed, 2, "{}: {}", msg, report);
+            assert!(report.errors.is_empty(), "{}: {}", msg, report);
+            assert_eq!(state.lock().unwrap().files.len(), 4, "{}", msg);
+        }
+
+        {
+            let msg = "pull from server";
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.pulled, 4, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/b".into(), &second),
+                Ok("book/b".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "escaped etags of propfind match etags of put";
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.pulled + report.pushed, 0, "{}: {}", msg, report);
+            let report = sync(&mut target, &second).unwrap();
+            assert_eq!(report.pulled + report.pushed, 0, "{}: {}", msg, report);
+        }
+
+        {
+            let msg = "stale etag is refused";
+            use crate::sync::SyncTarget;
+            assert_eq!(
+                target.put("x.md", b"x", Some("\"999\"")),
+                Err("RemoteChanged".into()),
+                "{}",
+                msg
+            );
+            target.put("x.md", b"x", None).unwrap();
+            assert_eq!(
+                target.delete("x.md", Some("\"999\"")),
+                Err("RemoteChanged".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "concurrent edits become a conflict";
+            Note::update(&"book/a".into(), "first".into(), &first).unwrap();
+            Note::update(&"book/a".into(), "second".into(), &second).unwrap();
+            sync(&mut target, &second).unwrap();
+            let report = sync(&mut target, &first).unwrap();
+            assert_eq!(report.conflicts, 1, "{}: {}", msg, report);
+            assert_eq!(
+                Note::cat(&"book/a".into(), &first),
+                Ok("second".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(
+                Note::cat(&"book/a (conflict)".into(), &first),
+                Ok("first".into()),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "etags parsed from propfind";
+            let xml = r#"<D:multistatus xmlns:D="DAV:"><D:response><D:href>/a/x.md</D:href><D:getetag>"1"</D:getetag></D:response><D:response><D:href>/a/</D:href><D:resourcetype><D:collection/></D:resourcetype></D:response></D:multistatus>"#;
+            assert_eq!(super::elements(xml, "response").len(), 2, "{}", msg);
+            assert_eq!(super::elements(xml, "getetag"), vec!["\"1\""], "{}", msg);
+            assert_eq!(
+                super::text("&quot;a&amp;b&#39;&#x3C;&gt; &unknown;"),
+                "\"a&b'<> &unknown;",
+                "{}",
+                msg
+            );
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, fs, path::Path, path::PathBuf};
+
+use string_template::Template;
+
+use crate::db::data_dir;
+
+const EXTENSION: &str = "md";
+
+/// directory holding templates as `<name>.md` files.
+pub fn dir() -> Result<PathBuf, String> {
+    let dir = data_dir()?.join("templates");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+/// file of template `name`, which need not exist yet.
+pub fn path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("InvalidTemplateName: {}", name));
+    }
+    Ok(dir()?.join(format!("{}.{}", name, EXTENSION)))
+}
+
+pub fn list() -> Result<Vec<String>, String> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir()?).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            if let Some(name) = path.file_stem() {
+                names.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+pub fn read(name: &str) -> Result<String, String> {
+    let path = path(name)?;
+    if !path.exists() {
+        return Err("NotFound".into());
+    }
+    fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+/// `date`, `time`, `title` and `book` of note at `note`, overridden by `vars`.
+pub fn variables(note: &Path, vars: &[(String, String)]) -> HashMap<String, String> {
+    let now = chrono::Local::now();
+    let mut variables = HashMap::new();
+    variables.insert("date".into(), now.format("%Y-%m-%d").to_string());
+    variables.insert("time".into(), now.format("%H:%M").to_string());
+    variables.insert(
+        "title".into(),
+        note.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    );
+    variables.insert(
+        "book".into(),
+        note.parent()
+            .map(|book| book.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    );
+    variables.extend(vars.iter().cloned());
+    variables
+}
+
+/// template `name` with `{{variable}}` replaced, unknown variables are kept.
+pub fn render(name: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+    let variables = variables
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    Ok(Template::new(&read(name)?).render(&variables))
+}
+
+/// parse `key=value` of `--var`.
+pub fn parse_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("InvalidVariable: {}", var))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{list, parse_var, path, read, render, variables};
+    use crate::{
+        commands::Command,
+        db::{establish_connection, models::Note},
+    };
+
+    #[test]
+    pub fn integration_test() {
+        let name = format!("meeting_{}", uuid::Uuid::new_v4());
+
+        {
+            let msg = "missing template";
+            assert_eq!(read(&name), Err("NotFound".into()), "{}", msg);
+            assert!(path("../escape").is_err(), "{}", msg);
+        }
+
+        {
+            let msg = "template is rendered with variables";
+            fs::write(
+                path(&name).unwrap(),
+                "# {{title}} in {{book}}\n{{date}} with {{who}}, {{unknown}}",
+            )
+            .unwrap();
+            assert!(list().unwrap().contains(&name), "{}", msg);
+            let vars = variables("/work/standup".as_ref(), &[parse_var("who=team").unwrap()]);
+            let date = vars["date"].clone();
+            assert_eq!(
+                render(&name, &vars),
+                Ok(format!(
+                    "# standup in /work\n{} with team, {{{{unknown}}}}",
+                    date
+                )),
+                "{}",
+                msg
+            );
+            assert!(parse_var("who").is_err(), "{}", msg);
+        }
+
+        {
+            let msg = "template does not overwrite existing note";
+            let conn = establish_connection().expect("connection or migration failed!");
+            let note = PathBuf::from("/work/standup");
+            let make = |template: Option<String>| {
+                Command::Make {
+                    parents: true,
+                    template,
+                    vars: vec![],
+                    path: note.clone(),
+                }
+                .run(&conn)
+            };
+            make(None).unwrap();
+            Note::update(&note, "kept".into(), &conn).unwrap();
+            assert_eq!(
+                make(Some(name.clone())),
+                Err("AlreadyExists".into()),
+                "{}",
+                msg
+            );
+            assert_eq!(Note::cat(&note, &conn), Ok("kept".into()), "{}", msg);
+        }
+        fs::remove_file(path(&name).unwrap()).unwrap();
+    }
+}
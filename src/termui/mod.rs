@@ -1,42 +1,116 @@
+use std::time::{Duration, Instant};
+
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, MouseButton,
+        MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use tui::Terminal;
 use tui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::Clear,
 };
 
 use crate::db::DbConnection;
 
-use self::views::{ActiveElement, FolderView, Interpreter, PopUpView, TuiCommand};
+use self::views::{
+    ActiveElement, CalendarView, FinderView, FolderView, Interpreter, LogView, PreviewView, Return,
+    TuiCommand,
+};
 
 mod views;
 
+/// clicks on the same row within this time open the item
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// terminals narrower than this stack panes without margin
+const NARROW: u16 = 60;
+
+/// panes shown, side pane holds messages or calendar.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Panes {
+    Both,
+    Folder,
+    Side,
+}
+
+impl Panes {
+    fn next(self) -> Self {
+        match self {
+            Self::Both => Self::Folder,
+            Self::Folder => Self::Side,
+            Self::Side => Self::Both,
+        }
+    }
+}
+
+/// screen areas of last render, to route mouse events
+#[derive(Default, Clone, Copy)]
+struct Areas {
+    panes: Rect,
+    folder: Rect,
+    side: Rect,
+    preview: Rect,
+    interpreter: Rect,
+    /// panes are side by side, border can be dragged
+    resizable: bool,
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    column >= area.x && column < area.x + area.width && row >= area.y && row < area.y + area.height
+}
+
 pub type TermBackend = Terminal<CrosstermBackend<std::io::Stdout>>;
-pub struct AppContext {
+pub struct AppContext<B: Backend = CrosstermBackend<std::io::Stdout>> {
     conn: DbConnection,
     interpreter: Interpreter,
     folder: FolderView,
-    popup: PopUpView,
+    calendar: CalendarView,
+    finder: FinderView,
+    log: LogView,
+    preview: PreviewView,
     state: ActiveElement,
-    terminal: TermBackend,
+    terminal: Terminal<B>,
+    panes: Panes,
+    /// width of folder pane in percent
+    split: u16,
+    dragging: bool,
+    last_click: Option<(Instant, u16)>,
+    areas: Areas,
 }
 
 impl AppContext {
     pub fn new(conn: DbConnection) -> Self {
-        let terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))
+        let terminal: TermBackend = Terminal::new(CrosstermBackend::new(std::io::stdout()))
             .expect("could not create a terminal");
+        let mut app = Self::with_terminal(conn, terminal);
+        app.interpreter.load_history();
+        app
+    }
+}
+
+impl<B: Backend> AppContext<B> {
+    pub fn with_terminal(conn: DbConnection, terminal: Terminal<B>) -> Self {
         Self {
             conn,
             terminal,
+            panes: Panes::Both,
             state: Default::default(),
             interpreter: Default::default(),
-            popup: Default::default(),
+            log: Default::default(),
+            preview: Default::default(),
             folder: Default::default(),
+            calendar: Default::default(),
+            finder: Default::default(),
+            split: 33,
+            dragging: false,
+            last_click: None,
+            areas: Default::default(),
         }
     }
 
@@ -57,61 +131,563 @@ impl AppContext {
             terminal,
             state,
             folder,
-            popup,
+            calendar,
+            finder,
+            log,
+            preview,
             interpreter,
             conn,
+            panes,
+            split,
+            areas,
+            ..
         } = self;
         let items = match folder.list(&conn) {
             Ok(items) => items,
             Err(msg) => {
-                popup.push(Err(msg));
+                log.push(Err(msg));
                 vec![]
             }
         };
         let is_folder_view = matches!(state, ActiveElement::FolderView);
+        let is_calendar = matches!(state, ActiveElement::Calendar);
+        let mut days = match is_calendar {
+            true => calendar.days(conn).unwrap_or_else(|msg| {
+                log.push(Err(msg));
+                vec![]
+            }),
+            false => vec![],
+        };
+        // tree shows selected item beside messages
+        let is_tree = folder.is_tree();
+        if is_tree {
+            preview.update(folder.selected_path(conn), conn);
+        }
+        let is_finder = matches!(state, ActiveElement::Finder);
+        let is_log = matches!(state, ActiveElement::Log);
+        let mut found = match is_finder {
+            true => finder.preview(conn).unwrap_or_else(|msg| msg),
+            false => String::new(),
+        };
         terminal
             .draw(|rect| {
+                let size = rect.size();
+                let narrow = size.width < NARROW;
                 let chunks = Layout::default()
                     .direction(Direction::Vertical)
-                    .margin(2)
+                    .margin(if narrow { 0 } else { 2 })
                     .constraints([Constraint::Min(1), Constraint::Length(3)].as_ref())
-                    .split(rect.size());
-
-                let chucks_2 = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Ratio(1, 3), Constraint::Min(1)].as_ref())
-                    .split(chunks[0]);
-                rect.render_stateful_widget(folder, chucks_2[0], &mut (items, is_folder_view));
-                rect.render_widget(popup, chucks_2[1]);
-                rect.render_stateful_widget(interpreter, chunks[1], &mut !is_folder_view);
+                    .split(size);
+
+                let (folder_area, side_area) = match panes {
+                    Panes::Folder => (chunks[0], Rect::default()),
+                    Panes::Side => (Rect::default(), chunks[0]),
+                    Panes::Both => {
+                        let (direction, first) = match narrow {
+                            true => (Direction::Vertical, 50),
+                            false => (Direction::Horizontal, *split),
+                        };
+                        let chucks_2 = Layout::default()
+                            .direction(direction)
+                            .constraints(
+                                [Constraint::Percentage(first), Constraint::Min(1)].as_ref(),
+                            )
+                            .split(chunks[0]);
+                        (chucks_2[0], chucks_2[1])
+                    }
+                };
+                let (preview_area, side_area) = match is_tree && !is_calendar {
+                    true if side_area.area() > 0 => {
+                        let chunks = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints([Constraint::Percentage(60), Constraint::Min(3)].as_ref())
+                            .split(side_area);
+                        (chunks[0], chunks[1])
+                    }
+                    _ => (Rect::default(), side_area),
+                };
+                *areas = Areas {
+                    panes: chunks[0],
+                    folder: folder_area,
+                    side: side_area,
+                    preview: preview_area,
+                    interpreter: chunks[1],
+                    resizable: !narrow && *panes == Panes::Both,
+                };
+                if folder_area.area() > 0 {
+                    rect.render_stateful_widget(folder, folder_area, &mut (items, is_folder_view));
+                }
+                if preview_area.area() > 0 {
+                    rect.render_widget(preview, preview_area);
+                }
+                match side_area.area() {
+                    0 => {}
+                    _ if is_log => {}
+                    _ if is_calendar => rect.render_stateful_widget(calendar, side_area, &mut days),
+                    _ => rect.render_stateful_widget(&mut *log, side_area, &mut false),
+                }
+                rect.render_stateful_widget(
+                    interpreter,
+                    chunks[1],
+                    &mut (!is_folder_view && !is_calendar && !is_finder && !is_log),
+                );
+                if is_finder {
+                    rect.render_stateful_widget(finder, rect.size(), &mut found);
+                }
+                if is_log {
+                    rect.render_widget(Clear, size);
+                    rect.render_stateful_widget(log, size, &mut true);
+                }
             })
             .expect("failed to draw");
     }
 
+    /// act on return of a view, returns false to quit.
+    fn handle(&mut self, ret: Return) -> bool {
+        match ret {
+            Return::Command(TuiCommand::Quit) => return false,
+            Return::Command(TuiCommand::Clear) => self.log.clear(),
+            Return::State(ActiveElement::Log) => {
+                self.log.open();
+                self.state = ActiveElement::Log;
+            }
+            Return::Command(TuiCommand::Command(mut cmd)) => {
+                cmd.resolve(&self.folder.get_context(&self.conn).path);
+                self.tui_mode(false);
+                self.log.push(cmd.run(&self.conn));
+                self.tui_mode(true);
+            }
+            Return::State(ActiveElement::Calendar) => {
+                if self.panes == Panes::Folder {
+                    self.panes = Panes::Both;
+                }
+                self.state = ActiveElement::Calendar;
+            }
+            Return::State(ActiveElement::Finder) => match self.finder.open(&self.conn) {
+                Ok(()) => self.state = ActiveElement::Finder,
+                Err(e) => self.log.push(Err(e)),
+            },
+            Return::State(s) => self.state = s,
+            Return::Jump(path) => {
+                self.folder.jump(&path, &self.conn);
+                self.state = ActiveElement::FolderView;
+            }
+            Return::Panes => self.panes = self.panes.next(),
+            Return::Error(e) => self.log.push(Err(e)),
+            Return::Pass => {}
+        }
+        true
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.dragging = false;
+        self.terminal
+            .resize(Rect::new(0, 0, width, height))
+            .expect("failed to resize");
+    }
+
+    fn handle_mouse(&mut self, event: MouseEvent) -> Return {
+        let MouseEvent {
+            kind, column, row, ..
+        } = event;
+        let areas = self.areas;
+        match (&self.state, kind) {
+            (ActiveElement::Finder, _) => return Return::Pass,
+            (ActiveElement::Log, MouseEventKind::ScrollDown | MouseEventKind::ScrollUp) => {
+                self.log.scroll(matches!(kind, MouseEventKind::ScrollDown));
+                return Return::Pass;
+            }
+            (ActiveElement::Log, _) => return Return::Pass,
+            _ => {}
+        }
+        match kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let border = areas.folder.x + areas.folder.width;
+                if areas.resizable
+                    && contains(areas.panes, column, row)
+                    && (column + 1 == border || column == border)
+                {
+                    self.dragging = true;
+                } else if contains(areas.folder, column, row) {
+                    let count = self.folder.list(&self.conn).map_or(0, |items| items.len());
+                    self.state = ActiveElement::FolderView;
+                    if self.folder.select_at(row, count) {
+                        let now = Instant::now();
+                        let double = matches!(self.last_click,
+                            Some((at, last_row)) if last_row == row && now - at < DOUBLE_CLICK);
+                        self.last_click = Some((now, row));
+                        if double {
+                            self.last_click = None;
+                            return self
+                                .folder
+                                .handle_events(KeyEvent::from(KeyCode::Enter), &self.conn);
+                        }
+                    }
+                } else if contains(areas.interpreter, column, row) {
+                    return Return::State(ActiveElement::Interpreter);
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging && areas.panes.width > 0 => {
+                let width = column.saturating_sub(areas.panes.x) as u32 * 100;
+                self.split = (width / areas.panes.width as u32).clamp(10, 90) as u16;
+            }
+            MouseEventKind::Up(_) => self.dragging = false,
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                let down = matches!(kind, MouseEventKind::ScrollDown);
+                if contains(areas.folder, column, row) {
+                    self.folder.scroll(down);
+                } else if contains(areas.preview, column, row) {
+                    self.preview.scroll(down);
+                } else if contains(areas.side, column, row) {
+                    self.log.scroll(down);
+                }
+            }
+            _ => {}
+        }
+        Return::Pass
+    }
+
     pub fn run(&mut self) {
         self.tui_mode(true);
         let mut running = true;
         while running {
             self.render();
-            if let Event::Key(key) = event::read().expect("can read events") {
-                match match self.state {
-                    ActiveElement::FolderView => self.folder.handle_events(key.code, &self.conn),
-                    ActiveElement::Interpreter => self
-                        .interpreter
-                        .handle_events(key.code, self.folder.get_context(&self.conn)),
-                } {
-                    views::Return::Command(TuiCommand::Quit) => running = false,
-                    views::Return::Command(TuiCommand::Command(cmd)) => {
-                        self.tui_mode(false);
-                        self.popup.push(cmd.execute(&self.conn));
-                        self.tui_mode(true);
-                    }
-                    views::Return::State(s) => self.state = s,
-                    views::Return::Error(e) => self.popup.push(Err(e)),
-                    views::Return::Pass => {}
+            let ret = match event::read().expect("can read events") {
+                Event::Key(key) => match self.state {
+                    ActiveElement::FolderView => self.folder.handle_events(key, &self.conn),
+                    ActiveElement::Interpreter => self.interpreter.handle_events(
+                        key,
+                        self.folder.get_context(&self.conn),
+                        &self.conn,
+                    ),
+                    ActiveElement::Calendar => self.calendar.handle_events(key.code),
+                    ActiveElement::Finder => self.finder.handle_events(key),
+                    ActiveElement::Log => self.log.handle_events(key.code),
+                },
+                Event::Mouse(mouse) => self.handle_mouse(mouse),
+                Event::Resize(width, height) => {
+                    self.resize(width, height);
+                    Return::Pass
                 }
-            }
+            };
+            running = self.handle(ret);
         }
         self.tui_mode(false);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use tui::{backend::TestBackend, Terminal};
+
+    use super::{
+        views::{ActiveElement, Return, TuiCommand},
+        AppContext,
+    };
+    use crate::db::{establish_connection, models::Note};
+
+    /// screen of app rendered at size as lines.
+    fn snapshot(app: &mut AppContext<TestBackend>, width: u16, height: u16) -> Vec<String> {
+        *app.terminal.backend_mut() = TestBackend::new(width, height);
+        app.resize(width, height);
+        app.render();
+        let buffer = app.terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(buffer.area.width.max(1) as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol.as_str()).collect())
+            .collect()
+    }
+
+    /// press keys in folder view.
+    fn keys(app: &mut AppContext<TestBackend>, keys: &str) {
+        for c in keys.chars() {
+            let ret = app
+                .folder
+                .handle_events(KeyEvent::from(KeyCode::Char(c)), &app.conn);
+            app.handle(ret);
+        }
+    }
+
+    /// type keys in input bar, returns shown input line.
+    fn type_keys(app: &mut AppContext<TestBackend>, keys: &[KeyEvent]) -> String {
+        for key in keys {
+            let context = app.folder.get_context(&app.conn);
+            app.interpreter.handle_events(*key, context, &app.conn);
+        }
+        snapshot(app, 40, 12)[10].clone()
+    }
+
+    fn chars(text: &str) -> Vec<KeyEvent> {
+        text.chars()
+            .map(|c| KeyEvent::from(KeyCode::Char(c)))
+            .collect()
+    }
+
+    #[test]
+    pub fn integration_test() {
+        let conn = establish_connection().expect("connection or migration failed!");
+        Note::make(&"/work/plan".into(), true, &conn).unwrap();
+        let terminal = Terminal::new(TestBackend::new(70, 12)).unwrap();
+        let mut app = AppContext::with_terminal(conn, terminal);
+
+        {
+            let msg = "wide terminal shows panes side by side with margin";
+            assert_eq!(
+                snapshot(&mut app, 70, 12),
+                vec![
+                    "                                                                      ",
+                    "                                                                      ",
+                    "  ┌───────────────────┐┌log────────────────────────────────────────┐  ",
+                    "  │work/              ││                                           │  ",
+                    "  │                   ││                                           │  ",
+                    "  │                   ││                                           │  ",
+                    "  └───────────────────┘└───────────────────────────────────────────┘  ",
+                    "  ┌md_cli|Esc: to stop editing|Enter: to execute|{{c/CHILD}}: child┐  ",
+                    "  │                                                                │  ",
+                    "  └────────────────────────────────────────────────────────────────┘  ",
+                    "                                                                      ",
+                    "                                                                      ",
+                ],
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "narrow terminal stacks panes without margin";
+            assert_eq!(
+                snapshot(&mut app, 40, 12),
+                vec![
+                    "┌──────────────────────────────────────┐",
+                    "│work/                                 │",
+                    "│                                      │",
+                    "└──────────────────────────────────────┘",
+                    "┌log───────────────────────────────────┐",
+                    "│                                      │",
+                    "│                                      │",
+                    "│                                      │",
+                    "└──────────────────────────────────────┘",
+                    "┌md_cli|Esc: to stop editing|Enter: to ┐",
+                    "│                                      │",
+                    "└──────────────────────────────────────┘",
+                ],
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "toggled pane fills screen";
+            app.handle(Return::Panes);
+            assert_eq!(
+                snapshot(&mut app, 40, 8),
+                vec![
+                    "┌──────────────────────────────────────┐",
+                    "│work/                                 │",
+                    "│                                      │",
+                    "│                                      │",
+                    "└──────────────────────────────────────┘",
+                    "┌md_cli|Esc: to stop editing|Enter: to ┐",
+                    "│                                      │",
+                    "└──────────────────────────────────────┘",
+                ],
+                "{}",
+                msg
+            );
+            app.handle(Return::Panes);
+            assert_eq!(
+                snapshot(&mut app, 40, 8)[..2],
+                [
+                    "┌log───────────────────────────────────┐",
+                    "│                                      │",
+                ],
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "tree expands books in place and previews selection";
+            Note::make(&"/work/old/log".into(), true, &app.conn).unwrap();
+            keys(&mut app, "wTzo");
+            assert_eq!(
+                snapshot(&mut app, 70, 14)[2..9],
+                [
+                    "  ┌───────────────────┐┌/work/─────────────────────────────────────┐  ",
+                    "  │▾ work/            ││old/                                       │  ",
+                    "  │  ▸ old/           ││plan                                       │  ",
+                    "  │    plan           │└───────────────────────────────────────────┘  ",
+                    "  │                   │┌log────────────────────────────────────────┐  ",
+                    "  │                   ││                                           │  ",
+                    "  └───────────────────┘└───────────────────────────────────────────┘  ",
+                ],
+                "{}",
+                msg
+            );
+            keys(&mut app, "jzajj");
+            assert_eq!(
+                app.folder.selected_path(&app.conn),
+                Some("/work/plan".into()),
+                "{}",
+                msg
+            );
+            keys(&mut app, "kzc");
+            assert_eq!(
+                app.folder.selected_path(&app.conn),
+                Some("/work/old/".into()),
+                "{}",
+                msg
+            );
+            keys(&mut app, "zc");
+            assert_eq!(app.folder.list(&app.conn).unwrap().len(), 1, "{}", msg);
+            keys(&mut app, "T");
+        }
+
+        {
+            let msg = "input bar edits, completes and browses history";
+            app.handle(Return::State(ActiveElement::Interpreter));
+            let tab = KeyEvent::from(KeyCode::Tab);
+            let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+            assert_eq!(
+                type_keys(&mut app, &[chars("ca").as_slice(), &[tab]].concat()),
+                "│cat _                                 │",
+                "{}",
+                msg
+            );
+            assert_eq!(
+                type_keys(&mut app, &[chars("w").as_slice(), &[tab]].concat()),
+                "│cat work/_                            │",
+                "{}",
+                msg
+            );
+            assert_eq!(
+                type_keys(&mut app, &[ctrl('w')]),
+                "│cat _                                 │",
+                "{}",
+                msg
+            );
+            type_keys(&mut app, &[chars("{").as_slice(), &[tab]].concat());
+            assert_eq!(
+                snapshot(&mut app, 40, 12)[9],
+                "┌{{CHILD}} {{PARENT}} {{c}} {{p}}──────┐",
+                "{}",
+                msg
+            );
+            assert_eq!(
+                type_keys(&mut app, &[chars("c").as_slice(), &[tab]].concat()),
+                "│cat {{c}} _                           │",
+                "{}",
+                msg
+            );
+            let home = KeyEvent::from(KeyCode::Home);
+            let delete = KeyEvent::from(KeyCode::Delete);
+            let right = KeyEvent::from(KeyCode::Right);
+            assert_eq!(
+                type_keys(&mut app, &[home, delete, right, right, ctrl('k')]),
+                "│at_                                   │",
+                "{}",
+                msg
+            );
+            let enter = KeyEvent::from(KeyCode::Enter);
+            let up = KeyEvent::from(KeyCode::Up);
+            let down = KeyEvent::from(KeyCode::Down);
+            assert_eq!(
+                type_keys(
+                    &mut app,
+                    &[&[enter], chars("ls").as_slice(), &[up]].concat()
+                ),
+                "│at_                                   │",
+                "{}",
+                msg
+            );
+            assert_eq!(
+                type_keys(&mut app, &[down]),
+                "│ls_                                   │",
+                "{}",
+                msg
+            );
+            app.handle(Return::State(ActiveElement::FolderView));
+        }
+
+        {
+            let msg = "log wraps messages below their time and opens in pager";
+            app.log.push(Ok(String::new()));
+            app.log.push(Err("NotFound".into()));
+            app.log
+                .push(Ok("first line of a much longer output\nsecond".into()));
+            let screen = snapshot(&mut app, 40, 12);
+            let log = screen[5..8]
+                .iter()
+                .map(|line| line.chars().skip(10).collect::<String>())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                log,
+                vec![
+                    "first line of a much longer  │",
+                    "output                       │",
+                    "second                       │",
+                ],
+                "{}",
+                msg
+            );
+            assert!(screen[6].contains("│         output"), "{}", msg);
+            let output = (1..=20)
+                .map(|n| format!("line {}", n))
+                .collect::<Vec<_>>()
+                .join("\n");
+            app.log.push(Ok(output));
+            keys(&mut app, "L");
+            let screen = snapshot(&mut app, 40, 12);
+            assert!(screen[0].starts_with("┌log|j/k: scroll"), "{}", msg);
+            assert!(
+                screen[1].ends_with(" line 1                       │"),
+                "{}",
+                msg
+            );
+            app.log.handle_events(KeyCode::Char('j'));
+            assert!(
+                snapshot(&mut app, 40, 12)[1].starts_with("│         line 2 "),
+                "{}",
+                msg
+            );
+            app.log.handle_events(KeyCode::Char('e'));
+            let screen = snapshot(&mut app, 40, 12);
+            assert!(screen[1].contains("NotFound"), "{}", msg);
+            assert!(!screen[2].contains("line"), "{}", msg);
+            app.log.handle_events(KeyCode::Char('e'));
+            let ret = app.log.handle_events(KeyCode::Esc);
+            app.handle(ret);
+            app.handle(Return::Command(TuiCommand::Clear));
+            assert!(
+                !snapshot(&mut app, 40, 12)[5].contains("NotFound"),
+                "{}",
+                msg
+            );
+        }
+
+        {
+            let msg = "repeated errors are logged once, repeated outputs each";
+            for value in [Ok("done"), Ok("done"), Err("Busy"), Err("Busy")] {
+                app.log.push(value.map(String::from).map_err(String::from));
+            }
+            let screen = snapshot(&mut app, 40, 12);
+            let count = |text| screen.iter().filter(|line| line.contains(text)).count();
+            assert_eq!((count("done"), count("Busy")), (2, 1), "{}", msg);
+            app.handle(Return::Command(TuiCommand::Clear));
+        }
+
+        {
+            let msg = "tiny terminals render without panic";
+            for (width, height) in [(8, 3), (1, 1), (0, 0)] {
+                assert_eq!(
+                    snapshot(&mut app, width, height).len(),
+                    height as usize,
+                    "{}",
+                    msg
+                );
+            }
+        }
+    }
+}
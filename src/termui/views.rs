@@ -1,24 +1,41 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
 
-use crossterm::event::KeyCode;
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveTime, Weekday};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use rustyline::history::History;
 use string_template::Template;
 use structopt::StructOpt;
 use tui::{
     buffer::Buffer,
-    layout::Rect,
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, StatefulWidget, Widget},
+    text::{Span, Spans},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, StatefulWidget, Widget, Wrap,
+    },
 };
 
 use crate::{
     commands::Command,
-    db::{models::Folder, DbConnection},
+    db::{
+        data_dir,
+        models::{Folder, Note},
+        DbConnection,
+    },
+    find::{self, Match},
+    interpreter::{complete, word_start, CommandSpec, HISTORY_FILE},
+    journal,
 };
 
 pub enum ActiveElement {
     FolderView,
     Interpreter,
+    Calendar,
+    Finder,
+    Log,
 }
 
 impl Default for ActiveElement {
@@ -31,10 +48,13 @@ impl Default for ActiveElement {
 pub enum TuiCommand {
     #[structopt(flatten)]
     Command(Command),
+    #[structopt(about = "clear message log")]
+    Clear,
     #[structopt(visible_alias = "q", about = "quit")]
     Quit,
 }
 
+#[derive(Clone)]
 pub enum Item {
     Note(String),
     Folder(String),
@@ -48,6 +68,12 @@ impl Item {
         }
     }
 
+    pub fn title(&self) -> &str {
+        match self {
+            Self::Note(title) | Self::Folder(title) => title,
+        }
+    }
+
     pub fn get_name(self) -> String {
         match self {
             Self::Note(path) => path,
@@ -62,14 +88,38 @@ impl Item {
 pub enum Return {
     Command(TuiCommand),
     State(ActiveElement),
+    /// show book or note at path in folder view
+    Jump(PathBuf),
+    /// show other panes
+    Panes,
     Error(String),
     Pass,
 }
 
+/// narrows folder view to titles matching pattern, or to notes below the
+/// book whose body contains it.
+#[derive(Default)]
+struct Filter {
+    pattern: String,
+    bodies: bool,
+    editing: bool,
+    /// notes found by body search, searched again when pattern or mode
+    /// changes as bodies are decrypted
+    found: Option<Result<Vec<Item>, String>>,
+}
+
 #[derive(Default)]
 pub struct FolderView {
     path: PathBuf,
     items_state: ListState,
+    filter: Option<Filter>,
+    /// first shown item and area of last render, to map clicks to items
+    offset: usize,
+    area: Rect,
+    /// show books below path as tree, holds expanded books relative to path
+    tree: Option<HashSet<PathBuf>>,
+    /// first key of two key commands like `zo`
+    pending: Option<char>,
 }
 
 impl FolderView {
@@ -89,12 +139,223 @@ impl FolderView {
     }
 
     pub fn list(&self, conn: &DbConnection) -> Result<Vec<Item>, String> {
-        let (folders, notes) = Folder::list(&self.path, conn)?;
-        Ok(folders
-            .into_iter()
-            .map(|f| Item::Folder(f.title))
-            .chain(notes.into_iter().map(|n| Item::Note(n.title)))
-            .collect())
+        if let Some(Filter {
+            found: Some(found), ..
+        }) = &self.filter
+        {
+            return found.clone();
+        }
+        let items = match &self.tree {
+            Some(expanded) => {
+                let mut items = vec![];
+                Self::walk(&self.path, Path::new(""), expanded, conn, &mut items)?;
+                items
+            }
+            None => {
+                let (folders, notes) = Folder::list(&self.path, conn)?;
+                folders
+                    .into_iter()
+                    .map(|f| Item::Folder(f.title))
+                    .chain(notes.into_iter().map(|n| Item::Note(n.title)))
+                    .collect()
+            }
+        }
+        .into_iter();
+        Ok(match &self.filter {
+            Some(filter) => items
+                .filter(|item| find::score(&filter.pattern, item.title()).is_some())
+                .collect(),
+            None => items.collect(),
+        })
+    }
+
+    /// items of `book` titled by path relative to `dir`, followed by items
+    /// of expanded books, only expanded books are listed.
+    fn walk(
+        book: &Path,
+        dir: &Path,
+        expanded: &HashSet<PathBuf>,
+        conn: &DbConnection,
+        items: &mut Vec<Item>,
+    ) -> Result<(), String> {
+        let (folders, notes) = Folder::list(&book.to_path_buf(), conn)?;
+        for folder in folders {
+            let path = dir.join(&folder.title);
+            items.push(Item::Folder(path.to_string_lossy().into()));
+            if expanded.contains(&path) {
+                Self::walk(&book.join(&folder.title), &path, expanded, conn, items)?;
+            }
+        }
+        for note in notes {
+            items.push(Item::Note(dir.join(&note.title).to_string_lossy().into()));
+        }
+        Ok(())
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.tree.is_some()
+    }
+
+    /// absolute path of selected item, books end with `/`.
+    pub fn selected_path(&self, conn: &DbConnection) -> Option<PathBuf> {
+        let item = self.get_selected_item(conn)?;
+        Some(Path::new("/").join(&self.path).join(item.get_name()))
+    }
+
+    fn toggle_tree(&mut self, conn: &DbConnection) {
+        let selected = self.get_selected_item(conn);
+        self.tree = match self.tree.take() {
+            Some(_) => None,
+            None => Some(Default::default()),
+        };
+        // keep selection on selected item or the top book holding it
+        let title = selected
+            .as_ref()
+            .and_then(|item| Path::new(item.title()).components().next())
+            .map(|title| title.as_os_str().to_string_lossy().to_string());
+        let selected = self.list(conn).ok().and_then(|items| {
+            items
+                .iter()
+                .position(|item| Some(item.title()) == title.as_deref())
+        });
+        self.items_state.select(selected);
+    }
+
+    /// expand (or collapse) selected book, collapsing a note or collapsed
+    /// book collapses the book holding it and selects it.
+    fn expand(&mut self, expand: Option<bool>, conn: &DbConnection) {
+        let selected = self.get_selected_item(conn);
+        let expanded = match &mut self.tree {
+            Some(expanded) => expanded,
+            None => return,
+        };
+        let (path, is_book) = match &selected {
+            Some(Item::Folder(path)) => (PathBuf::from(path), true),
+            Some(Item::Note(path)) => (PathBuf::from(path), false),
+            None => return,
+        };
+        let is_open = expanded.contains(&path);
+        match expand {
+            Some(true) | None if is_book && !is_open => {
+                expanded.insert(path);
+            }
+            Some(false) | None if is_open => {
+                expanded.remove(&path);
+            }
+            Some(false) => {
+                let parent = match path.parent() {
+                    Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                    _ => return,
+                };
+                expanded.remove(&parent);
+                let title = parent.to_string_lossy();
+                let selected = self
+                    .list(conn)
+                    .ok()
+                    .and_then(|items| items.iter().position(|item| item.title() == title));
+                self.items_state.select(selected);
+            }
+            _ => {}
+        }
+    }
+
+    /// notes below `book` whose body contains `pattern`, titled by path relative to `dir`.
+    fn search(
+        book: &Path,
+        dir: &Path,
+        pattern: &str,
+        conn: &DbConnection,
+        items: &mut Vec<Item>,
+    ) -> Result<(), String> {
+        let (folders, notes) = Folder::list(&book.to_path_buf(), conn)?;
+        for note in notes {
+            // locked notes can not be searched
+            if let Ok(body) = note.read_body() {
+                if body.to_lowercase().contains(pattern) {
+                    items.push(Item::Note(dir.join(&note.title).to_string_lossy().into()));
+                }
+            }
+        }
+        for folder in folders {
+            Self::search(
+                &book.join(&folder.title),
+                &dir.join(&folder.title),
+                pattern,
+                conn,
+                items,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// select next (or previous) item of filtered list, wrapping around.
+    fn next_match(&mut self, forward: bool, conn: &DbConnection) {
+        let count = self.list(conn).map(|items| items.len()).unwrap_or(0);
+        if count == 0 {
+            return;
+        }
+        let selected = self.items_state.selected().unwrap_or(0).min(count - 1);
+        self.items_state.select(Some(match forward {
+            true => (selected + 1) % count,
+            false => (selected + count - 1) % count,
+        }));
+    }
+
+    fn handle_filter(&mut self, key: KeyEvent, conn: &DbConnection) -> Return {
+        let filter = match &mut self.filter {
+            Some(filter) => filter,
+            None => return Return::Pass,
+        };
+        match key.code {
+            KeyCode::Esc => self.filter = None,
+            KeyCode::Enter => {
+                filter.editing = false;
+                return Return::Pass;
+            }
+            KeyCode::Tab => filter.bodies = !filter.bodies,
+            KeyCode::Backspace if filter.pattern.is_empty() => self.filter = None,
+            KeyCode::Backspace => {
+                filter.pattern.pop();
+            }
+            KeyCode::Char(c) => filter.pattern.push(c),
+            _ => return Return::Pass,
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.found = match filter.bodies {
+                true => {
+                    let mut items = vec![];
+                    let pattern = filter.pattern.to_lowercase();
+                    Some(
+                        Self::search(&self.path, Path::new(""), &pattern, conn, &mut items)
+                            .map(|_| items),
+                    )
+                }
+                false => None,
+            };
+        }
+        self.items_state.select(Some(0));
+        Return::Pass
+    }
+
+    /// select item shown at screen `row`, returns if an item was hit.
+    pub fn select_at(&mut self, row: u16, count: usize) -> bool {
+        let top = self.area.y + 1;
+        if row < top || row + 1 >= self.area.y + self.area.height {
+            return false;
+        }
+        let index = self.offset + (row - top) as usize;
+        if index >= count {
+            return false;
+        }
+        self.items_state.select(Some(index));
+        true
+    }
+
+    pub fn scroll(&mut self, down: bool) {
+        match down {
+            true => self.down(),
+            false => self.up(),
+        }
     }
 
     fn up(&mut self) {
@@ -121,6 +382,8 @@ impl FolderView {
             Some(Item::Folder(title)) => {
                 self.path.push(title);
                 self.items_state.select(None);
+                self.filter = None;
+                self.tree = self.tree.as_ref().map(|_| Default::default());
             }
             None => {}
         }
@@ -128,27 +391,443 @@ impl FolderView {
     }
 
     fn back(&mut self) {
+        // left book stays expanded in tree
+        if let (Some(expanded), Some(title)) = (&mut self.tree, self.path.file_name()) {
+            let title = PathBuf::from(title);
+            *expanded = expanded
+                .drain()
+                .map(|path| title.join(path))
+                .chain(Some(title.clone()))
+                .collect();
+        }
         self.path.pop();
+        self.filter = None;
     }
 
-    pub fn handle_events(&mut self, key: KeyCode, conn: &DbConnection) -> Return {
-        match key {
+    /// open book holding book or note at `target` and select it.
+    pub fn jump(&mut self, target: &Path, conn: &DbConnection) {
+        let target = target.to_string_lossy();
+        let target = Path::new(target.trim_start_matches('/').trim_end_matches('/'));
+        let title = target
+            .file_name()
+            .map(|title| title.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (title, parent) = match &mut self.tree {
+            Some(expanded) => {
+                // expand books down to target, below current book if possible
+                let target = match target.strip_prefix(&self.path) {
+                    Ok(target) => target.to_path_buf(),
+                    Err(_) => {
+                        self.path = PathBuf::new();
+                        expanded.clear();
+                        target.to_path_buf()
+                    }
+                };
+                expanded.extend(
+                    target
+                        .ancestors()
+                        .skip(1)
+                        .filter(|book| *book != Path::new(""))
+                        .map(Path::to_path_buf),
+                );
+                (target.to_string_lossy().to_string(), None)
+            }
+            None => (title, target.parent().map(Path::to_path_buf)),
+        };
+        if let Some(parent) = parent {
+            self.path = parent;
+        }
+        let selected = self
+            .list(conn)
+            .ok()
+            .and_then(|items| items.iter().position(|item| item.title() == title));
+        self.items_state.select(selected);
+    }
+
+    pub fn handle_events(&mut self, key: KeyEvent, conn: &DbConnection) -> Return {
+        if self.filter.as_ref().is_some_and(|filter| filter.editing) {
+            return self.handle_filter(key, conn);
+        }
+        if key.code == KeyCode::Char('p') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Return::State(ActiveElement::Finder);
+        }
+        if let Some('z') = self.pending.take() {
+            match key.code {
+                KeyCode::Char('o') => self.expand(Some(true), conn),
+                KeyCode::Char('c') => self.expand(Some(false), conn),
+                KeyCode::Char('a') => self.expand(None, conn),
+                _ => {}
+            }
+            return Return::Pass;
+        }
+        match key.code {
             KeyCode::Char('q') => return Return::Command(TuiCommand::Quit),
             KeyCode::Backspace | KeyCode::Char('h') | KeyCode::Left => self.back(),
             KeyCode::Char('k') | KeyCode::Up => self.up(),
             KeyCode::Char('j') | KeyCode::Down => self.down(),
+            KeyCode::Enter | KeyCode::Char('l') | KeyCode::Right
+                if self.is_tree()
+                    && matches!(self.get_selected_item(conn), Some(Item::Folder(_))) =>
+            {
+                self.expand(None, conn)
+            }
             KeyCode::Enter | KeyCode::Char('l') | KeyCode::Right => {
                 if let Some(note) = self.enter(self.get_selected_item(&conn)) {
                     return Return::Command(TuiCommand::Command(Command::Edit { note }));
                 }
             }
             KeyCode::Char(':') | KeyCode::Tab => return Return::State(ActiveElement::Interpreter),
+            KeyCode::Char('t') => return Return::Command(TuiCommand::Command(Command::Today)),
+            KeyCode::Char('u') => return Return::Command(TuiCommand::Command(Command::Undo)),
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Return::Command(TuiCommand::Command(Command::Redo))
+            }
+            KeyCode::Char('C') => return Return::State(ActiveElement::Calendar),
+            KeyCode::Char('w') => return Return::Panes,
+            KeyCode::Char('L') => return Return::State(ActiveElement::Log),
+            KeyCode::Char('T') => self.toggle_tree(conn),
+            KeyCode::Char('z') => self.pending = Some('z'),
+            KeyCode::Char('/') => return Return::State(ActiveElement::Finder),
+            KeyCode::Char('f') => {
+                let filter = self.filter.get_or_insert_with(Default::default);
+                filter.editing = true;
+            }
+            KeyCode::Char('n') if self.filter.is_some() => self.next_match(true, conn),
+            KeyCode::Char('N') if self.filter.is_some() => self.next_match(false, conn),
+            KeyCode::Esc => self.filter = None,
             _ => {}
         }
         Return::Pass
     }
 }
 
+/// overlay ranking all book and note paths by the typed pattern.
+#[derive(Default)]
+pub struct FinderView {
+    input: String,
+    paths: Vec<PathBuf>,
+    matches: Vec<Match>,
+    matches_state: ListState,
+}
+
+impl FinderView {
+    /// start a new search over current paths.
+    pub fn open(&mut self, conn: &DbConnection) -> Result<(), String> {
+        self.paths = find::paths(conn)?;
+        self.input.clear();
+        self.update();
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        self.matches = find::rank(&self.input, &self.paths);
+        self.matches_state
+            .select(Some(0).filter(|_| !self.matches.is_empty()));
+    }
+
+    fn selected(&self) -> Option<&Path> {
+        self.matches
+            .get(self.matches_state.selected()?)
+            .map(|m| m.path.as_path())
+    }
+
+    fn shift(&mut self, down: bool) {
+        if let Some(selected) = self.matches_state.selected() {
+            let selected = match down {
+                true => (selected + 1).min(self.matches.len() - 1),
+                false => selected.saturating_sub(1),
+            };
+            self.matches_state.select(Some(selected));
+        }
+    }
+
+    /// body of selected note or items of selected book.
+    pub fn preview(&self, conn: &DbConnection) -> Result<String, String> {
+        match self.selected() {
+            Some(path) => preview(path, conn),
+            None => Ok(String::new()),
+        }
+    }
+
+    pub fn handle_events(&mut self, key: KeyEvent) -> Return {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Return::State(ActiveElement::FolderView),
+            KeyCode::Enter => {
+                if let Some(path) = self.selected() {
+                    return Return::Jump(path.to_path_buf());
+                }
+            }
+            KeyCode::Up => self.shift(false),
+            KeyCode::Down => self.shift(true),
+            KeyCode::Char('p') if ctrl => self.shift(false),
+            KeyCode::Char('n') if ctrl => self.shift(true),
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                self.update();
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                self.update();
+            }
+            _ => {}
+        }
+        Return::Pass
+    }
+}
+
+/// body of note or items of book at path.
+fn preview(path: &Path, conn: &DbConnection) -> Result<String, String> {
+    if !path.to_string_lossy().ends_with('/') {
+        return Note::cat(&path.to_path_buf(), conn);
+    }
+    let (folders, notes) = Folder::list(&path.to_path_buf(), conn)?;
+    Ok(folders
+        .into_iter()
+        .map(|f| format!("{}/", f.title))
+        .chain(notes.into_iter().map(|n| n.title))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// body or items of item selected in folder view.
+#[derive(Default)]
+pub struct PreviewView {
+    path: Option<PathBuf>,
+    text: String,
+    scroll: u16,
+}
+
+impl PreviewView {
+    /// show item at path, keeps scroll position while path is the same.
+    pub fn update(&mut self, path: Option<PathBuf>, conn: &DbConnection) {
+        if path != self.path {
+            self.scroll = 0;
+        }
+        self.text = match &path {
+            Some(path) => preview(path, conn).unwrap_or_else(|msg| msg),
+            None => String::new(),
+        };
+        self.path = path;
+    }
+
+    pub fn scroll(&mut self, down: bool) {
+        self.scroll = match down {
+            true => self.scroll.saturating_add(1),
+            false => self.scroll.saturating_sub(1),
+        };
+    }
+}
+
+impl Widget for &mut PreviewView {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title = self
+            .path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Paragraph::new(self.text.as_str())
+            .style(Style::default().fg(Color::White))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0))
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .render(area, buf);
+    }
+}
+
+/// `percent` of `area` in its center.
+fn centered(area: Rect, percent: u16) -> Rect {
+    let width = area.width * percent / 100;
+    let height = area.height * percent / 100;
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+impl StatefulWidget for &mut FinderView {
+    fn render(self, area: Rect, buf: &mut Buffer, preview: &mut Self::State) {
+        let area = centered(area, 80);
+        Clear.render(area, buf);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Yellow))
+            .title("find|Enter: jump|Up/Down: select|Esc: back");
+        let inner = block.inner(area);
+        block.render(area, buf);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(1)].as_ref())
+            .split(inner);
+        Paragraph::new(Spans::from(vec![
+            Span::raw("> "),
+            Span::raw(self.input.as_str()),
+            Span::styled("_", Style::default().add_modifier(Modifier::UNDERLINED)),
+        ]))
+        .render(chunks[0], buf);
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)].as_ref())
+            .split(chunks[1]);
+        let matched = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        let list = List::new(
+            self.matches
+                .iter()
+                .map(|m| {
+                    let color = match m.path.to_string_lossy().ends_with('/') {
+                        true => Color::Green,
+                        false => Color::Blue,
+                    };
+                    ListItem::new(Spans::from(
+                        m.path
+                            .to_string_lossy()
+                            .chars()
+                            .enumerate()
+                            .map(|(i, c)| match m.indices.contains(&i) {
+                                true => Span::styled(c.to_string(), matched),
+                                false => Span::styled(c.to_string(), Style::default().fg(color)),
+                            })
+                            .collect::<Vec<_>>(),
+                    ))
+                })
+                .collect::<Vec<_>>(),
+        )
+        .block(Block::default().borders(Borders::TOP))
+        .highlight_style(Style::default().bg(Color::DarkGray));
+        StatefulWidget::render(list, panes[0], buf, &mut self.matches_state);
+        Paragraph::new(preview.as_str())
+            .style(Style::default().fg(Color::White))
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::TOP | Borders::LEFT))
+            .render(panes[1], buf);
+    }
+
+    type State = String;
+}
+
+/// month of journal, days with entries are highlighted.
+pub struct CalendarView {
+    selected: NaiveDate,
+}
+
+impl Default for CalendarView {
+    fn default() -> Self {
+        Self {
+            selected: journal::today(),
+        }
+    }
+}
+
+impl CalendarView {
+    pub fn days(&self, conn: &DbConnection) -> Result<Vec<u32>, String> {
+        journal::days(self.selected.year(), self.selected.month(), conn)
+    }
+
+    fn shift_days(&mut self, days: i64) {
+        if let Some(date) = self.selected.checked_add_signed(Duration::days(days)) {
+            self.selected = date;
+        }
+    }
+
+    fn shift_month(&mut self, forward: bool) {
+        let months = Months::new(1);
+        if let Some(date) = match forward {
+            true => self.selected.checked_add_months(months),
+            false => self.selected.checked_sub_months(months),
+        } {
+            self.selected = date;
+        }
+    }
+
+    pub fn handle_events(&mut self, key: KeyCode) -> Return {
+        match key {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('C') => {
+                return Return::State(ActiveElement::FolderView)
+            }
+            KeyCode::Char('h') | KeyCode::Left => self.shift_days(-1),
+            KeyCode::Char('l') | KeyCode::Right => self.shift_days(1),
+            KeyCode::Char('k') | KeyCode::Up => self.shift_days(-7),
+            KeyCode::Char('j') | KeyCode::Down => self.shift_days(7),
+            KeyCode::Char('<') | KeyCode::PageUp => self.shift_month(false),
+            KeyCode::Char('>') | KeyCode::PageDown => self.shift_month(true),
+            KeyCode::Char('t') => self.selected = journal::today(),
+            KeyCode::Enter => {
+                return Return::Command(TuiCommand::Command(Command::Journal {
+                    date: Some(self.selected),
+                    config: None,
+                }))
+            }
+            _ => {}
+        }
+        Return::Pass
+    }
+}
+
+impl StatefulWidget for &mut CalendarView {
+    fn render(self, area: Rect, buf: &mut Buffer, days: &mut Self::State) {
+        let first = self.selected.with_day(1).unwrap_or(self.selected);
+        let mut lines = vec![
+            Spans::from(first.format("%B %Y").to_string()),
+            Spans::from("Mo Tu We Th Fr Sa Su"),
+        ];
+        let mut week = vec![Span::raw(
+            "   ".repeat(first.weekday().num_days_from_monday() as usize),
+        )];
+        let mut date = first;
+        while date.month() == first.month() {
+            let mut style = Style::default();
+            if days.contains(&date.day()) {
+                style = style.fg(Color::Green).add_modifier(Modifier::BOLD);
+            }
+            if date == self.selected {
+                style = style.bg(Color::Yellow).fg(Color::Black);
+            }
+            week.push(Span::styled(format!("{:>2}", date.day()), style));
+            week.push(Span::raw(" "));
+            if date.weekday() == Weekday::Sun {
+                lines.push(Spans::from(std::mem::take(&mut week)));
+            }
+            date = match date.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        if !week.is_empty() {
+            lines.push(Spans::from(week));
+        }
+        Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .style(Style::default().fg(Color::Yellow))
+                    .title("journal|Enter: open|t: today|</>: month|Esc: back"),
+            )
+            .render(area, buf);
+    }
+
+    type State = Vec<u32>;
+}
+
+/// indent and expand marker of tree item, and its title.
+fn tree_label(item: &Item, expanded: &HashSet<PathBuf>) -> (String, String) {
+    let path = Path::new(item.title());
+    let indent = "  ".repeat(path.components().count().saturating_sub(1));
+    let marker = match item {
+        Item::Folder(_) if expanded.contains(path) => "▾ ",
+        Item::Folder(_) => "▸ ",
+        Item::Note(_) => "  ",
+    };
+    let title = path
+        .file_name()
+        .map(|title| title.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (format!("{}{}", indent, marker), title)
+}
+
 impl StatefulWidget for &mut FolderView {
     fn render(self, area: Rect, buf: &mut Buffer, (items, is_active): &mut Self::State) {
         let block = Block::default()
@@ -161,7 +840,22 @@ impl StatefulWidget for &mut FolderView {
                     style.fg(Color::White)
                 }
             })
-            .title(self.path.to_str().unwrap_or(""));
+            .title(match &self.filter {
+                Some(filter) => format!(
+                    "{}|{} {}{}",
+                    self.path.to_str().unwrap_or(""),
+                    if filter.bodies { "body" } else { "filter" },
+                    filter.pattern,
+                    if filter.editing { "_" } else { "" }
+                ),
+                None => self.path.to_str().unwrap_or("").to_string(),
+            });
+        let pattern = self
+            .filter
+            .as_ref()
+            .filter(|filter| !filter.bodies)
+            .map(|filter| filter.pattern.as_str())
+            .unwrap_or("");
         match self.items_state.selected() {
             _ if items.is_empty() => {
                 self.items_state.select(None);
@@ -174,14 +868,49 @@ impl StatefulWidget for &mut FolderView {
             }
             _ => {}
         }
+        // same scrolling as List, which keeps its offset private
+        self.area = area;
+        let height = area.height.saturating_sub(2) as usize;
+        match self.items_state.selected() {
+            Some(selected) if selected < self.offset => self.offset = selected,
+            Some(selected) if height > 0 && selected >= self.offset + height => {
+                self.offset = selected + 1 - height
+            }
+            None => self.offset = 0,
+            _ => {}
+        }
+        let tree = self
+            .tree
+            .as_ref()
+            .filter(|_| !self.filter.as_ref().is_some_and(|filter| filter.bodies));
         let list = List::new(
             items
                 .drain(..)
                 .map(|item| {
-                    ListItem::new(Spans::from(vec![{
-                        let color = item.get_color();
-                        Span::styled(item.get_name(), Style::default().fg(color))
-                    }]))
+                    let style = Style::default().fg(item.get_color());
+                    let (prefix, title) = match tree {
+                        Some(expanded) => tree_label(&item, expanded),
+                        None => (String::new(), item.title().to_string()),
+                    };
+                    let indices = find::score(pattern, &title)
+                        .map(|(_, indices)| indices)
+                        .unwrap_or_default();
+                    let matched = style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+                    let name = match item {
+                        Item::Folder(_) => format!("{}/", title),
+                        Item::Note(_) => title,
+                    };
+                    ListItem::new(Spans::from(
+                        Some(Span::styled(prefix, style))
+                            .into_iter()
+                            .chain(name.chars().enumerate().map(
+                                |(i, c)| match indices.contains(&i) {
+                                    true => Span::styled(c.to_string(), matched),
+                                    false => Span::styled(c.to_string(), style),
+                                },
+                            ))
+                            .collect::<Vec<_>>(),
+                    ))
                 })
                 .collect::<Vec<_>>(),
         )
@@ -198,43 +927,225 @@ impl StatefulWidget for &mut FolderView {
     type State = (Vec<Item>, bool);
 }
 
+/// messages kept in log, older ones are dropped
+const LOG_SIZE: usize = 1000;
+/// width of time column of log
+const TIME_WIDTH: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+struct Message {
+    time: NaiveTime,
+    level: Level,
+    text: String,
+}
+
+/// command outputs and errors, oldest first, shown in side pane or full screen.
 #[derive(Default)]
-pub struct PopUpView {
-    pub msgs: Vec<Result<String, String>>,
+pub struct LogView {
+    msgs: VecDeque<Message>,
+    errors_only: bool,
+    /// first shown line
+    top: usize,
+    /// scrolled away from newest line
+    scrolled: bool,
+    /// message to show at top on next render
+    jump: Option<usize>,
+    /// last top line and page height of last render
+    max_top: usize,
+    height: usize,
+}
+
+/// lines of text wrapped at `width` chars, breaking at spaces if possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    for line in text.lines() {
+        let mut rest = line.chars().collect::<Vec<_>>();
+        while rest.len() > width {
+            let at = rest[..=width]
+                .iter()
+                .rposition(|c| *c == ' ')
+                .filter(|at| *at > 0)
+                .unwrap_or(width);
+            lines.push(rest[..at].iter().collect());
+            let skip = if rest[at] == ' ' { at + 1 } else { at };
+            rest.drain(..skip);
+        }
+        lines.push(rest.into_iter().collect());
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
 }
 
-impl PopUpView {
+impl LogView {
     pub fn push(&mut self, value: Result<String, String>) {
-        self.msgs.push(value)
+        let (level, text) = match value {
+            Ok(text) if text.trim().is_empty() => return,
+            Ok(text) => (Level::Info, text),
+            Err(text) => (Level::Error, text),
+        };
+        let time = chrono::Local::now().time();
+        // repeated errors of redraws are logged once, outputs of repeated
+        // commands are all kept
+        if let Some(last) = self.msgs.back_mut() {
+            if level == Level::Error && last.level == level && last.text == text {
+                last.time = time;
+                return;
+            }
+        }
+        if self.msgs.len() == LOG_SIZE {
+            self.msgs.pop_front();
+        }
+        self.msgs.push_back(Message { time, level, text });
+        self.scrolled = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.msgs.clear();
+        self.top = 0;
+        self.scrolled = false;
+    }
+
+    /// show newest message from its start.
+    pub fn open(&mut self) {
+        self.jump = self.msgs.len().checked_sub(1);
+    }
+
+    pub fn scroll(&mut self, down: bool) {
+        self.shift(if down { 1 } else { -1 });
+    }
+
+    fn shift(&mut self, lines: isize) {
+        self.top = (self.top as isize + lines).clamp(0, self.max_top as isize) as usize;
+        self.scrolled = self.top < self.max_top;
+    }
+
+    /// log lines wrapped at width, and first line of each shown message.
+    fn lines(&self, width: usize) -> (Vec<Spans<'static>>, Vec<(usize, usize)>) {
+        let mut lines = vec![];
+        let mut starts = vec![];
+        let shown = self
+            .msgs
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| !self.errors_only || msg.level == Level::Error);
+        for (index, msg) in shown {
+            starts.push((index, lines.len()));
+            let style = match msg.level {
+                Level::Info => Style::default().fg(Color::White),
+                Level::Error => Style::default().fg(Color::Red),
+            };
+            let time = msg.time.format("%H:%M:%S ").to_string();
+            for (i, line) in wrap(&msg.text, width.saturating_sub(TIME_WIDTH))
+                .into_iter()
+                .enumerate()
+            {
+                let time = match i {
+                    0 => Span::styled(time.clone(), Style::default().fg(Color::DarkGray)),
+                    _ => Span::raw(" ".repeat(TIME_WIDTH)),
+                };
+                lines.push(Spans::from(vec![time, Span::styled(line, style)]));
+            }
+        }
+        (lines, starts)
+    }
+
+    pub fn handle_events(&mut self, key: KeyCode) -> Return {
+        let page = self.height.max(1) as isize;
+        match key {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('L') => {
+                return Return::State(ActiveElement::FolderView)
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.shift(1),
+            KeyCode::Char('k') | KeyCode::Up => self.shift(-1),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.shift(page),
+            KeyCode::PageUp => self.shift(-page),
+            KeyCode::Char('g') | KeyCode::Home => self.shift(-(self.top as isize)),
+            KeyCode::Char('G') | KeyCode::End => self.shift(self.max_top as isize),
+            KeyCode::Char('e') => {
+                self.errors_only = !self.errors_only;
+                self.scrolled = false;
+            }
+            KeyCode::Char('c') => self.clear(),
+            _ => {}
+        }
+        Return::Pass
     }
 }
 
-impl Widget for &mut PopUpView {
-    fn render(self, area: Rect, buf: &mut Buffer) {
+impl StatefulWidget for &mut LogView {
+    fn render(self, area: Rect, buf: &mut Buffer, full_screen: &mut Self::State) {
+        let title = match (*full_screen, self.errors_only) {
+            (true, errors) => format!(
+                "log{}|j/k: scroll|g/G: top/bottom|e: errors only|c: clear|Esc: back",
+                if errors { " (errors)" } else { "" }
+            ),
+            (false, true) => "log (errors)".to_string(),
+            (false, false) => "log".to_string(),
+        };
         let block = Block::default()
             .borders(Borders::ALL)
             .style(Style::default().fg(Color::White))
-            .title("popup");
-        let list = List::new(
-            self.msgs
-                .iter()
-                .rev()
-                .map(|item| {
-                    ListItem::new(match item {
-                        Err(msg) => Text::styled(msg, Style::default().fg(Color::Red)),
-                        Ok(msg) => Text::styled(msg, Style::default().fg(Color::White)),
-                    })
-                })
-                .collect::<Vec<_>>(),
-        )
-        .block(block);
-        Widget::render(list, area, buf);
+            .title(title);
+        let inner = block.inner(area);
+        let (mut lines, starts) = self.lines(inner.width as usize);
+        self.height = inner.height as usize;
+        self.max_top = lines.len().saturating_sub(self.height);
+        if let Some(index) = self.jump.take() {
+            if let Some((_, start)) = starts.iter().rev().find(|(i, _)| *i <= index) {
+                self.top = *start;
+                self.scrolled = true;
+            }
+        }
+        if !self.scrolled {
+            self.top = self.max_top;
+        }
+        self.top = self.top.min(self.max_top);
+        // lines above top are dropped, scroll offset of paragraph is u16
+        lines.drain(..self.top);
+        Paragraph::new(lines).block(block).render(area, buf);
     }
+
+    type State = bool;
 }
 
-#[derive(Default)]
+/// input bar, keeps history of executed lines in the interpreter history file.
 pub struct Interpreter {
     input: String,
+    /// byte index of cursor in input
+    cursor: usize,
+    history: History,
+    history_file: Option<PathBuf>,
+    /// history entry shown, none while editing a new line
+    entry: Option<usize>,
+    /// line edited before browsing history
+    draft: String,
+    /// candidates of last ambiguous completion
+    candidates: Vec<String>,
+    /// commands to complete, read on first completion
+    commands: Option<CommandSpec>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self {
+            input: String::new(),
+            cursor: 0,
+            history: History::new(),
+            history_file: None,
+            entry: None,
+            draft: String::new(),
+            candidates: vec![],
+            commands: None,
+        }
+    }
 }
 
 pub struct Context {
@@ -242,11 +1153,153 @@ pub struct Context {
     pub note: Option<Item>,
 }
 
+const TEMPLATE_VARIABLES: [&str; 4] = ["{{CHILD}}", "{{PARENT}}", "{{c}}", "{{p}}"];
+
 impl Interpreter {
-    pub fn handle_events(&mut self, key: KeyCode, context: Context) -> Return {
-        match key {
+    /// share history with the command line interpreter.
+    pub fn load_history(&mut self) {
+        if let Ok(dir) = data_dir() {
+            let file = dir.join(HISTORY_FILE);
+            // no history yet on first run
+            let _ = self.history.load(&file);
+            self.history_file = Some(file);
+        }
+    }
+
+    fn set_input(&mut self, input: String) {
+        self.cursor = input.len();
+        self.input = input;
+    }
+
+    /// byte index of char before cursor.
+    fn prev_char(&self) -> Option<usize> {
+        self.input[..self.cursor]
+            .char_indices()
+            .last()
+            .map(|(i, _)| i)
+    }
+
+    /// byte index of char after cursor.
+    fn next_char(&self) -> Option<usize> {
+        self.input[self.cursor..]
+            .chars()
+            .next()
+            .map(|c| self.cursor + c.len_utf8())
+    }
+
+    /// byte index of start of word before cursor.
+    fn prev_word(&self) -> usize {
+        let before = self.input[..self.cursor].trim_end();
+        before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+
+    /// byte index of end of word after cursor.
+    fn next_word(&self) -> usize {
+        let after = &self.input[self.cursor..];
+        let skipped = after.len() - after.trim_start().len();
+        after[skipped..]
+            .find(char::is_whitespace)
+            .map_or(self.input.len(), |i| self.cursor + skipped + i)
+    }
+
+    fn history(&mut self, older: bool) {
+        let entry = match (self.entry, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = self.input.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(entry), true) => Some(entry.saturating_sub(1)),
+            (Some(entry), false) if entry + 1 < self.history.len() => Some(entry + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+        self.entry = entry;
+        let input = match entry {
+            Some(entry) => self.history.get(entry).cloned().unwrap_or_default(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.set_input(input);
+    }
+
+    /// complete word before cursor, ambiguous completions are extended to
+    /// their common prefix and listed.
+    fn complete(&mut self, context: &Context, conn: &DbConnection) {
+        let line = &self.input[..self.cursor];
+        let start = word_start(line);
+        let word = &line[start..];
+        let replacements = match word.starts_with('{') {
+            true => TEMPLATE_VARIABLES
+                .iter()
+                .filter(|variable| variable.starts_with(word))
+                .map(|variable| (variable.to_string(), format!("{} ", variable)))
+                .collect::<Vec<_>>(),
+            false => {
+                let cwd = Path::new("/").join(&context.path);
+                let commands = self
+                    .commands
+                    .get_or_insert_with(|| CommandSpec::new(&TuiCommand::clap()));
+                let (_, pairs) = complete(commands, &cwd, line, conn);
+                pairs
+                    .into_iter()
+                    .map(|pair| (pair.display, pair.replacement))
+                    .collect()
+            }
+        };
+        let replacement = match replacements.as_slice() {
+            [] => return self.candidates.clear(),
+            [(_, replacement)] => replacement.clone(),
+            [(_, first), rest @ ..] => {
+                let mut prefix = first.as_str();
+                for (_, other) in rest {
+                    let common = prefix
+                        .char_indices()
+                        .zip(other.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map_or(prefix.len().min(other.len()), |((i, _), _)| i);
+                    prefix = &prefix[..common];
+                }
+                prefix.to_string()
+            }
+        };
+        self.candidates = match replacements.len() {
+            1 => vec![],
+            _ => replacements
+                .into_iter()
+                .map(|(display, _)| display)
+                .collect(),
+        };
+        if replacement.len() >= word.len() {
+            self.input.replace_range(start..self.cursor, &replacement);
+            self.cursor = start + replacement.len();
+        }
+    }
+
+    pub fn handle_events(
+        &mut self,
+        key: KeyEvent,
+        context: Context,
+        conn: &DbConnection,
+    ) -> Return {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if key.code != KeyCode::Tab {
+            self.candidates.clear();
+        }
+        match key.code {
             KeyCode::Enter => {
-                let template = Template::new(&self.input.drain(..).collect::<String>());
+                let line = std::mem::take(&mut self.input);
+                self.cursor = 0;
+                self.entry = None;
+                if self.history.add(line.as_str()) {
+                    if let Some(file) = &self.history_file {
+                        // history is kept on a best effort basis
+                        let _ = self.history.append(file);
+                    }
+                }
+                let template = Template::new(&line);
                 let mut subs = HashMap::new();
 
                 let parent = context.path.to_str().unwrap_or("");
@@ -275,10 +1328,40 @@ impl Interpreter {
                     Err(e) => return Return::Error(e.to_string()),
                 }
             }
-            KeyCode::Esc | KeyCode::Tab => return Return::State(ActiveElement::FolderView),
-            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Esc => return Return::State(ActiveElement::FolderView),
+            KeyCode::Tab => self.complete(&context, conn),
+            KeyCode::Up => self.history(true),
+            KeyCode::Down => self.history(false),
+            KeyCode::Left if ctrl => self.cursor = self.prev_word(),
+            KeyCode::Right if ctrl => self.cursor = self.next_word(),
+            KeyCode::Left => self.cursor = self.prev_char().unwrap_or(0),
+            KeyCode::Right => self.cursor = self.next_char().unwrap_or(self.cursor),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.input.len(),
+            KeyCode::Char('w') if ctrl => {
+                let start = self.prev_word();
+                self.input.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            KeyCode::Char('u') if ctrl => {
+                self.input.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            KeyCode::Char('k') if ctrl => self.input.truncate(self.cursor),
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
             KeyCode::Backspace => {
-                self.input.pop();
+                if let Some(prev) = self.prev_char() {
+                    self.input.remove(prev);
+                    self.cursor = prev;
+                }
+            }
+            KeyCode::Delete if self.next_char().is_some() => {
+                self.input.remove(self.cursor);
             }
             _ => {}
         }
@@ -288,30 +1371,41 @@ impl Interpreter {
 
 impl StatefulWidget for &mut Interpreter {
     fn render(self, area: Rect, buf: &mut Buffer, input_mode: &mut Self::State) {
-        let input = Paragraph::new(Spans::from({
-            let input: &str = self.input.as_ref();
-            let mut spans = vec![Span::raw(input)];
-            if *input_mode {
-                spans.push(Span::styled(
-                    "_".to_owned(),
-                    Style::default()
-                        .add_modifier(Modifier::UNDERLINED)
-                        .fg(Color::Yellow),
-                ))
-            }
-            spans
-        }))
-        .style({
-            let mut style = Style::default();
-            if *input_mode {
-                style = style.fg(Color::Yellow)
-            }
-            style
-        })
-        .block(Block::default().borders(Borders::ALL).title(format!(
-            "{}|Esc: to stop editing|Enter: to execute|{{{{c/CHILD}}}}: child|{{{{p/PARENT}}}}: parent",
-            structopt::clap::crate_name!()
-        )));
+        let (before, after) = self.input.split_at(self.cursor);
+        let mut spans = vec![Span::raw(before)];
+        if *input_mode {
+            let mut after = after.chars();
+            spans.push(Span::styled(
+                after.next().map_or("_".to_string(), String::from),
+                Style::default()
+                    .add_modifier(Modifier::UNDERLINED)
+                    .fg(Color::Yellow),
+            ));
+            spans.push(Span::raw(after.as_str()));
+        } else {
+            spans.push(Span::raw(after));
+        }
+        // keep cursor in sight
+        let width = area.width.saturating_sub(3) as usize;
+        let scroll = before.chars().count().saturating_sub(width) as u16;
+        let input = Paragraph::new(Spans::from(spans))
+            .style({
+                let mut style = Style::default();
+                if *input_mode {
+                    style = style.fg(Color::Yellow)
+                }
+                style
+            })
+            .scroll((0, scroll))
+            .block(Block::default().borders(Borders::ALL).title(
+                match self.candidates.is_empty() {
+                    true => format!(
+                        "{}|Esc: to stop editing|Enter: to execute|{{{{c/CHILD}}}}: child|{{{{p/PARENT}}}}: parent|Tab: complete",
+                        structopt::clap::crate_name!()
+                    ),
+                    false => self.candidates.join(" "),
+                },
+            ));
         input.render(area, buf);
     }
 